@@ -0,0 +1,110 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Standalone FlightVars server.
+//!
+//! This executable runs FlightVars outside the simulator. It loads the same settings
+//! the plugin does, but the config file, the log target and the enabled domains can be
+//! chosen from the command line. With no config file, log messages are written to the
+//! console, as they are whenever the log file cannot be opened.
+
+extern crate flightvars;
+extern crate getopts;
+#[macro_use] extern crate log;
+
+use std::env;
+use std::mem;
+use std::process;
+
+use getopts::Options;
+
+use flightvars::config::{LoggingSettings, Settings};
+use flightvars::fv::FlightVars;
+use flightvars::logging;
+
+const DEFAULT_CONFIG_FILE: &'static str = "flightvars.conf";
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.optopt("c", "config", 
+        &format!("load the settings from FILE (default: {})", DEFAULT_CONFIG_FILE), "FILE");
+    opts.optopt("l", "log-file", "write the log messages to FILE", "FILE");
+    opts.optflag("", "log-console", "write the log messages to the console");
+    opts.optopt("d", "domains", "comma-separated list of domains to enable", "LIST");
+    opts.optflag("h", "help", "print this help and exit");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            println!("{}", opts.short_usage(&program));
+            process::exit(1);
+        }
+    };
+    if matches.opt_present("h") {
+        let brief = format!("Usage: {} [options]", program);
+        print!("{}", opts.usage(&brief));
+        return;
+    }
+
+    let config_file = matches.opt_str("c").unwrap_or(DEFAULT_CONFIG_FILE.to_string());
+    let mut settings = match Settings::from_toml_file(&config_file) {
+        Ok(settings) => settings,
+        Err(e) if matches.opt_present("c") => {
+            println!("FlightVars cannot load config file at {}: {}", config_file, e);
+            process::exit(1);
+        }
+        Err(_) => {
+            println!("FlightVars cannot load config file at {}", config_file);
+            println!("Falling back to default settings");
+            let mut settings = Settings::default();
+            settings.logging.console = true;
+            settings
+        }
+    };
+    if let Some(file) = matches.opt_str("l") {
+        settings.logging.file = file;
+        settings.logging.console = false;
+    }
+    if matches.opt_present("log-console") {
+        settings.logging.console = true;
+    }
+    if let Some(domains) = matches.opt_str("d") {
+        settings.domains.enabled = domains
+            .split(',')
+            .map(|d| d.trim())
+            .filter(|d| !d.is_empty())
+            .map(|d| d.to_string())
+            .collect();
+    }
+
+    let log_file = settings.logging.file.clone();
+    let log_level = settings.logging.level;
+    if let Err(e) = logging::config_logging(mem::replace(&mut settings.logging, Default::default())) {
+        println!("FlightVars cannot write log messages to {}: {}", log_file, e);
+        println!("Falling back to console logging");
+        let console = LoggingSettings { level: log_level, console: true, ..Default::default() };
+        if let Err(e) = logging::config_logging(console) {
+            println!("FlightVars cannot configure logging: {}", e);
+        }
+    }
+    info!("Starting FlightVars server v{}", env!("CARGO_PKG_VERSION"));
+    let flightvars = match FlightVars::new(&settings) {
+        Ok(fv) => fv,
+        Err(e) => {
+            error!("cannot start FlightVars: {:?}", e);
+            println!("FlightVars cannot start: {}", e);
+            process::exit(1);
+        }
+    };
+    info!("FlightVars server started successfully");
+    flightvars.wait();
+}
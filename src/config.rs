@@ -12,6 +12,7 @@ use std::io;
 use std::io::Read;
 use std::path::Path;
 use std::result;
+use std::time::Duration;
 
 use log::LogLevelFilter;
 use log4rs::pattern::PatternLayout;
@@ -21,6 +22,12 @@ use toml;
 const DEFAULT_LOGGING_LEVEL: LogLevelFilter = LogLevelFilter::Info;
 const DEFAULT_LOGGING_PATTERN: &'static str = "%d{%Y/%m/%d %H:%M:%S.%f} - [%l] [%M]: %m";
 const DEFAULT_LOGGING_FILE: &'static str = "Modules/flightvars.log";
+const DEFAULT_UDP_SESSION_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_SERIAL_HANDSHAKE_TIMEOUT_SECS: u64 = 5;
+const DEFAULT_MQTT_BRIDGE_CLIENT_ID: &'static str = "flightvars";
+const DEFAULT_MQTT_BRIDGE_PREFIX: &'static str = "flightvars";
+const DEFAULT_MQTT_BRIDGE_KEEP_ALIVE_SECS: u16 = 30;
+const DEFAULT_DOMAINS: &'static [&'static str] = &["fsuipc", "lvar"];
 
 pub enum Error {
 	CannotParse,
@@ -33,6 +40,7 @@ pub struct LoggingSettings {
     pub level: LogLevelFilter,
     pub pattern: PatternLayout,
     pub file: String,
+    pub console: bool,
 }
 
 impl Decodable for LoggingSettings {
@@ -50,6 +58,9 @@ impl Decodable for LoggingSettings {
         if let Ok(file) = d.read_struct_field("file", 0, |d| d.read_str()) {
             result.file = file;
         }
+        if let Ok(console) = d.read_struct_field("console", 0, |d| d.read_bool()) {
+            result.console = console;
+        }
         Ok(result)
     }
 }
@@ -60,32 +71,33 @@ impl Default for LoggingSettings {
             level: DEFAULT_LOGGING_LEVEL,
             pattern: PatternLayout::new(DEFAULT_LOGGING_PATTERN).unwrap(),
             file: DEFAULT_LOGGING_FILE.to_string(),
+            console: false,
         }
     }
 }
 
 pub struct OacspSerialSettings {
     pub ports: Vec<OsString>,
+    /// Whether the serial ports of the system are probed for OACSP clients.
+    pub discover: bool,
+    /// The IDs of the clients accepted on discovered ports, or any client if empty.
+    pub clients: Vec<String>,
+    pub handshake_timeout: Duration,
 }
 
 impl Decodable for OacspSerialSettings {
     fn decode<D: Decoder>(d: &mut D) -> result::Result<Self, D::Error> {                        
-        let ports = try!(
-        	d.read_struct_field("ports", 0, |d| 
-	            d.read_option(|d, is_defined|
-            		if is_defined {
-                		d.read_seq(|d, len| {
-                        	let mut ports = Vec::with_capacity(len);
-                        	for i in 0..len {
-                        	    ports.push(OsString::from(try!(d.read_seq_elt(i, |d| d.read_str()))));
-                        	}
-                        	Ok(ports)
-                        })
-            		} else { Ok(Vec::new()) }            		
-        )));
-        Ok(OacspSerialSettings {
-            ports: ports,
-        })
+        let mut result = OacspSerialSettings::default();
+        let ports = try!(read_str_list(d, "ports"));
+        result.ports = ports.into_iter().map(OsString::from).collect();
+        if let Ok(discover) = d.read_struct_field("discover", 0, |d| d.read_bool()) {
+            result.discover = discover;
+        }
+        result.clients = try!(read_str_list(d, "clients"));
+        if let Ok(secs) = d.read_struct_field("handshake-timeout", 0, |d| d.read_u64()) {
+            result.handshake_timeout = Duration::from_secs(secs);
+        }
+        Ok(result)
     }
 }
 
@@ -93,13 +105,245 @@ impl Default for OacspSerialSettings {
     fn default() -> OacspSerialSettings {
         OacspSerialSettings {
             ports: Vec::new(),
+            discover: false,
+            clients: Vec::new(),
+            handshake_timeout: Duration::from_secs(DEFAULT_SERIAL_HANDSHAKE_TIMEOUT_SECS),
+        }
+    }
+}
+
+pub struct BinarySerialSettings {
+    pub ports: Vec<OsString>,
+}
+
+impl Decodable for BinarySerialSettings {
+    fn decode<D: Decoder>(d: &mut D) -> result::Result<Self, D::Error> {
+        let ports = try!(read_str_list(d, "ports"));
+        Ok(BinarySerialSettings {
+            ports: ports.into_iter().map(OsString::from).collect(),
+        })
+    }
+}
+
+impl Default for BinarySerialSettings {
+    fn default() -> BinarySerialSettings {
+        BinarySerialSettings {
+            ports: Vec::new(),
+        }
+    }
+}
+
+pub struct OacspTcpSettings {
+    pub listen: Vec<String>,
+}
+
+impl Decodable for OacspTcpSettings {
+    fn decode<D: Decoder>(d: &mut D) -> result::Result<Self, D::Error> {
+        Ok(OacspTcpSettings {
+            listen: try!(read_str_list(d, "listen")),
+        })
+    }
+}
+
+impl Default for OacspTcpSettings {
+    fn default() -> OacspTcpSettings {
+        OacspTcpSettings {
+            listen: Vec::new(),
+        }
+    }
+}
+
+pub struct OacspIpcSettings {
+    pub listen: Vec<String>,
+}
+
+impl Decodable for OacspIpcSettings {
+    fn decode<D: Decoder>(d: &mut D) -> result::Result<Self, D::Error> {
+        Ok(OacspIpcSettings {
+            listen: try!(read_str_list(d, "listen")),
+        })
+    }
+}
+
+impl Default for OacspIpcSettings {
+    fn default() -> OacspIpcSettings {
+        OacspIpcSettings {
+            listen: Vec::new(),
+        }
+    }
+}
+
+pub struct OacspUdpSettings {
+    pub listen: Vec<String>,
+    pub session_timeout: Duration,
+}
+
+impl Decodable for OacspUdpSettings {
+    fn decode<D: Decoder>(d: &mut D) -> result::Result<Self, D::Error> {
+        let mut result = OacspUdpSettings::default();
+        result.listen = try!(read_str_list(d, "listen"));
+        if let Ok(secs) = d.read_struct_field("session-timeout", 0, |d| d.read_u64()) {
+            result.session_timeout = Duration::from_secs(secs);
+        }
+        Ok(result)
+    }
+}
+
+impl Default for OacspUdpSettings {
+    fn default() -> OacspUdpSettings {
+        OacspUdpSettings {
+            listen: Vec::new(),
+            session_timeout: Duration::from_secs(DEFAULT_UDP_SESSION_TIMEOUT_SECS),
+        }
+    }
+}
+
+pub struct MqttSettings {
+    pub listen: Vec<String>,
+}
+
+impl Decodable for MqttSettings {
+    fn decode<D: Decoder>(d: &mut D) -> result::Result<Self, D::Error> {
+        Ok(MqttSettings {
+            listen: try!(read_str_list(d, "listen")),
+        })
+    }
+}
+
+impl Default for MqttSettings {
+    fn default() -> MqttSettings {
+        MqttSettings {
+            listen: Vec::new(),
+        }
+    }
+}
+
+pub struct WebSocketSettings {
+    pub listen: Vec<String>,
+}
+
+impl Decodable for WebSocketSettings {
+    fn decode<D: Decoder>(d: &mut D) -> result::Result<Self, D::Error> {
+        Ok(WebSocketSettings {
+            listen: try!(read_str_list(d, "listen")),
+        })
+    }
+}
+
+impl Default for WebSocketSettings {
+    fn default() -> WebSocketSettings {
+        WebSocketSettings {
+            listen: Vec::new(),
+        }
+    }
+}
+
+pub struct HttpSettings {
+    pub listen: Vec<String>,
+}
+
+impl Decodable for HttpSettings {
+    fn decode<D: Decoder>(d: &mut D) -> result::Result<Self, D::Error> {
+        Ok(HttpSettings {
+            listen: try!(read_str_list(d, "listen")),
+        })
+    }
+}
+
+impl Default for HttpSettings {
+    fn default() -> HttpSettings {
+        HttpSettings {
+            listen: Vec::new(),
+        }
+    }
+}
+
+pub struct JsonRpcSettings {
+    pub listen: Vec<String>,
+}
+
+impl Decodable for JsonRpcSettings {
+    fn decode<D: Decoder>(d: &mut D) -> result::Result<Self, D::Error> {
+        Ok(JsonRpcSettings {
+            listen: try!(read_str_list(d, "listen")),
+        })
+    }
+}
+
+impl Default for JsonRpcSettings {
+    fn default() -> JsonRpcSettings {
+        JsonRpcSettings {
+            listen: Vec::new(),
+        }
+    }
+}
+
+pub struct MqttBridgeSettings {
+    pub broker: String,
+    pub client_id: String,
+    pub prefix: String,
+    pub keep_alive: u16,
+    pub publish: Vec<String>,
+}
+
+impl Decodable for MqttBridgeSettings {
+    fn decode<D: Decoder>(d: &mut D) -> result::Result<Self, D::Error> {
+        let broker = try!(d.read_struct_field("broker", 0, |d| d.read_str()));
+        let mut result = MqttBridgeSettings {
+            broker: broker,
+            client_id: DEFAULT_MQTT_BRIDGE_CLIENT_ID.to_string(),
+            prefix: DEFAULT_MQTT_BRIDGE_PREFIX.to_string(),
+            keep_alive: DEFAULT_MQTT_BRIDGE_KEEP_ALIVE_SECS,
+            publish: try!(read_str_list(d, "publish")),
+        };
+        if let Ok(client_id) = d.read_struct_field("client-id", 0, |d| d.read_str()) {
+            result.client_id = client_id;
+        }
+        if let Ok(prefix) = d.read_struct_field("prefix", 0, |d| d.read_str()) {
+            result.prefix = prefix;
+        }
+        if let Ok(keep_alive) = d.read_struct_field("keep-alive", 0, |d| d.read_u16()) {
+            result.keep_alive = keep_alive;
+        }
+        Ok(result)
+    }
+}
+
+pub struct DomainSettings {
+    pub enabled: Vec<String>,
+}
+
+impl Decodable for DomainSettings {
+    fn decode<D: Decoder>(d: &mut D) -> result::Result<Self, D::Error> {
+        let mut result = DomainSettings::default();
+        if let Some(enabled) = try!(read_opt_str_list(d, "enabled")) {
+            result.enabled = enabled;
+        }
+        Ok(result)
+    }
+}
+
+impl Default for DomainSettings {
+    fn default() -> DomainSettings {
+        DomainSettings {
+            enabled: DEFAULT_DOMAINS.iter().map(|d| d.to_string()).collect(),
         }
     }
 }
 
 pub struct Settings {
     pub logging: LoggingSettings,
+    pub domains: DomainSettings,
     pub oacsp_serial: OacspSerialSettings,
+    pub oacsp_tcp: OacspTcpSettings,
+    pub oacsp_udp: OacspUdpSettings,
+    pub oacsp_ipc: OacspIpcSettings,
+    pub mqtt: MqttSettings,
+    pub mqtt_bridge: Option<MqttBridgeSettings>,
+    pub websocket: WebSocketSettings,
+    pub http: HttpSettings,
+    pub jsonrpc: JsonRpcSettings,
+    pub binary_serial: BinarySerialSettings,
 }
 
 impl Settings {
@@ -120,13 +364,63 @@ impl Settings {
 			Some(section) => try!(toml::decode(section).ok_or(Error::CannotDecode)),
 			None => LoggingSettings::default(),            
         };
+        let domains = match table.remove("domains") {
+            Some(section) => try!(toml::decode(section).ok_or(Error::CannotDecode)),
+            None => DomainSettings::default(),
+        };
         let oacsp_serial = match table.remove("oacsp-serial") {
             Some(section) => try!(toml::decode(section).ok_or(Error::CannotDecode)),
             None => OacspSerialSettings::default(),
         };
+        let oacsp_tcp = match table.remove("oacsp-tcp") {
+            Some(section) => try!(toml::decode(section).ok_or(Error::CannotDecode)),
+            None => OacspTcpSettings::default(),
+        };
+        let oacsp_udp = match table.remove("oacsp-udp") {
+            Some(section) => try!(toml::decode(section).ok_or(Error::CannotDecode)),
+            None => OacspUdpSettings::default(),
+        };
+        let oacsp_ipc = match table.remove("oacsp-ipc") {
+            Some(section) => try!(toml::decode(section).ok_or(Error::CannotDecode)),
+            None => OacspIpcSettings::default(),
+        };
+        let mqtt = match table.remove("mqtt") {
+            Some(section) => try!(toml::decode(section).ok_or(Error::CannotDecode)),
+            None => MqttSettings::default(),
+        };
+        let mqtt_bridge = match table.remove("mqtt-bridge") {
+            Some(section) => Some(try!(toml::decode(section).ok_or(Error::CannotDecode))),
+            None => None,
+        };
+        let websocket = match table.remove("websocket") {
+            Some(section) => try!(toml::decode(section).ok_or(Error::CannotDecode)),
+            None => WebSocketSettings::default(),
+        };
+        let http = match table.remove("http") {
+            Some(section) => try!(toml::decode(section).ok_or(Error::CannotDecode)),
+            None => HttpSettings::default(),
+        };
+        let jsonrpc = match table.remove("jsonrpc") {
+            Some(section) => try!(toml::decode(section).ok_or(Error::CannotDecode)),
+            None => JsonRpcSettings::default(),
+        };
+        let binary_serial = match table.remove("binary-serial") {
+            Some(section) => try!(toml::decode(section).ok_or(Error::CannotDecode)),
+            None => BinarySerialSettings::default(),
+        };
         Ok(Settings {
 			logging: logging,
+			domains: domains,
 			oacsp_serial: oacsp_serial,                
+			oacsp_tcp: oacsp_tcp,
+			oacsp_udp: oacsp_udp,
+			oacsp_ipc: oacsp_ipc,
+			mqtt: mqtt,
+			mqtt_bridge: mqtt_bridge,
+			websocket: websocket,
+			http: http,
+			jsonrpc: jsonrpc,
+			binary_serial: binary_serial,
         })
     }
 }
@@ -135,16 +429,49 @@ impl Default for Settings {
     fn default() -> Settings {
         Settings {
             logging: LoggingSettings::default(),
+            domains: DomainSettings::default(),
             oacsp_serial: OacspSerialSettings::default(),
+            oacsp_tcp: OacspTcpSettings::default(),
+            oacsp_udp: OacspUdpSettings::default(),
+            oacsp_ipc: OacspIpcSettings::default(),
+            mqtt: MqttSettings::default(),
+            mqtt_bridge: None,
+            websocket: WebSocketSettings::default(),
+            http: HttpSettings::default(),
+            jsonrpc: JsonRpcSettings::default(),
+            binary_serial: BinarySerialSettings::default(),
         }
     }
 }
 
+/// Reads a list of strings from the given field, or an empty list if not present.
+fn read_str_list<D: Decoder>(d: &mut D, field: &str) -> result::Result<Vec<String>, D::Error> {
+    read_opt_str_list(d, field).map(|list| list.unwrap_or(Vec::new()))
+}
+
+/// Reads a list of strings from the given field, if present.
+fn read_opt_str_list<D: Decoder>(d: &mut D, field: &str) 
+    -> result::Result<Option<Vec<String>>, D::Error> {
+    d.read_struct_field(field, 0, |d| 
+        d.read_option(|d, is_defined|
+            if is_defined {
+                d.read_seq(|d, len| {
+                    let mut list = Vec::with_capacity(len);
+                    for i in 0..len {
+                        list.push(try!(d.read_seq_elt(i, |d| d.read_str())));
+                    }
+                    Ok(Some(list))
+                })
+            } else { Ok(None) }
+    ))
+}
+
 
 #[cfg(test)]
 mod tests {
 
 	use std::ffi::OsString;
+	use std::time::Duration;
 	
 	use log::LogLevelFilter;
 
@@ -203,6 +530,30 @@ mod tests {
 	    assert_eq!(s.logging.file, "/path/to/log/file");
 	} 
 	
+	#[test]
+	fn should_load_logging_console() {
+	    let s = Settings::from_toml(r#"
+        	[logging]
+        	console = true
+        	"#).ok().unwrap();
+	    assert!(s.logging.console);
+	} 
+	
+	#[test]
+	fn should_load_all_domains_by_default() {
+	    let s = Settings::from_toml("").ok().unwrap();
+	    assert_eq!(&s.domains.enabled, &["fsuipc", "lvar"]);
+	} 
+	
+	#[test]
+	fn should_load_enabled_domains() {
+	    let s = Settings::from_toml(r#"
+        	[domains]
+        	enabled = ["lvar"]
+        	"#).ok().unwrap();
+	    assert_eq!(&s.domains.enabled, &["lvar"]);
+	} 
+	
 	#[test]
 	fn should_load_oacsp_serial_ports() {
 	    let s = Settings::from_toml(r#"
@@ -212,6 +563,27 @@ mod tests {
 	    assert_eq!(&s.oacsp_serial.ports, &[OsString::from("COM1"), OsString::from("COM2")]);
 	} 
 	
+	#[test]
+	fn should_load_oacsp_serial_discovery_settings() {
+	    let s = Settings::from_toml(r#"
+        	[oacsp-serial]
+        	discover = true
+        	clients = ["mcp", "fcu"]
+        	handshake-timeout = 10
+        	"#).ok().unwrap();
+	    assert!(s.oacsp_serial.ports.is_empty());
+	    assert!(s.oacsp_serial.discover);
+	    assert_eq!(&s.oacsp_serial.clients, &["mcp", "fcu"]);
+	    assert_eq!(s.oacsp_serial.handshake_timeout, Duration::from_secs(10));
+	} 
+	
+	#[test]
+	fn should_not_discover_oacsp_serial_ports_by_default() {
+	    let s = Settings::from_toml("").ok().unwrap();
+	    assert!(!s.oacsp_serial.discover);
+	    assert_eq!(s.oacsp_serial.handshake_timeout, Duration::from_secs(5));
+	} 
+	
 	#[test]
 	fn should_fail_load_oacsp_serial_invalid_ports() {
 	    assert!(Settings::from_toml(r#"
@@ -219,4 +591,123 @@ mod tests {
         	ports = "This is not a valid port description"
         	"#).is_err());
 	} 
-}
\ No newline at end of file
+	
+	#[test]
+	fn should_load_no_oacsp_tcp_listeners_by_default() {
+	    let s = Settings::from_toml("").ok().unwrap();
+	    assert!(s.oacsp_tcp.listen.is_empty());
+	} 
+	
+	#[test]
+	fn should_load_oacsp_tcp_listeners() {
+	    let s = Settings::from_toml(r#"
+        	[oacsp-tcp]
+        	listen = ["0.0.0.0:1801", "127.0.0.1:1802"]
+        	"#).ok().unwrap();
+	    assert_eq!(&s.oacsp_tcp.listen, &["0.0.0.0:1801", "127.0.0.1:1802"]);
+	} 
+	
+	#[test]
+	fn should_load_oacsp_udp_settings() {
+	    let s = Settings::from_toml(r#"
+        	[oacsp-udp]
+        	listen = ["0.0.0.0:1801"]
+        	session-timeout = 10
+        	"#).ok().unwrap();
+	    assert_eq!(&s.oacsp_udp.listen, &["0.0.0.0:1801"]);
+	    assert_eq!(s.oacsp_udp.session_timeout, Duration::from_secs(10));
+	} 
+	
+	#[test]
+	fn should_load_oacsp_udp_default_session_timeout() {
+	    let s = Settings::from_toml(r#"
+        	[oacsp-udp]
+        	listen = ["0.0.0.0:1801"]
+        	"#).ok().unwrap();
+	    assert_eq!(s.oacsp_udp.session_timeout, Duration::from_secs(60));
+	} 
+	
+	#[test]
+	fn should_load_oacsp_ipc_listeners() {
+	    let s = Settings::from_toml(r#"
+        	[oacsp-ipc]
+        	listen = ["/var/run/flightvars.sock"]
+        	"#).ok().unwrap();
+	    assert_eq!(&s.oacsp_ipc.listen, &["/var/run/flightvars.sock"]);
+	} 
+	
+	#[test]
+	fn should_load_mqtt_listeners() {
+	    let s = Settings::from_toml(r#"
+        	[mqtt]
+        	listen = ["0.0.0.0:1883"]
+        	"#).ok().unwrap();
+	    assert_eq!(&s.mqtt.listen, &["0.0.0.0:1883"]);
+	} 
+	
+	#[test]
+	fn should_load_no_mqtt_bridge_by_default() {
+	    let s = Settings::from_toml("").ok().unwrap();
+	    assert!(s.mqtt_bridge.is_none());
+	} 
+	
+	#[test]
+	fn should_load_mqtt_bridge() {
+	    let s = Settings::from_toml(r#"
+        	[mqtt-bridge]
+        	broker = "localhost:1883"
+        	prefix = "sim"
+        	publish = ["lvar/A320_Gear_Lever"]
+        	"#).ok().unwrap();
+	    let bridge = s.mqtt_bridge.unwrap();
+	    assert_eq!(bridge.broker, "localhost:1883");
+	    assert_eq!(bridge.client_id, "flightvars");
+	    assert_eq!(bridge.prefix, "sim");
+	    assert_eq!(bridge.keep_alive, 30);
+	    assert_eq!(&bridge.publish, &["lvar/A320_Gear_Lever"]);
+	} 
+	
+	#[test]
+	fn should_fail_load_mqtt_bridge_without_broker() {
+	    assert!(Settings::from_toml(r#"
+        	[mqtt-bridge]
+        	prefix = "sim"
+        	"#).is_err());
+	} 
+	
+	#[test]
+	fn should_load_websocket_listeners() {
+	    let s = Settings::from_toml(r#"
+        	[websocket]
+        	listen = ["0.0.0.0:8080"]
+        	"#).ok().unwrap();
+	    assert_eq!(&s.websocket.listen, &["0.0.0.0:8080"]);
+	} 
+	
+	#[test]
+	fn should_load_http_listeners() {
+	    let s = Settings::from_toml(r#"
+        	[http]
+        	listen = ["127.0.0.1:8000"]
+        	"#).ok().unwrap();
+	    assert_eq!(&s.http.listen, &["127.0.0.1:8000"]);
+	} 
+	
+	#[test]
+	fn should_load_jsonrpc_listeners() {
+	    let s = Settings::from_toml(r#"
+        	[jsonrpc]
+        	listen = ["0.0.0.0:1802"]
+        	"#).ok().unwrap();
+	    assert_eq!(&s.jsonrpc.listen, &["0.0.0.0:1802"]);
+	} 
+	
+	#[test]
+	fn should_load_binary_serial_ports() {
+	    let s = Settings::from_toml(r#"
+        	[binary-serial]
+        	ports = ["COM3"]
+        	"#).ok().unwrap();
+	    assert_eq!(&s.binary_serial.ports, &[OsString::from("COM3")]);
+	} 
+}
@@ -0,0 +1,59 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io;
+
+/// A space of FSUIPC offsets the domain reads from and writes to.
+///
+/// As in FSUIPC, the operations are not performed one by one. They are appended to a
+/// `Session` and then processed all at once by the backend.
+pub trait Backend {
+    fn process(&mut self, session: &mut Session) -> io::Result<()>;
+}
+
+/// An operation on the offset space.
+#[derive(Debug)]
+pub enum Operation {
+    /// Read as many bytes as the buffer length from the given offset.
+    Read(u16, Vec<u8>),
+    /// Write the given bytes at the given offset.
+    Write(u16, Vec<u8>),
+}
+
+/// A batch of operations to be processed by a backend.
+pub struct Session {
+    ops: Vec<Operation>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session { ops: Vec::new() }
+    }
+
+    /// Appends a read operation, returning the index to obtain its result.
+    pub fn read(&mut self, offset: u16, len: usize) -> usize {
+        self.ops.push(Operation::Read(offset, vec![0; len]));
+        self.ops.len() - 1
+    }
+
+    pub fn write(&mut self, offset: u16, data: &[u8]) {
+        self.ops.push(Operation::Write(offset, data.to_vec()));
+    }
+
+    /// Obtains the bytes of the read operation at the given index.
+    pub fn read_result(&self, index: usize) -> &[u8] {
+        match self.ops[index] {
+            Operation::Read(_, ref data) => data,
+            Operation::Write(_, _) => panic!("operation {} is not a read", index),
+        }
+    }
+
+    pub fn operations_mut(&mut self) -> &mut [Operation] {
+        &mut self.ops
+    }
+}
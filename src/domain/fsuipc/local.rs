@@ -0,0 +1,43 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io;
+
+use fsuipc::*;
+use fsuipc::local::LocalHandle;
+
+use super::backend::*;
+
+/// A backend that accesses the FSUIPC module loaded in the simulator process.
+pub struct LocalBackend {
+    handle: LocalHandle,
+}
+
+impl LocalBackend {
+    pub fn new() -> io::Result<LocalBackend> {
+        Ok(LocalBackend { handle: try!(LocalHandle::new()) })
+    }
+}
+
+impl Backend for LocalBackend {
+    fn process(&mut self, session: &mut Session) -> io::Result<()> {
+        let mut local = self.handle.session();
+        for op in session.operations_mut() {
+            match *op {
+                Operation::Read(offset, ref mut data) => {
+                    try!(local.read_bytes(offset, data.as_mut_ptr(), data.len()));
+                }
+                Operation::Write(offset, ref data) => {
+                    try!(local.write_bytes(offset, data.as_ptr(), data.len()));
+                }
+            }
+        }
+        try!(local.process());
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+use super::backend::*;
+
+/// The size of the FSUIPC offset space.
+pub const MEMORY_SIZE: usize = 64 * 1024;
+
+/// A backend that emulates the FSUIPC offset space in memory.
+///
+/// It is useful to run the fsuipc domain outside the simulator. The clones of a memory
+/// backend share the same offset space, so the memory can be inspected and altered
+/// after the backend is handed to the domain.
+#[derive(Clone)]
+pub struct MemoryBackend {
+    state: Rc<RefCell<MemoryState>>,
+}
+
+struct MemoryState {
+    memory: Vec<u8>,
+    timeouts: usize,
+}
+
+#[allow(dead_code)]
+impl MemoryBackend {
+    pub fn new() -> MemoryBackend {
+        let state = MemoryState {
+            memory: vec![0; MEMORY_SIZE],
+            timeouts: 0,
+        };
+        MemoryBackend { state: Rc::new(RefCell::new(state)) }
+    }
+
+    /// Obtains `len` bytes from the given offset.
+    pub fn peek(&self, offset: u16, len: usize) -> Vec<u8> {
+        let state = self.state.borrow();
+        let from = offset as usize;
+        state.memory[from..from + len].to_vec()
+    }
+
+    /// Stores the given bytes at the given offset.
+    pub fn poke(&self, offset: u16, data: &[u8]) {
+        let mut state = self.state.borrow_mut();
+        let from = offset as usize;
+        state.memory[from..from + data.len()].copy_from_slice(data);
+    }
+
+    /// Makes the next `count` sessions to fail with a `TimedOut` error.
+    ///
+    /// This emulates a FSUIPC module that does not respond to requests, as it happens
+    /// while the simulator is loading a flight.
+    pub fn inject_timeouts(&self, count: usize) {
+        self.state.borrow_mut().timeouts = count;
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn process(&mut self, session: &mut Session) -> io::Result<()> {
+        let mut state = self.state.borrow_mut();
+        if state.timeouts > 0 {
+            state.timeouts -= 1;
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut, "FSUIPC memory backend timed out"));
+        }
+        for op in session.operations_mut() {
+            match *op {
+                Operation::Read(offset, ref mut data) => {
+                    let len = data.len();
+                    let from = try!(check_bounds(offset, len));
+                    data.copy_from_slice(&state.memory[from..from + len]);
+                }
+                Operation::Write(offset, ref data) => {
+                    let from = try!(check_bounds(offset, data.len()));
+                    state.memory[from..from + data.len()].copy_from_slice(data);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_bounds(offset: u16, len: usize) -> io::Result<usize> {
+    let from = offset as usize;
+    if from + len > MEMORY_SIZE {
+        let error = io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("offset 0x{:x} with length {} is out of bounds", offset, len));
+        return Err(error);
+    }
+    Ok(from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use domain::fsuipc::backend::*;
+
+    use super::*;
+
+    #[test]
+    fn should_write_and_read_in_session() {
+        let mut mem = MemoryBackend::new();
+        let mut session = Session::new();
+        session.write(0x1234, &[1, 2, 3]);
+        let read = session.read(0x1235, 2);
+        mem.process(&mut session).unwrap();
+        assert_eq!(session.read_result(read), &[2, 3]);
+        assert_eq!(mem.peek(0x1234, 3), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn should_fail_out_of_bounds() {
+        let mut mem = MemoryBackend::new();
+        let mut session = Session::new();
+        session.read(0xffff, 2);
+        let error = mem.process(&mut session).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn should_fail_with_injected_timeouts() {
+        let mut mem = MemoryBackend::new();
+        mem.inject_timeouts(1);
+        let mut session = Session::new();
+        session.write(0x1234, &[1]);
+        let error = mem.process(&mut session).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(mem.peek(0x1234, 1), vec![0]);
+        mem.process(&mut session).unwrap();
+        assert_eq!(mem.peek(0x1234, 1), vec![1]);
+    }
+}
@@ -0,0 +1,551 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+mod backend;
+#[cfg(windows)]
+mod local;
+mod memory;
+
+use std::collections::VecDeque;
+use std::io;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use domain::*;
+
+pub use self::backend::*;
+#[cfg(windows)]
+pub use self::local::*;
+pub use self::memory::*;
+
+pub struct Fsuipc<B: Backend> {
+    backend: B,
+    subscriptions: Vec<Subscription>,
+    writes: VecDeque<WriteOp>,
+}
+
+impl<B: Backend> Fsuipc<B> {
+    pub fn new(backend: B) -> Fsuipc<B> {
+        Fsuipc {
+            backend: backend,
+            subscriptions: Vec::new(),
+            writes: VecDeque::with_capacity(1024),
+        }
+    }
+    
+    fn poll_writes(&mut self) -> io::Result<()> {
+        loop {
+            match self.writes.pop_front() {
+                Some(op) => {
+                    match self.poll_write(&op) {
+                        Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
+                            warn!("FSUIPC is not responding to write operations at this moment");
+                            debug!("write operation {:?} is queued again to be processed later", 
+                                op);
+                            self.writes.push_front(op);
+                            return Ok(());
+                        }
+                        Err(other) => return Err(other),
+                        _ => {} 
+                    }                    
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+    
+    fn poll_write(&mut self, write: &WriteOp) -> io::Result<()> {
+        debug!("processing a write request {:?}", write);
+        let mut session = Session::new();
+        session.write(write.addr, &write.data);
+        self.backend.process(&mut session)
+    }
+    
+    fn poll_subscriptions(&mut self, events: &mut Vec<Event>) -> io::Result<()> {
+        let mut session = Session::new();
+        for sub in self.subscriptions.iter_mut() {
+            sub.append_read(&mut session);
+        }
+        try!(self.backend.process(&mut session));
+        for sub in self.subscriptions.iter_mut() {
+            sub.trigger_event(&session, events);
+        }
+        Ok(())
+    }
+}
+
+impl<B: Backend> Domain for Fsuipc<B> {
+    fn read(&mut self, variable: &Var) -> io::Result<Value> {
+        match variable {
+            &Var::Offset(ref offset) => {
+                try!(check_offset(offset));
+                let mut session = Session::new();
+                let index = session.read(offset.0, usize::from(offset.1));
+                try!(self.backend.process(&mut session));
+                decode_value(offset, session.read_result(index))
+            }
+            _ => {
+                let error = io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("fsuipc domain cannot read variable {:?}", variable));
+                Err(error)
+            }
+        }
+    }
+
+    fn write(&mut self, variable: &Var, value: &Value) -> io::Result<()> {
+        debug!("queueing write operation for {:?} <- {}", variable, value);
+        match variable {
+            &Var::Offset(ref offset) => {
+                let data = try!(encode_value(offset, value));
+            	self.writes.push_back(WriteOp { addr: offset.0, data: data });
+            }
+            _ => {
+                let error = io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("fsuipc domain cannot write variable {:?}", variable));
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+    
+    fn subscribe(&mut self, device: DeviceId, variable: &Var) -> io::Result<()> {
+        info!("receiving a subscription from device {} for {:?}", device, variable);
+        match variable {
+            &Var::Offset(ref offset) => {
+                try!(check_offset(offset));
+                let subscription = Subscription {
+                    device: device,
+                    offset: offset.clone(),
+                    retain: None,
+                    read_index: 0,
+                };
+                self.subscriptions.push(subscription);
+                Ok(())			
+            },
+            _ => {
+                let error = io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("fsuipc domain cannot process subscription to variable {:?}", variable));
+                Err(error)
+            }
+        }
+    }
+    
+    fn unsubscribe(&mut self, device: DeviceId, variable: &Var) -> io::Result<()> {
+        debug!("removing subscription for device ID {} to {:?}", device, variable);
+        self.subscriptions.retain(|s| s.device != device || Var::Offset(s.offset) != *variable);
+        Ok(())
+    }
+    
+    fn unsubscribe_all(&mut self, device: DeviceId) -> io::Result<()> {
+        debug!("removing all subscriptions for device ID {}", device);
+        self.subscriptions.retain(|s| s.device != device);
+        Ok(())
+    }
+    
+    fn poll(&mut self, events: &mut Vec<Event>) -> io::Result<()> {
+        try!(self.poll_writes());
+        try!(self.poll_subscriptions(events));
+        Ok(())
+    }    
+}
+
+struct Subscription {
+    device: DeviceId,
+    offset: Offset,
+    retain: Option<Vec<u8>>,
+    read_index: usize,
+}
+
+impl Subscription {
+    fn append_read(&mut self, session: &mut Session) {
+        let nbytes = usize::from(self.offset.1);
+        self.read_index = session.read(self.offset.0, nbytes);
+    }
+
+    pub fn trigger_event(&mut self, session: &Session, events: &mut Vec<Event>) {
+        let data = session.read_result(self.read_index);
+        let significant = significant_data(&self.offset, data);
+        let must_trigger = self.retain.as_ref().map(|v| &v[..] != significant).unwrap_or(true);
+        if must_trigger {
+            let decoded_value = decode_value(&self.offset, data);
+            match decoded_value {
+                Ok(value) => {
+                    self.retain = Some(significant.to_vec());
+                    let event = Event::new(self.device, "fsuipc", Var::Offset(self.offset), value);
+                    events.push(event);
+                }
+                Err(e) => {
+                    error!("unexpected error while decoding event value: {:?}", e);
+                }
+            }
+        }
+    }
+}
+
+/// Checks that the size of the given offset is admitted by its type and fits in the
+/// offset space, as offsets may be built without the validation of `Offset::typed()`.
+fn check_offset(offset: &Offset) -> io::Result<()> {
+    if offset.is_valid() {
+        Ok(())
+    } else {
+        let error = io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid size {} for offset {:04x} of type {}", offset.1, offset.0, offset.2));
+        Err(error)
+    }
+}
+
+/// Decodes the value of the given offset from the bytes read from it.
+///
+/// FSUIPC data is little-endian. Strings end at their first NUL character. 
+fn decode_value(offset: &Offset, data: &[u8]) -> io::Result<Value> {
+    let size = offset.1 as usize;
+    if data.len() < size {
+        let error = io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected data length {} for offset {}", data.len(), offset));
+        return Err(error);
+    }
+    let mut data = &data[..size];
+    let value = match offset.2 {
+        OffsetType::I8 => Value::I8(try!(data.read_i8())),
+        OffsetType::U8 => Value::U8(try!(data.read_u8())),
+        OffsetType::I16 => Value::I16(try!(data.read_i16::<LittleEndian>())),
+        OffsetType::U16 => Value::U16(try!(data.read_u16::<LittleEndian>())),
+        OffsetType::I32 => Value::I32(try!(data.read_i32::<LittleEndian>())),
+        OffsetType::U32 => Value::U32(try!(data.read_u32::<LittleEndian>())),
+        OffsetType::I64 => Value::I64(try!(data.read_i64::<LittleEndian>())),
+        OffsetType::U64 => Value::U64(try!(data.read_u64::<LittleEndian>())),
+        OffsetType::F32 => Value::Float(try!(data.read_f32::<LittleEndian>()) as f64),
+        OffsetType::F64 => Value::Float(try!(data.read_f64::<LittleEndian>())),
+        OffsetType::Fix64 => 
+            Value::Float(try!(data.read_i64::<LittleEndian>()) as f64 / FIX64_ONE),
+        OffsetType::Str =>
+            Value::Str(String::from_utf8_lossy(significant_data(offset, data)).into_owned()),
+        OffsetType::Bytes => Value::Bytes(data.to_vec()),
+    };
+    Ok(value)
+}
+
+/// Returns the part of the data read from the given offset that determines its value.
+///
+/// That is the whole data but for strings, which end at their first NUL character.
+fn significant_data<'a>(offset: &Offset, data: &'a [u8]) -> &'a [u8] {
+    match offset.2 {
+        OffsetType::Str => {
+            let len = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+            &data[..len]
+        }
+        _ => data,
+    }
+}
+
+/// Encodes the given value as the bytes to be written into the given offset.
+fn encode_value(offset: &Offset, value: &Value) -> io::Result<Vec<u8>> {
+    try!(check_offset(offset));
+    let size = offset.1 as usize;
+    let invalid_value = || io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("value {} cannot be written into offset {}", value, offset));
+    let mut data = Vec::with_capacity(size);
+    match offset.2 {
+        OffsetType::F32 => try!(data.write_f32::<LittleEndian>(try!(f64::from_value(value)) as f32)),
+        OffsetType::F64 => try!(data.write_f64::<LittleEndian>(try!(f64::from_value(value)))),
+        OffsetType::Fix64 => {
+            let fixed = try!(f64::from_value(value)) * FIX64_ONE;
+            let limit = (1u64 << 63) as f64;
+            if !(fixed >= -limit && fixed < limit) {
+                return Err(invalid_value());
+            }
+            try!(data.write_i64::<LittleEndian>(fixed.round() as i64));
+        }
+        OffsetType::Str => match *value {
+            Value::Str(ref s) if s.len() < size => {
+                data.extend_from_slice(s.as_bytes());
+                data.resize(size, 0);
+            }
+            _ => return Err(invalid_value()),
+        },
+        OffsetType::Bytes => match *value {
+            Value::Bytes(ref bytes) if bytes.len() == size => data.extend_from_slice(bytes),
+            _ => return Err(invalid_value()),
+        },
+        _ => {
+            let bits = try!(integer_bits(value, offset.1));
+            try!(data.write_u64::<LittleEndian>(bits));
+            data.truncate(size);
+        }
+    }
+    Ok(data)
+}
+
+/// The value of 1 in 64-bit fixed-point numbers.
+const FIX64_ONE: f64 = 4294967296.0;
+
+/// Obtains the bits of an integer value to be written into an offset of the given size.
+///
+/// The value may be in the range of either the signed or the unsigned integers of
+/// that size, so `-1` and `255` are both written as `0xff` into a 1-byte offset.
+fn integer_bits(value: &Value, size: u16) -> io::Result<u64> {
+    if size == 8 {
+        return u64::from_value(value).or_else(|_| i64::from_value(value).map(|i| i as u64));
+    }
+    let bits = size as u32 * 8;
+    let i = try!(i64::from_value(value));
+    if i < -(1i64 << (bits - 1)) || i >= (1i64 << bits) {
+        let error = io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("value {} does not fit in {} bytes", value, size));
+        return Err(error);
+    }
+    Ok(i as u64)
+}
+
+#[derive(Debug)]
+struct WriteOp {
+    addr: u16,
+    data: Vec<u8>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use domain::*;
+
+    use super::*;
+
+    #[test]
+    fn should_trigger_event_on_subscription() {
+        let mem = MemoryBackend::new();
+        mem.poke(0x1234, &[42]);
+        let mut fsuipc = Fsuipc::new(mem.clone());
+        fsuipc.subscribe(1, &Var::offset(0x1234, 1).unwrap()).unwrap();
+        let events = poll(&mut fsuipc);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].device, 1);
+        assert_eq!(events[0].variable, Var::offset(0x1234, 1).unwrap());
+        assert_eq!(events[0].value, Value::U8(42));
+    }
+
+    #[test]
+    fn should_trigger_event_only_on_change() {
+        let mem = MemoryBackend::new();
+        let mut fsuipc = Fsuipc::new(mem.clone());
+        fsuipc.subscribe(1, &Var::offset(0x1234, 1).unwrap()).unwrap();
+        assert_eq!(poll(&mut fsuipc).len(), 1);
+        assert_eq!(poll(&mut fsuipc).len(), 0);
+        mem.poke(0x1234, &[7]);
+        let events = poll(&mut fsuipc);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].value, Value::U8(7));
+    }
+
+    #[test]
+    fn should_not_trigger_event_after_unsubscribe() {
+        let mem = MemoryBackend::new();
+        let mut fsuipc = Fsuipc::new(mem.clone());
+        fsuipc.subscribe(1, &Var::offset(0x1234, 1).unwrap()).unwrap();
+        fsuipc.unsubscribe_all(1).unwrap();
+        assert_eq!(poll(&mut fsuipc).len(), 0);
+    }
+
+    #[test]
+    fn should_not_trigger_event_after_unsubscribe_from_var() {
+        let mem = MemoryBackend::new();
+        let mut fsuipc = Fsuipc::new(mem.clone());
+        fsuipc.subscribe(1, &Var::offset(0x1234, 1).unwrap()).unwrap();
+        fsuipc.subscribe(1, &Var::offset(0x1235, 1).unwrap()).unwrap();
+        fsuipc.unsubscribe(1, &Var::offset(0x1234, 1).unwrap()).unwrap();
+        let events = poll(&mut fsuipc);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].variable, Var::offset(0x1235, 1).unwrap());
+    }
+
+    #[test]
+    fn should_fail_to_subscribe_named_var() {
+        let mut fsuipc = Fsuipc::new(MemoryBackend::new());
+        let error = fsuipc.subscribe(1, &Var::named("foobar")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn should_trigger_event_on_subscription_to_8_byte_offset() {
+        let mem = MemoryBackend::new();
+        mem.poke(0x0560, &[0, 0, 0, 0, 0, 0, 0xf0, 0x3f]);
+        let mut fsuipc = Fsuipc::new(mem.clone());
+        let offset = Var::Offset("0560+8:f64".parse().unwrap());
+        fsuipc.subscribe(1, &offset).unwrap();
+        let events = poll(&mut fsuipc);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].value, Value::Float(1.0));
+        mem.poke(0x0566, &[0x00, 0x40]);
+        let events = poll(&mut fsuipc);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].value, Value::Float(2.0));
+    }
+
+    #[test]
+    fn should_trigger_event_on_subscription_to_long_offset() {
+        let mem = MemoryBackend::new();
+        mem.poke(0x3d00, b"Airbus A320");
+        let mut fsuipc = Fsuipc::new(mem.clone());
+        let offset = Var::Offset("3D00+256:str".parse().unwrap());
+        fsuipc.subscribe(1, &offset).unwrap();
+        let events = poll(&mut fsuipc);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].value, Value::Str("Airbus A320".to_string()));
+        mem.poke(0x3dfe, b"x");
+        assert_eq!(poll(&mut fsuipc).len(), 0);
+        mem.poke(0x3d07, b"B");
+        let events = poll(&mut fsuipc);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].value, Value::Str("Airbus B320".to_string()));
+    }
+
+    #[test]
+    fn should_fail_to_subscribe_invalid_offset() {
+        let mut fsuipc = Fsuipc::new(MemoryBackend::new());
+        let offsets = vec![
+            Offset(0x1234, 3, OffsetType::I32),
+            Offset(0x1234, 0, OffsetType::Bytes),
+            Offset(0xfff0, 32, OffsetType::Str),
+        ];
+        for offset in offsets {
+            let error = fsuipc.subscribe(1, &Var::Offset(offset)).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            let error = fsuipc.read(&Var::Offset(offset)).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            let error = fsuipc.write(&Var::Offset(offset), &Value::I64(0)).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert_eq!(poll(&mut fsuipc).len(), 0);
+    }
+
+    #[test]
+    fn should_write_8_byte_offset() {
+        let mem = MemoryBackend::new();
+        let mut fsuipc = Fsuipc::new(mem.clone());
+        fsuipc.write(&Var::Offset("0560+8:u64".parse().unwrap()), &Value::U64(0x0102030405060708)).unwrap();
+        poll(&mut fsuipc);
+        assert_eq!(mem.peek(0x0560, 8), vec![8, 7, 6, 5, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn should_write_offset() {
+        let mem = MemoryBackend::new();
+        let mut fsuipc = Fsuipc::new(mem.clone());
+        fsuipc.write(&Var::offset(0x1234, 2).unwrap(), &Value::I64(0x0102)).unwrap();
+        poll(&mut fsuipc);
+        assert_eq!(mem.peek(0x1234, 2), vec![0x02, 0x01]);
+    }
+
+    #[test]
+    fn should_fail_to_write_values_unsupported_by_offset_type() {
+        let mut fsuipc = Fsuipc::new(MemoryBackend::new());
+        let offset = Var::Offset(Offset::typed(0x1234, 3, OffsetType::Str).unwrap());
+        let error = fsuipc.write(&offset, &Value::I64(1)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        let error = fsuipc.write(&offset, &Value::Str("foo".to_string())).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn should_decode_typed_offsets() {
+        let offset = |s: &str| s.parse::<Offset>().unwrap();
+        assert_eq!(decode_value(&offset("0+2"), &[0xff, 0xff]).unwrap(), Value::I16(-1));
+        assert_eq!(decode_value(&offset("0+2:u16"), &[0x01, 0x02]).unwrap(), Value::U16(0x0201));
+        assert_eq!(
+            decode_value(&offset("0+4:u32"), &[0x00, 0x00, 0x00, 0x80]).unwrap(), 
+            Value::U32(0x80000000));
+        assert_eq!(
+            decode_value(&offset("0+4:f32"), &[0x00, 0x00, 0xc0, 0x3f]).unwrap(), 
+            Value::Float(1.5));
+        assert_eq!(
+            decode_value(&offset("0+8:fix64"), &[0, 0, 0, 0x80, 0x2a, 0, 0, 0]).unwrap(), 
+            Value::Float(42.5));
+        assert_eq!(
+            decode_value(&offset("0+6:str"), b"A320\0x").unwrap(), 
+            Value::Str("A320".to_string()));
+        assert_eq!(
+            decode_value(&offset("0+2:bytes"), &[0x01, 0x02]).unwrap(), 
+            Value::Bytes(vec![0x01, 0x02]));
+        assert!(decode_value(&offset("0+4"), &[0x01, 0x02]).is_err());
+    }
+
+    #[test]
+    fn should_encode_typed_offsets() {
+        let offset = |s: &str| s.parse::<Offset>().unwrap();
+        assert_eq!(encode_value(&offset("0+2"), &Value::I64(0x0102)).unwrap(), vec![0x02, 0x01]);
+        assert_eq!(
+            encode_value(&offset("0+8:f64"), &Value::Float(1.0)).unwrap(), 
+            vec![0, 0, 0, 0, 0, 0, 0xf0, 0x3f]);
+        assert_eq!(
+            encode_value(&offset("0+8:fix64"), &Value::Float(-1.0)).unwrap(), 
+            vec![0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(
+            encode_value(&offset("0+8:u64"), &Value::U64(u64::max_value())).unwrap(), 
+            vec![0xff; 8]);
+        assert_eq!(
+            encode_value(&offset("0+6:str"), &Value::Str("A320".to_string())).unwrap(), 
+            b"A320\0\0".to_vec());
+        assert!(encode_value(&offset("0+4:str"), &Value::Str("A320".to_string())).is_err());
+        assert!(encode_value(&offset("0+2:bytes"), &Value::Bytes(vec![1])).is_err());
+    }
+
+    #[test]
+    fn should_write_signed_and_unsigned_values() {
+        let mem = MemoryBackend::new();
+        let mut fsuipc = Fsuipc::new(mem.clone());
+        fsuipc.write(&Var::offset(0x1234, 1).unwrap(), &Value::I64(-1)).unwrap();
+        fsuipc.write(&Var::offset(0x1235, 1).unwrap(), &Value::U8(254)).unwrap();
+        poll(&mut fsuipc);
+        assert_eq!(mem.peek(0x1234, 2), vec![0xff, 0xfe]);
+    }
+
+    #[test]
+    fn should_fail_to_write_values_out_of_range() {
+        let mut fsuipc = Fsuipc::new(MemoryBackend::new());
+        let offset = Var::offset(0x1234, 1).unwrap();
+        for value in vec![Value::I64(256), Value::I64(-129), Value::Float(1.5), Value::Str("1".to_string())] {
+            let error = fsuipc.write(&offset, &value).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn should_read_offset() {
+        let mem = MemoryBackend::new();
+        mem.poke(0x1234, &[42]);
+        let mut fsuipc = Fsuipc::new(mem.clone());
+        assert_eq!(fsuipc.read(&Var::offset(0x1234, 1).unwrap()).unwrap(), Value::U8(42));
+        assert!(fsuipc.read(&Var::named("foobar")).is_err());
+    }
+
+    #[test]
+    fn should_retry_queued_writes_after_timeout() {
+        let mem = MemoryBackend::new();
+        let mut fsuipc = Fsuipc::new(mem.clone());
+        fsuipc.write(&Var::offset(0x1234, 1).unwrap(), &Value::I64(1)).unwrap();
+        fsuipc.write(&Var::offset(0x1235, 1).unwrap(), &Value::I64(2)).unwrap();
+        mem.inject_timeouts(1);
+        poll(&mut fsuipc);
+        assert_eq!(mem.peek(0x1234, 2), vec![0, 0]);
+        poll(&mut fsuipc);
+        assert_eq!(mem.peek(0x1234, 2), vec![1, 2]);
+    }
+
+    fn poll<B: Backend>(fsuipc: &mut Fsuipc<B>) -> Vec<Event> {
+        let mut events = Vec::new();
+        fsuipc.poll(&mut events).unwrap();
+        events
+    }
+}
@@ -0,0 +1,19 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use super::ffi::Id;
+
+/// A table of named variables the lvar domain reads from and writes to.
+///
+/// This mirrors the subset of the PANELS functions used by the domain.
+pub trait Backend {
+    /// Obtains the ID of the named variable, or `None` if there is no such variable.
+    fn check_named_variable(&mut self, name: &str) -> Option<Id>;
+    fn get_named_variable_value(&mut self, id: Id) -> f64;
+    fn set_named_variable_value(&mut self, id: Id, value: f64);
+}
@@ -24,95 +24,95 @@ type GeneratePhase = u32;
 /// declared in `struct PANELS` in `gauges.h` header file of P3D SDK.
 pub struct PanelFunctions {
     _mod_id: Id,
-    _mod_init: extern "stdcall" fn(),
-    _mod_deinit: extern "stdcall" fn(),
+    _mod_init: extern "system" fn(),
+    _mod_deinit: extern "system" fn(),
     _mod_flags: Flags32,
     _mod_priority: u32,
     _mod_version: u32,
 
-    _reserved1: extern "stdcall" fn(),
-    _reserved2: extern "stdcall" fn(),
-    _reserved3: extern "stdcall" fn(),
-    _reserved4: extern "stdcall" fn(),
-    _reserved5: extern "stdcall" fn(),
-    _is_panel_visible_ident: extern "stdcall" fn(),
-    _tooltip_units_getset: extern "stdcall" fn(),
-    _reserved7: extern "stdcall" fn(),
-    _reserved8: extern "stdcall" fn(),
-    _reserved9: extern "stdcall" fn(),
-    _reserved10: extern "stdcall" fn(),
-    _reserved11: extern "stdcall" fn(),
-    _reserved12: extern "stdcall" fn(),
-    _reserved13: extern "stdcall" fn(),
-    _reserved14: extern "stdcall" fn(),
-    _reserved15: extern "stdcall" fn(),
-    _reserved16: extern "stdcall" fn(),
-    _reserved17: extern "stdcall" fn(),
-    _element_list_query: extern "stdcall" fn(),
-    _element_list_install: extern "stdcall" fn(),
-    _element_list_initialize: extern "stdcall" fn(),
-    _element_list_update: extern "stdcall" fn(),
-    _element_list_generate: extern "stdcall" fn(),
-    _element_list_plot: extern "stdcall" fn(),
-    _element_list_erase: extern "stdcall" fn(),
-    _element_list_kill: extern "stdcall" fn(),
-    _mouse_list_install: extern "stdcall" fn(),
-    _mouse_list_register: extern "stdcall" fn(),
-    _mouse_list_unregister: extern "stdcall" fn(),
-    _panel_window_togle: extern "stdcall" fn(),
-    _trigger_key_event: extern "stdcall" fn(),
-    _register_var_by_name: extern "stdcall" fn(),
-    _initialize_var: extern "stdcall" fn(),
-    _initialize_var_by_name: extern "stdcall" fn(),
-    _lookup_var: extern "stdcall" fn(),
-    _unregister_var_by_name: extern "stdcall" fn(),
-    _unregister_all_named_vars: extern "stdcall" fn(),
-    _reserved18: extern "stdcall" fn(),
-    _reserved19: extern "stdcall" fn(),
-    _panel_window_close_ident: extern "stdcall" fn(),
-    _panel_window_open_ident: extern "stdcall" fn(),
-    _panel_window_toggle_hud_color: extern "stdcall" fn(),
-    _panel_window_toggle_hud_units: extern "stdcall" fn(),
-    _radio_stack_popup: extern "stdcall" fn(),
-    _radio_stack_autoclose: extern "stdcall" fn(),
-    pub check_named_variable: extern "stdcall" fn(name: *const c_char) -> Id,
-    _register_named_variable: extern "stdcall" fn(),
-    pub get_named_variable_value: extern "stdcall" fn(id: Id) -> f64,
-    _get_named_variable_typed_value: extern "stdcall" fn(),
-    pub set_named_variable_value: extern "stdcall" fn(id: Id, value: f64),
-    _set_named_variable_typed_value: extern "stdcall" fn(),
-    _reserved26: extern "stdcall" fn(),
-    _reserved27: extern "stdcall" fn(),
-    _get_name_of_named_variable: extern "stdcall" fn(),
-    _reserved29: extern "stdcall" fn(),
-    _panel_resource_string_get: extern "stdcall" fn(),
-    _panel_window_toggle_menu_id: extern "stdcall" fn(),
-    _reserved30: extern "stdcall" fn(),
-    _reserved31: extern "stdcall" fn(),
-    _element_use_color: extern "stdcall" fn(),
-    _set_gauge_flags: extern "stdcall" fn(),
-    _get_gauge_flags: extern "stdcall" fn(),
-    _gauge_calculator_code_precompile: extern "stdcall" fn(),
-    _execute_calculator_code: extern "stdcall" fn(),
-    _format_calculator_string: extern "stdcall" fn(),
-    _reserved32: extern "stdcall" fn(),
-    _reserved33: extern "stdcall" fn(),
-    _get_units_enum: extern "stdcall" fn(),
-    _get_aircraft_var_enum: extern "stdcall" fn(),
-    _aircraft_varget: extern "stdcall" fn(),
-    _panel_register_c_callback: extern "stdcall" fn(),
-    _panel_get_registered_c_callback: extern "stdcall" fn(),
-    _panel_get_aircraft_c_callback: extern "stdcall" fn(),
-    _send_key_event: extern "stdcall" fn(),
-    _register_key_event_handler: extern "stdcall" fn(),
-    _unregister_key_event_handler: extern "stdcall" fn(),
-    _reserved34: extern "stdcall" fn(),
-    _reserved35: extern "stdcall" fn(),
-    _process_shared_event_out: extern "stdcall" fn(),
-    _is_master: extern "stdcall" fn(),
-    _reserved36: extern "stdcall" fn(),
-    _set_named_variable_value_sync: extern "stdcall" fn(),
-    _set_named_variable_sync_enabled: extern "stdcall" fn(),
+    _reserved1: extern "system" fn(),
+    _reserved2: extern "system" fn(),
+    _reserved3: extern "system" fn(),
+    _reserved4: extern "system" fn(),
+    _reserved5: extern "system" fn(),
+    _is_panel_visible_ident: extern "system" fn(),
+    _tooltip_units_getset: extern "system" fn(),
+    _reserved7: extern "system" fn(),
+    _reserved8: extern "system" fn(),
+    _reserved9: extern "system" fn(),
+    _reserved10: extern "system" fn(),
+    _reserved11: extern "system" fn(),
+    _reserved12: extern "system" fn(),
+    _reserved13: extern "system" fn(),
+    _reserved14: extern "system" fn(),
+    _reserved15: extern "system" fn(),
+    _reserved16: extern "system" fn(),
+    _reserved17: extern "system" fn(),
+    _element_list_query: extern "system" fn(),
+    _element_list_install: extern "system" fn(),
+    _element_list_initialize: extern "system" fn(),
+    _element_list_update: extern "system" fn(),
+    _element_list_generate: extern "system" fn(),
+    _element_list_plot: extern "system" fn(),
+    _element_list_erase: extern "system" fn(),
+    _element_list_kill: extern "system" fn(),
+    _mouse_list_install: extern "system" fn(),
+    _mouse_list_register: extern "system" fn(),
+    _mouse_list_unregister: extern "system" fn(),
+    _panel_window_togle: extern "system" fn(),
+    _trigger_key_event: extern "system" fn(),
+    _register_var_by_name: extern "system" fn(),
+    _initialize_var: extern "system" fn(),
+    _initialize_var_by_name: extern "system" fn(),
+    _lookup_var: extern "system" fn(),
+    _unregister_var_by_name: extern "system" fn(),
+    _unregister_all_named_vars: extern "system" fn(),
+    _reserved18: extern "system" fn(),
+    _reserved19: extern "system" fn(),
+    _panel_window_close_ident: extern "system" fn(),
+    _panel_window_open_ident: extern "system" fn(),
+    _panel_window_toggle_hud_color: extern "system" fn(),
+    _panel_window_toggle_hud_units: extern "system" fn(),
+    _radio_stack_popup: extern "system" fn(),
+    _radio_stack_autoclose: extern "system" fn(),
+    pub check_named_variable: extern "system" fn(name: *const c_char) -> Id,
+    _register_named_variable: extern "system" fn(),
+    pub get_named_variable_value: extern "system" fn(id: Id) -> f64,
+    _get_named_variable_typed_value: extern "system" fn(),
+    pub set_named_variable_value: extern "system" fn(id: Id, value: f64),
+    _set_named_variable_typed_value: extern "system" fn(),
+    _reserved26: extern "system" fn(),
+    _reserved27: extern "system" fn(),
+    _get_name_of_named_variable: extern "system" fn(),
+    _reserved29: extern "system" fn(),
+    _panel_resource_string_get: extern "system" fn(),
+    _panel_window_toggle_menu_id: extern "system" fn(),
+    _reserved30: extern "system" fn(),
+    _reserved31: extern "system" fn(),
+    _element_use_color: extern "system" fn(),
+    _set_gauge_flags: extern "system" fn(),
+    _get_gauge_flags: extern "system" fn(),
+    _gauge_calculator_code_precompile: extern "system" fn(),
+    _execute_calculator_code: extern "system" fn(),
+    _format_calculator_string: extern "system" fn(),
+    _reserved32: extern "system" fn(),
+    _reserved33: extern "system" fn(),
+    _get_units_enum: extern "system" fn(),
+    _get_aircraft_var_enum: extern "system" fn(),
+    _aircraft_varget: extern "system" fn(),
+    _panel_register_c_callback: extern "system" fn(),
+    _panel_get_registered_c_callback: extern "system" fn(),
+    _panel_get_aircraft_c_callback: extern "system" fn(),
+    _send_key_event: extern "system" fn(),
+    _register_key_event_handler: extern "system" fn(),
+    _unregister_key_event_handler: extern "system" fn(),
+    _reserved34: extern "system" fn(),
+    _reserved35: extern "system" fn(),
+    _process_shared_event_out: extern "system" fn(),
+    _is_master: extern "system" fn(),
+    _reserved36: extern "system" fn(),
+    _set_named_variable_value_sync: extern "system" fn(),
+    _set_named_variable_sync_enabled: extern "system" fn(),
 }
 
 #[allow(non_upper_case_globals)]
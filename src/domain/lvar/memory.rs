@@ -0,0 +1,85 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::backend::*;
+use super::ffi::Id;
+
+/// A backend that keeps a table of named variables in memory.
+///
+/// It is useful to run the lvar domain outside the simulator. The clones of a memory
+/// backend share the same table, so the variables can be inspected and altered after
+/// the backend is handed to the domain.
+#[derive(Clone)]
+pub struct MemoryBackend {
+    vars: Rc<RefCell<Vec<(String, f64)>>>,
+}
+
+#[allow(dead_code)]
+impl MemoryBackend {
+    pub fn new() -> MemoryBackend {
+        MemoryBackend { vars: Rc::new(RefCell::new(Vec::new())) }
+    }
+
+    /// Sets the value of the given variable, defining it if it does not exist.
+    pub fn define(&self, name: &str, value: f64) {
+        let mut vars = self.vars.borrow_mut();
+        match vars.iter().position(|v| v.0 == name) {
+            Some(i) => vars[i].1 = value,
+            None => vars.push((name.to_string(), value)),
+        }
+    }
+
+    /// Obtains the value of the given variable, if defined.
+    pub fn value(&self, name: &str) -> Option<f64> {
+        self.vars.borrow().iter().find(|v| v.0 == name).map(|v| v.1)
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn check_named_variable(&mut self, name: &str) -> Option<Id> {
+        self.vars.borrow().iter().position(|v| v.0 == name).map(|i| i as Id)
+    }
+
+    fn get_named_variable_value(&mut self, id: Id) -> f64 {
+        self.vars.borrow()[id as usize].1
+    }
+
+    fn set_named_variable_value(&mut self, id: Id, value: f64) {
+        self.vars.borrow_mut()[id as usize].1 = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use domain::lvar::backend::*;
+
+    use super::*;
+
+    #[test]
+    fn should_check_defined_variables() {
+        let mut mem = MemoryBackend::new();
+        mem.define("foo", 1.0);
+        mem.define("bar", 2.0);
+        assert_eq!(mem.check_named_variable("foo"), Some(0));
+        assert_eq!(mem.check_named_variable("bar"), Some(1));
+        assert_eq!(mem.check_named_variable("baz"), None);
+    }
+
+    #[test]
+    fn should_get_and_set_variables() {
+        let mut mem = MemoryBackend::new();
+        mem.define("foo", 1.0);
+        let id = mem.check_named_variable("foo").unwrap();
+        assert_eq!(mem.get_named_variable_value(id), 1.0);
+        mem.set_named_variable_value(id, 2.5);
+        assert_eq!(mem.value("foo"), Some(2.5));
+    }
+}
@@ -7,24 +7,31 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 pub mod ffi;
+mod backend;
+mod memory;
+#[cfg(windows)]
+mod panels;
 
 use std::collections::VecDeque;
-use std::ffi::CString;
 use std::io;
 
 use domain::*;
-use types::*;
 
-use self::ffi::*;
+pub use self::backend::*;
+pub use self::memory::*;
+#[cfg(windows)]
+pub use self::panels::*;
 
-pub struct LVar {
+pub struct LVar<B: Backend> {
+    backend: B,
     subscriptions: Vec<Subscription>,
     writes: VecDeque<WriteOp>,
 }
 
-impl LVar {
-    pub fn new() -> LVar {
+impl<B: Backend> LVar<B> {
+    pub fn new(backend: B) -> LVar<B> {
         LVar { 
+            backend: backend,
             subscriptions: Vec::new(), 
             writes: VecDeque::with_capacity(32) 
         }
@@ -36,8 +43,8 @@ impl LVar {
             match self.writes.pop_front() {
                 Some(op) => {
 			        debug!("processing a write operation for {:?} <- {}", op.lvar, op.value);
-                    match check_named_variable(&op.lvar) {
-                        Some(id) => { set_named_variable_value(id, op.value); }
+                    match self.backend.check_named_variable(&op.lvar) {
+                        Some(id) => { self.backend.set_named_variable_value(id, op.value); }
                         None => {
                             error!("there is no such lvar named {}", op.lvar);
                             next_writes.push_back(op); 
@@ -52,20 +59,45 @@ impl LVar {
 
     fn poll_events(&mut self, events: &mut Vec<Event>) -> io::Result<()> {
         for sub in self.subscriptions.iter_mut() {
-            sub.trigger_event(events);
+            sub.trigger_event(&mut self.backend, events);
         }
         Ok(())
     }    
 }
 
-impl Domain for LVar {
+impl<B: Backend> Domain for LVar<B> {
+    fn read(&mut self, variable: &Var) -> io::Result<Value> {
+        match variable {
+            &Var::Named(ref lvar) => {
+                match self.backend.check_named_variable(lvar) {
+                    Some(id) => {
+                        let value = self.backend.get_named_variable_value(id);
+                        Ok(Value::from_f64(value))
+                    }
+                    None => {
+                        let error = io::Error::new(
+                            io::ErrorKind::NotFound,
+                            format!("there is no such lvar named {}", lvar));
+                        Err(error)
+                    }
+                }
+            }
+            _ => {
+                let error = io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("lvar domain does not support variable {:?}", variable));
+                Err(error)
+            }
+        }
+    }
+
     fn write(&mut self, variable: &Var, value: &Value) -> io::Result<()> {
         debug!("queueing write operation for {:?} <- {}", variable, value);
         match variable {
             &Var::Named(ref lvar) => {
                 let op = WriteOp {
                     lvar: lvar.clone(),
-                    value: f64::from(value)
+                    value: try!(f64::from_value(value))
                 };
                 self.writes.push_back(op);
                 Ok(())
@@ -100,6 +132,14 @@ impl Domain for LVar {
         }
     }
     
+    fn unsubscribe(&mut self, device: DeviceId, variable: &Var) -> io::Result<()> {
+        debug!("removing subscription for device ID {} to {:?}", device, variable);
+        self.subscriptions.retain(|s| {
+            s.device != device || Var::Named(s.lvar.clone()) != *variable
+        });
+        Ok(())
+    }
+    
     fn unsubscribe_all(&mut self, device: DeviceId) -> io::Result<()> {
         debug!("removing all subscriptions for device ID {}", device);
         self.subscriptions.retain(|s| s.device != device);
@@ -120,19 +160,19 @@ struct Subscription {
 }
 
 impl Subscription {
-    fn trigger_event(&mut self, events: &mut Vec<Event>) {
-        let id = match check_named_variable(&self.lvar) {
+    fn trigger_event<B: Backend>(&mut self, backend: &mut B, events: &mut Vec<Event>) {
+        let id = match backend.check_named_variable(&self.lvar) {
             Some(id) => id,
             None => {
                 error!("cannot obtain LVAR ID for variable {}", self.lvar);
                 return;
             } 
         };
-        let val = Value::Number(get_named_variable_value(id) as isize);
+        let val = Value::from_f64(backend.get_named_variable_value(id));
         let must_trigger = self.retain.as_ref().map(|v| *v != val).unwrap_or(true);
         if must_trigger {
             let var = Var::Named(self.lvar.clone());
-            let event = Event::new(self.device, "lvar", var, val);
+            let event = Event::new(self.device, "lvar", var, val.clone());
             events.push(event);
             self.retain = Some(val);
         }
@@ -144,31 +184,132 @@ struct WriteOp {
     value: f64,
 }
 
-fn check_named_variable(name: &str) -> Option<Id> {
-    unsafe {
-        let func = (*Panels).check_named_variable;
-        let name = match CString::new(name) {
-            Ok(raw) => raw,
-            Err(e) => {
-                error!("cannot convert {} to a valid C-like string: {:?}", name, e);
-                return None;
-            }
-        };
-        let id = (func)(name.as_ptr());
-        if id != -1 { Some(id) } else { None }
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use domain::*;
+
+    use super::*;
+
+    #[test]
+    fn should_trigger_event_on_subscription() {
+        let mem = MemoryBackend::new();
+        mem.define("foobar", 42.0);
+        let mut lvar = LVar::new(mem.clone());
+        lvar.subscribe(1, &Var::named("foobar")).unwrap();
+        let events = poll(&mut lvar);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].device, 1);
+        assert_eq!(events[0].variable, Var::named("foobar"));
+        assert_eq!(events[0].value, Value::I64(42));
     }
-}
 
-fn get_named_variable_value(id: Id) -> f64 {
-    unsafe {
-        let func = (*Panels).get_named_variable_value;
-        (func)(id)
+    #[test]
+    fn should_trigger_event_only_on_change() {
+        let mem = MemoryBackend::new();
+        mem.define("foobar", 42.0);
+        let mut lvar = LVar::new(mem.clone());
+        lvar.subscribe(1, &Var::named("foobar")).unwrap();
+        assert_eq!(poll(&mut lvar).len(), 1);
+        assert_eq!(poll(&mut lvar).len(), 0);
+        mem.define("foobar", 7.0);
+        let events = poll(&mut lvar);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].value, Value::I64(7));
+    }
+
+    #[test]
+    fn should_not_trigger_event_for_undefined_var() {
+        let mut lvar = LVar::new(MemoryBackend::new());
+        lvar.subscribe(1, &Var::named("foobar")).unwrap();
+        assert_eq!(poll(&mut lvar).len(), 0);
+    }
+
+    #[test]
+    fn should_not_trigger_event_after_unsubscribe() {
+        let mem = MemoryBackend::new();
+        mem.define("foobar", 42.0);
+        let mut lvar = LVar::new(mem.clone());
+        lvar.subscribe(1, &Var::named("foobar")).unwrap();
+        lvar.unsubscribe_all(1).unwrap();
+        assert_eq!(poll(&mut lvar).len(), 0);
+    }
+
+    #[test]
+    fn should_not_trigger_event_after_unsubscribe_from_var() {
+        let mem = MemoryBackend::new();
+        mem.define("foo", 1.0);
+        mem.define("bar", 2.0);
+        let mut lvar = LVar::new(mem.clone());
+        lvar.subscribe(1, &Var::named("foo")).unwrap();
+        lvar.subscribe(2, &Var::named("foo")).unwrap();
+        lvar.subscribe(1, &Var::named("bar")).unwrap();
+        lvar.unsubscribe(1, &Var::named("foo")).unwrap();
+        let events = poll(&mut lvar);
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|e| e.device != 1 || e.variable == Var::named("bar")));
+    }
+
+    #[test]
+    fn should_write_lvar() {
+        let mem = MemoryBackend::new();
+        mem.define("foobar", 0.0);
+        let mut lvar = LVar::new(mem.clone());
+        lvar.write(&Var::named("foobar"), &Value::I64(42)).unwrap();
+        poll(&mut lvar);
+        assert_eq!(mem.value("foobar"), Some(42.0));
+    }
+
+    #[test]
+    fn should_read_lvar() {
+        let mem = MemoryBackend::new();
+        mem.define("foobar", 42.0);
+        let mut lvar = LVar::new(mem.clone());
+        assert_eq!(lvar.read(&Var::named("foobar")).unwrap(), Value::I64(42));
+    }
+
+    #[test]
+    fn should_keep_decimals_of_lvars() {
+        let mem = MemoryBackend::new();
+        mem.define("foobar", 0.0);
+        let mut lvar = LVar::new(mem.clone());
+        lvar.subscribe(1, &Var::named("foobar")).unwrap();
+        lvar.write(&Var::named("foobar"), &Value::Float(245.5)).unwrap();
+        let events = poll(&mut lvar);
+        assert_eq!(events[0].value, Value::Float(245.5));
+        assert_eq!(lvar.read(&Var::named("foobar")).unwrap(), Value::Float(245.5));
+    }
+
+    #[test]
+    fn should_fail_to_write_string_to_lvar() {
+        let mut lvar = LVar::new(MemoryBackend::new());
+        let error = lvar.write(&Var::named("foobar"), &Value::Str("foo".to_string())).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn should_fail_to_read_undefined_lvar() {
+        let mut lvar = LVar::new(MemoryBackend::new());
+        let error = lvar.read(&Var::named("foobar")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn should_retry_pending_writes_until_lvar_is_defined() {
+        let mem = MemoryBackend::new();
+        let mut lvar = LVar::new(mem.clone());
+        lvar.write(&Var::named("foobar"), &Value::I64(42)).unwrap();
+        poll(&mut lvar);
+        assert_eq!(mem.value("foobar"), None);
+        mem.define("foobar", 0.0);
+        poll(&mut lvar);
+        assert_eq!(mem.value("foobar"), Some(42.0));
     }
-}
 
-fn set_named_variable_value(id: Id, value: f64) {
-    unsafe {
-        let func = (*Panels).set_named_variable_value;
-        (func)(id, value)
+    fn poll<B: Backend>(lvar: &mut LVar<B>) -> Vec<Event> {
+        let mut events = Vec::new();
+        lvar.poll(&mut events).unwrap();
+        events
     }
 }
@@ -0,0 +1,65 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::ffi::CString;
+
+use super::backend::*;
+use super::ffi::*;
+
+/// A backend that uses the PANELS function table exported by FSX/P3D.
+///
+/// The table is set by the simulator when the module is loaded. Until then, or when
+/// running outside the simulator, no variable is found.
+pub struct PanelsBackend;
+
+impl PanelsBackend {
+    pub fn new() -> PanelsBackend {
+        PanelsBackend
+    }
+
+    fn panels(&self) -> Option<&PanelFunctions> {
+        unsafe {
+            if Panels.is_null() { None } else { Some(&*Panels) }
+        }
+    }
+}
+
+impl Backend for PanelsBackend {
+    fn check_named_variable(&mut self, name: &str) -> Option<Id> {
+        let func = match self.panels() {
+            Some(panels) => panels.check_named_variable,
+            None => {
+                debug!("cannot check LVAR {}: PANELS functions are not available", name);
+                return None;
+            }
+        };
+        let name = match CString::new(name) {
+            Ok(raw) => raw,
+            Err(e) => {
+                error!("cannot convert {} to a valid C-like string: {:?}", name, e);
+                return None;
+            }
+        };
+        let id = (func)(name.as_ptr());
+        if id != -1 { Some(id) } else { None }
+    }
+
+    fn get_named_variable_value(&mut self, id: Id) -> f64 {
+        let func = self.panels()
+            .expect("PANELS functions are not available")
+            .get_named_variable_value;
+        (func)(id)
+    }
+
+    fn set_named_variable_value(&mut self, id: Id, value: f64) {
+        let func = self.panels()
+            .expect("PANELS functions are not available")
+            .set_named_variable_value;
+        (func)(id, value)
+    }
+}
@@ -11,6 +11,7 @@ use std::collections::HashMap;
 use std::io;
 use std::rc::Rc;
 
+use config::DomainSettings;
 use types::*;
 
 pub mod fsuipc;
@@ -36,8 +37,11 @@ impl Event {
 }
 
 pub trait Domain {
+    /// Reads the current value of the given variable right away.
+    fn read(&mut self, variable: &Var) -> io::Result<Value>;
     fn write(&mut self, variable: &Var, value: &Value) -> io::Result<()>;
     fn subscribe(&mut self, device: DeviceId, variable: &Var) -> io::Result<()>;
+    fn unsubscribe(&mut self, device: DeviceId, variable: &Var) -> io::Result<()>;
     fn unsubscribe_all(&mut self, device: DeviceId) -> io::Result<()>;
     fn poll(&mut self, events: &mut Vec<Event>) -> io::Result<()>;
 }
@@ -49,30 +53,71 @@ pub struct DomainDispatcher {
 
 impl DomainDispatcher {
     
-    pub fn new() -> io::Result<DomainDispatcher> {
+    pub fn new(settings: &DomainSettings) -> io::Result<DomainDispatcher> {
         let mut dispatcher = DomainDispatcher { domains: HashMap::new() };
-        dispatcher.add("fsuipc", try!(fsuipc::Fsuipc::new()));
-        dispatcher.add("lvar", lvar::LVar::new());
+        for name in &settings.enabled {
+            match &name[..] {
+                "fsuipc" => try!(dispatcher.add_fsuipc()),
+                "lvar" => dispatcher.add_lvar(),
+                _ => {
+                    let error = io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("cannot enable unknown domain '{}'", name));
+                    return Err(error);
+                }
+            }
+            info!("domain {} is enabled", name);
+        }
         Ok(dispatcher)
     }
+
+    #[cfg(windows)]
+    fn add_fsuipc(&mut self) -> io::Result<()> {
+        let backend = try!(fsuipc::LocalBackend::new());
+        self.add("fsuipc", fsuipc::Fsuipc::new(backend));
+        Ok(())
+    }
+
+    #[cfg(not(windows))]
+    fn add_fsuipc(&mut self) -> io::Result<()> {
+        info!("FSUIPC is not available on this platform, using an in-memory offset space");
+        self.add("fsuipc", fsuipc::Fsuipc::new(fsuipc::MemoryBackend::new()));
+        Ok(())
+    }
     
-    fn add<D: Domain + 'static>(&mut self, name: &str, d: D) {
+    #[cfg(windows)]
+    fn add_lvar(&mut self) {
+        self.add("lvar", lvar::LVar::new(lvar::PanelsBackend::new()));
+    }
+
+    #[cfg(not(windows))]
+    fn add_lvar(&mut self) {
+        info!("PANELS are not available on this platform, using an in-memory LVAR table");
+        self.add("lvar", lvar::LVar::new(lvar::MemoryBackend::new()));
+    }
+
+    pub fn add<D: Domain + 'static>(&mut self, name: &str, d: D) {
         self.domains.insert(name.to_string(), Rc::new(RefCell::new(d)));
     }
     
-    pub fn with_domain<F>(&mut self, name: &str, f: F) -> io::Result<()> 
-    where F: FnOnce(&mut Domain) -> io::Result<()> {
+    pub fn with_domain<F, T>(&mut self, name: &str, f: F) -> io::Result<T> 
+    where F: FnOnce(&mut Domain) -> io::Result<T> {
         match self.domains.get(name) {
             Some(domain) => f(&mut *domain.borrow_mut()),
             None => {
                 let error = io::Error::new(
-                    io::ErrorKind::InvalidInput,
+                    io::ErrorKind::NotFound,
                     format!("no such domain '{}'", name));
                 Err(error)
             }
         }
     }
 
+    /// Removes the subscriptions of the given device from all the domains.
+    pub fn unsubscribe_all(&mut self, device: DeviceId) -> io::Result<()> {
+        self.with_all_domains(|domain| domain.unsubscribe_all(device))
+    }
+
     pub fn with_all_domains<F>(&mut self, mut f: F) -> io::Result<()> 
     where F: FnMut(&mut Domain) -> io::Result<()> {
         for domain in self.domains.values() {
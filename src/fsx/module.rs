@@ -6,6 +6,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use std::mem;
 use std::mem::size_of;
 use std::ptr;
 
@@ -13,7 +14,7 @@ use libc::malloc;
 
 use fv::*;
 use config;
-use fsx::logging;
+use logging;
 
 const CONFIG_FILE: &'static str = "Modules/flightvars.conf";
 
@@ -27,17 +28,19 @@ impl Module {
     }
 
     pub fn start(&mut self) {
-        let settings = config::Settings::from_toml_file(CONFIG_FILE)
+        let mut settings = config::Settings::from_toml_file(CONFIG_FILE)
         	.ok()
         	.unwrap_or_else(|| {
     	        println!("FlightVars cannot load config file at {}", CONFIG_FILE);
     	        println!("Falling back to default settings");
     	        config::Settings::default()
         	});
-    	logging::config_logging(settings.logging);
+    	if let Err(e) = logging::config_logging(mem::replace(&mut settings.logging, Default::default())) {
+    	    println!("FlightVars cannot configure logging: {}", e);
+    	}
 
         info!("Starting FlightVars module v{}", FLIGHTVARS_VERSION);
-        self.flightvars = Some(FlightVars::new(&settings.oacsp_serial).unwrap());
+        self.flightvars = Some(FlightVars::new(&settings).unwrap());
         info!("FlightVars module started successfully");
     }
     pub fn stop(self) {
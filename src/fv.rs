@@ -7,22 +7,163 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use std::boxed::Box;
-use std::ffi::OsStr;
+use std::cell::Cell;
+use std::cmp;
+use std::ffi::{OsStr, OsString};
 use std::io;
+use std::rc::Rc;
 use std::sync::mpsc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use config::*;
 use domain;
 use domain::DomainDispatcher;
 use io::*;
 use proto::*;
+use types::*;
+
+const MIN_SERIAL_BACKOFF_SECS: u64 = 1;
+const MAX_SERIAL_BACKOFF_SECS: u64 = 30;
+
+/// A listener whose accepted devices are served with a given protocol.
+struct Endpoint {
+    listener: Box<Listener>,
+    protocol: Box<Fn(Device, DomainDispatcher) -> Box<Protocol>>,
+}
+
+/// A serial port served with a given protocol.
+///
+/// The port is supervised rather than opened once. Whenever it is down, either because
+/// it cannot be opened (e.g., the Arduino is not plugged yet) or because its device was
+/// closed after an IO error, it is opened again and attached to the reactor with a
+/// fresh protocol handler. Failed attempts are retried with an exponential backoff.
+struct SerialPort {
+    port: OsString,
+    name: String,
+    open: Box<Fn(&OsStr) -> io::Result<Device>>,
+    protocol: Box<Fn(Device, DomainDispatcher) -> Box<Protocol>>,
+    attached: Option<Rc<Cell<bool>>>,
+    backoff: Duration,
+    next_attempt: Instant,
+}
+
+impl SerialPort {
+
+    fn new<O, F>(port: &OsStr, name: &str, open: O, protocol: F) -> SerialPort 
+    where O: Fn(&OsStr) -> io::Result<Device> + 'static, 
+          F: Fn(Device, DomainDispatcher) -> Box<Protocol> + 'static {
+        SerialPort {
+            port: port.to_os_string(),
+            name: name.to_string(),
+            open: Box::new(open),
+            protocol: Box::new(protocol),
+            attached: None,
+            backoff: Duration::from_secs(MIN_SERIAL_BACKOFF_SECS),
+            next_attempt: Instant::now(),
+        }
+    }
+
+    fn is_up(&self) -> bool {
+        self.attached.as_ref().map(|a| a.get()).unwrap_or(false)
+    }
+
+    /// Checks whether the port is still attached, attaching it again if needed.
+    fn poll(&mut self, reactor: &mut Reactor<Box<Protocol>>, domains: &DomainDispatcher) {
+        if self.is_up() {
+            return;
+        }
+        if self.attached.take().is_some() {
+            warn!("serial port {:?} is down", self.port);
+            self.backoff = Duration::from_secs(MIN_SERIAL_BACKOFF_SECS);
+            self.next_attempt = Instant::now();
+        }
+        if Instant::now() < self.next_attempt {
+            return;
+        }
+        match self.attach(reactor, domains) {
+            Ok(attached) => {
+                info!("serial port {:?} is up, serving {} protocol", self.port, self.name);
+                self.attached = Some(attached);
+                self.backoff = Duration::from_secs(MIN_SERIAL_BACKOFF_SECS);
+            }
+            Err(e) => {
+                error!("cannot configure serial port {:?}: {:?}", self.port, e);
+                info!("retrying serial port {:?} in {} seconds", self.port, self.backoff.as_secs());
+                self.next_attempt = Instant::now() + self.backoff;
+                let backoff = cmp::min(self.backoff.as_secs() * 2, MAX_SERIAL_BACKOFF_SECS);
+                self.backoff = Duration::from_secs(backoff);
+            }
+        }
+    }
+
+    fn attach(&self, reactor: &mut Reactor<Box<Protocol>>, domains: &DomainDispatcher) 
+        -> io::Result<Rc<Cell<bool>>> {
+        let dev = try!((self.open)(&self.port));
+        debug!("initializing {} protocol for serial port {:?}", self.name, self.port);
+        let attached = Rc::new(Cell::new(true));
+        let proto = Supervised {
+            proto: (self.protocol)(dev, domains.clone()),
+            attached: attached.clone(),
+        };
+        debug!("attaching serial port {:?} to IO reactor", self.port);
+        try!(reactor.attach(Box::new(proto)));
+        Ok(attached)
+    }
+}
+
+/// A protocol handler that reports when its device is detached from the reactor.
+struct Supervised {
+    proto: Box<Protocol>,
+    attached: Rc<Cell<bool>>,
+}
+
+impl DeviceHandler for Supervised {
+    fn device(&mut self) -> &mut Device { self.proto.device() }
+
+    fn process_event(&mut self, event: Event) -> io::Result<()> {
+        if event == Event::Closed {
+            self.attached.set(false);
+        }
+        self.proto.process_event(event)
+    }
+}
+
+impl Protocol for Supervised {
+    fn send_update(&mut self, domain: &str, variable: Var, value: Value) -> io::Result<()> {
+        self.proto.send_update(domain, variable, value)
+    }
+
+    fn tick(&mut self) -> io::Result<()> { self.proto.tick() }
+}
+
+/// Lists the serial ports available in the system.
+fn available_serial_ports() -> io::Result<Vec<OsString>> {
+    Serial::available_ports().map(|ports| ports.into_iter().map(OsString::from).collect())
+}
+
+/// Opens a serial port connected to an Arduino board.
+fn open_arduino(port: &OsStr) -> io::Result<Device> {
+    let baudrate = 9600;
+    debug!("opening serial port {:?} at {} bauds", port, baudrate);
+    let name = try!(port.to_str().ok_or(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("serial port name {:?} is not valid UTF-8", port))));
+    let mut serial = try!(Serial::open_arduino(name, baudrate));
+    debug!("setting read-upon-available timeouts for serial port {:?}", port);
+    try!(serial.set_timeouts(&SerialTimeouts::ReadUponAvailable));
+    Ok(Device::from(serial))
+}
 
 pub struct FlightVars {
     cmd_channel: mpsc::Receiver<FlightVarsCommand>,
     domains: DomainDispatcher,
-    iocp: CompletionPort<Box<Protocol>>,
+    reactor: Reactor<Box<Protocol>>,
+    endpoints: Vec<Endpoint>,
+    serial_ports: Vec<SerialPort>,
+    serial_discovery: Option<OacspDiscovery>,
+    udp_endpoints: Vec<OacspUdp>,
+    mqtt_bridge: Option<MqttBridge>,
     stop: bool,
 }
 
@@ -30,17 +171,32 @@ unsafe impl Send for FlightVars {}
 
 impl FlightVars {
     
-    pub fn new(settings: &OacspSerialSettings) -> io::Result<FlightVarsHandler> {
-        let domains = try!(DomainDispatcher::new());
-        let iocp = try!(CompletionPort::new());
+    pub fn new(settings: &Settings) -> io::Result<FlightVarsHandler> {
+        let domains = try!(DomainDispatcher::new(&settings.domains));
+        let reactor = try!(Reactor::new());
         let (tx, rx) = mpsc::channel();
         let mut fv = FlightVars { 
             cmd_channel: rx, 
             domains: domains, 
-            iocp: iocp,
+            reactor: reactor,
+            endpoints: Vec::new(),
+            serial_ports: Vec::new(),
+            serial_discovery: None,
+            udp_endpoints: Vec::new(),
+            mqtt_bridge: None,
             stop: false,
         };
-        fv.open_serial_ports(settings);
+        fv.open_serial_ports(&settings.oacsp_serial, &settings.binary_serial);
+        fv.open_tcp_listeners(&settings.oacsp_tcp);
+        fv.open_ipc_listeners(&settings.oacsp_ipc);
+        fv.open_udp_endpoints(&settings.oacsp_udp);
+        fv.open_mqtt_listeners(&settings.mqtt);
+        if let Some(ref bridge) = settings.mqtt_bridge {
+            fv.open_mqtt_bridge(bridge);
+        }
+        fv.open_websocket_listeners(&settings.websocket);
+        fv.open_http_listeners(&settings.http);
+        fv.open_jsonrpc_listeners(&settings.jsonrpc);
         let join_handle = thread::spawn(move || fv.run());
         let handler = FlightVarsHandler {
             join_handle: join_handle,
@@ -49,36 +205,158 @@ impl FlightVars {
         Ok(handler)
     }
     
-    fn open_serial_ports(&mut self, settings: &OacspSerialSettings) {
-        // TODO: break coupleness among serial ports, OACSP and fixed baud-rate
-        for port in &settings.ports {
-            match self.open_serial_port(port) {
-                Ok(_) => {
-                    info!("serial port {:?} successfully configured", port);
+    fn open_serial_ports(&mut self, oacsp: &OacspSerialSettings, binary: &BinarySerialSettings) {
+        // TODO: break coupleness among serial ports and fixed baud-rate
+        for port in &oacsp.ports {
+            self.serial_ports.push(SerialPort::new(port, "OACSP", open_arduino, 
+                |dev, domains| Box::new(Oacsp::new(dev, domains))));
+        }
+        for port in &binary.ports {
+            self.serial_ports.push(SerialPort::new(port, "binary", open_arduino, 
+                |dev, domains| Box::new(Binary::new(dev, domains))));
+        }
+        self.supervise_serial_ports();
+        if oacsp.discover {
+            info!("discovering OACSP clients on serial ports");
+            let excluded = oacsp.ports.iter().chain(binary.ports.iter()).cloned().collect();
+            self.serial_discovery = Some(OacspDiscovery::new(
+                oacsp, excluded, available_serial_ports, open_arduino));
+        }
+    }
+    
+    fn open_tcp_listeners(&mut self, settings: &OacspTcpSettings) {
+        for addr in &settings.listen {
+            match TcpListener::bind(addr) {
+                Ok(listener) => {
+                    info!("listening for OACSP clients on {}", listener.name());
+                    self.add_endpoint(listener, |dev, domains| Box::new(Oacsp::new(dev, domains)));
+                }
+                Err(e) => {
+                    error!("cannot listen for OACSP clients on {}: {:?}", addr, e);
+                }
+            }
+        }
+    }
+    
+    fn open_ipc_listeners(&mut self, settings: &OacspIpcSettings) {
+        for path in &settings.listen {
+            match IpcListener::bind(path) {
+                Ok(listener) => {
+                    info!("listening for OACSP clients on {}", listener.name());
+                    self.add_endpoint(listener, |dev, domains| Box::new(Oacsp::new(dev, domains)));
+                }
+                Err(e) => {
+                    error!("cannot listen for OACSP clients on {}: {:?}", path, e);
+                }
+            }
+        }
+    }
+    
+    fn open_udp_endpoints(&mut self, settings: &OacspUdpSettings) {
+        for addr in &settings.listen {
+            match OacspUdp::bind(addr, self.domains.clone(), settings.session_timeout) {
+                Ok(endpoint) => {
+                    info!("listening for OACSP datagrams on {}", endpoint.name());
+                    self.udp_endpoints.push(endpoint);
+                }
+                Err(e) => {
+                    error!("cannot listen for OACSP datagrams on {}: {:?}", addr, e);
+                }
+            }
+        }
+    }
+    
+    fn open_mqtt_listeners(&mut self, settings: &MqttSettings) {
+        let broker = Broker::new();
+        for addr in &settings.listen {
+            match TcpListener::bind(addr) {
+                Ok(listener) => {
+                    info!("listening for MQTT clients on {}", listener.name());
+                    let broker = broker.clone();
+                    self.add_endpoint(listener, move |dev, domains| {
+                        Box::new(Mqtt::new(dev, domains, broker.clone()))
+                    });
+                }
+                Err(e) => {
+                    error!("cannot listen for MQTT clients on {}: {:?}", addr, e);
+                }
+            }
+        }
+    }
+    
+    fn open_mqtt_bridge(&mut self, settings: &MqttBridgeSettings) {
+        match MqttBridge::new(settings) {
+            Ok(bridge) => {
+                info!("bridging variables to MQTT broker {}", bridge.broker());
+                self.mqtt_bridge = Some(bridge);
+            }
+            Err(e) => {
+                error!("cannot configure MQTT bridge to {}: {:?}", settings.broker, e);
+            }
+        }
+    }
+    
+    fn open_websocket_listeners(&mut self, settings: &WebSocketSettings) {
+        for addr in &settings.listen {
+            match TcpListener::bind(addr) {
+                Ok(listener) => {
+                    info!("listening for WebSocket clients on {}", listener.name());
+                    self.add_endpoint(listener, |dev, domains| {
+                        Box::new(WebSocket::new(dev, domains))
+                    });
+                }
+                Err(e) => {
+                    error!("cannot listen for WebSocket clients on {}: {:?}", addr, e);
+                }
+            }
+        }
+    }
+    
+    fn open_http_listeners(&mut self, settings: &HttpSettings) {
+        for addr in &settings.listen {
+            match TcpListener::bind(addr) {
+                Ok(listener) => {
+                    info!("listening for HTTP clients on {}", listener.name());
+                    self.add_endpoint(listener, |dev, domains| Box::new(Rest::new(dev, domains)));
                 }
                 Err(e) => {
-                    error!("cannot configure serial port {:?}: {:?}", port, e);
+                    error!("cannot listen for HTTP clients on {}: {:?}", addr, e);
                 }
             }
         }
     }
     
-    fn open_serial_port(&mut self, port: &OsStr) -> io::Result<()> {
-        let baudrate = 9600;
-        debug!("opening serial port {:?} at {} bauds", port, baudrate);
-        let mut serial = try!(Serial::open_arduino(port.to_str().unwrap(), baudrate));
-        debug!("setting read-upon-available timeouts for serial port {:?}", port);
-        try!(serial.set_timeouts(&SerialTimeouts::ReadUponAvailable));
-        debug!("initializing OACSP protocol for serial port {:?}", port);
-        let proto = Oacsp::new(Device::from(serial), self.domains.clone());
-        debug!("attaching serial port {:?} to IOCP port", port);
-		try!(self.iocp.attach(Box::new(proto)));
-		Ok(())
-    } 
+    fn open_jsonrpc_listeners(&mut self, settings: &JsonRpcSettings) {
+        for addr in &settings.listen {
+            match TcpListener::bind(addr) {
+                Ok(listener) => {
+                    info!("listening for JSON-RPC clients on {}", listener.name());
+                    self.add_endpoint(listener, |dev, domains| {
+                        Box::new(JsonRpc::new(dev, domains))
+                    });
+                }
+                Err(e) => {
+                    error!("cannot listen for JSON-RPC clients on {}: {:?}", addr, e);
+                }
+            }
+        }
+    }
+    
+    fn add_endpoint<L, F>(&mut self, listener: L, protocol: F) 
+    where L: Listener + 'static, F: Fn(Device, DomainDispatcher) -> Box<Protocol> + 'static {
+        self.endpoints.push(Endpoint { 
+            listener: Box::new(listener), 
+            protocol: Box::new(protocol),
+        });
+    }
     
     fn run(&mut self) {
         self.stop = false;
         while !self.stop {
+            self.accept_connections();
+            self.supervise_serial_ports();
+            self.process_datagrams();
+            self.process_mqtt_bridge();
             self.process_io_event();
             self.process_domain_events();
             self.process_commands();
@@ -95,9 +373,57 @@ impl FlightVars {
         }
     }
     
+    fn accept_connections(&mut self) {
+        for endpoint in self.endpoints.iter_mut() {
+            loop {
+                match endpoint.listener.accept() {
+                    Ok(Some(dev)) => {
+                        info!("accepted connection from {} on {}", 
+                            dev.name(), endpoint.listener.name());
+                        let proto = (endpoint.protocol)(dev, self.domains.clone());
+                        if let Err(e) = self.reactor.attach(proto) {
+                            error!("cannot attach connection to IO reactor: {:?}", e);
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        error!("cannot accept connection on {}: {:?}", 
+                            endpoint.listener.name(), e);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    
+    fn supervise_serial_ports(&mut self) {
+        for port in self.serial_ports.iter_mut() {
+            port.poll(&mut self.reactor, &self.domains);
+        }
+        if let Some(ref mut discovery) = self.serial_discovery {
+            discovery.poll(&mut self.reactor, &self.domains);
+        }
+    }
+    
+    fn process_datagrams(&mut self) {
+        let now = Instant::now();
+        for endpoint in self.udp_endpoints.iter_mut() {
+            if let Err(e) = endpoint.process_datagrams(now) {
+                error!("cannot receive datagrams on {}: {:?}", endpoint.name(), e);
+            }
+            endpoint.expire_sessions(now);
+        }
+    }
+    
+    fn process_mqtt_bridge(&mut self) {
+        if let Some(ref mut bridge) = self.mqtt_bridge {
+            bridge.poll(&mut self.reactor, &mut self.domains);
+        }
+    }
+    
     fn process_io_event(&mut self) {
-        match self.iocp.process_event(&Duration::from_millis(50)) {
-            Err(ref e) if self.iocp.is_timeout_error(e) => {},
+        match self.reactor.process_event(&Duration::from_millis(50)) {
+            Err(ref e) if self.reactor.is_timeout_error(e) => {},
             Err(e) => {
                 error!("unexpected error ocurred while processing IO event: {:?}", e);
             }
@@ -117,14 +443,26 @@ impl FlightVars {
     
     fn send_domain_event(&mut self, ev: domain::Event) {
         debug!("processing event {:?}", ev);
-        match self.iocp.handler(&ev.device) {
+        match self.reactor.handler(&ev.device) {
             Some(handler) => {
                 if let Err(e) = handler.send_update(&ev.domain, ev.variable, ev.value) {
                     error!("cannot send domain event to device {}: {:?}", ev.device, e);
                 }
             }
             None => {
-                error!("cannot find a handler for device {} while sending domain event", ev.device);
+                let udp = self.udp_endpoints.iter_mut().find(|ep| ep.has_session(ev.device));
+                match udp {
+                    Some(endpoint) => {
+                        if let Err(e) = endpoint.send_update(
+                                ev.device, &ev.domain, ev.variable, ev.value) {
+                            error!("cannot send domain event to UDP peer {}: {:?}", ev.device, e);
+                        }
+                    }
+                    None => {
+                        error!("cannot find a handler for device {} while sending domain event", 
+                            ev.device);
+                    }
+                }
             }
         }
     }
@@ -145,4 +483,235 @@ impl FlightVarsHandler {
         self.cmd_channel.send(FlightVarsCommand::Close).unwrap();
         self.join_handle.join().unwrap();
     }
+
+    /// Blocks the calling thread until FlightVars terminates.
+    pub fn wait(self) {
+        self.join_handle.join().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::boxed::Box;
+    use std::cell::{Cell, RefCell};
+    use std::ffi::OsStr;
+    use std::io;
+    use std::io::{Read, Write};
+    use std::net;
+    use std::rc::Rc;
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    use config::DomainSettings;
+    use domain::*;
+    use proto::*;
+
+    use super::*;
+
+    #[test]
+    fn should_send_offset_events_to_oacsp_client() {
+        let offsets = fsuipc::MemoryBackend::new();
+        offsets.poke(0x0330, &[0x2a, 0x00]);
+        let mut fv = flightvars(offsets.clone());
+        let mut client = connect_oacsp(&mut fv);
+        client.send(b"BEGIN 2 arduino\nOBS_OFFSET 0330+2\n").unwrap();
+        assert_eq!(run_until_received(&mut fv, &mut client, 1), "EVENT_OFFSET 330+2 42\n");
+        offsets.poke(0x0330, &[0x2b, 0x00]);
+        assert_eq!(run_until_received(&mut fv, &mut client, 1), "EVENT_OFFSET 330+2 43\n");
+    }
+
+    #[test]
+    fn should_write_offsets_from_oacsp_client() {
+        let offsets = fsuipc::MemoryBackend::new();
+        let mut fv = flightvars(offsets.clone());
+        let mut client = connect_oacsp(&mut fv);
+        client.send(b"BEGIN 2 arduino\nWRITE_OFFSET 0330+1 42\n").unwrap();
+        for _ in 0..10 {
+            fv.process_io_event();
+            fv.process_domain_events();
+        }
+        assert_eq!(offsets.peek(0x0330, 1), vec![42]);
+    }
+
+    #[test]
+    fn should_serve_oacsp_to_tcp_clients() {
+        let offsets = fsuipc::MemoryBackend::new();
+        offsets.poke(0x0330, &[0x2a, 0x00]);
+        let mut fv = flightvars(offsets.clone());
+        let addr = listen_oacsp_tcp(&mut fv);
+        let mut client1 = connect_tcp(&mut fv, &addr);
+        let mut client2 = connect_tcp(&mut fv, &addr);
+        client1.write_all(b"BEGIN 2 client1\nOBS_OFFSET 0330+2\n").unwrap();
+        assert_eq!(run_until_read(&mut fv, &mut client1, 1), "EVENT_OFFSET 330+2 42\n");
+        client2.write_all(b"BEGIN 2 client2\nWRITE_OFFSET 0330+1 43\n").unwrap();
+        assert_eq!(run_until_read(&mut fv, &mut client1, 1), "EVENT_OFFSET 330+2 43\n");
+    }
+
+    #[test]
+    fn should_detach_disconnected_tcp_clients() {
+        let mut fv = flightvars(fsuipc::MemoryBackend::new());
+        let mut listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let dev = listener.accept().unwrap().unwrap();
+        let proto = Oacsp::new(dev, fv.domains.clone());
+        let id = fv.reactor.attach(Box::new(proto) as Box<Protocol>).unwrap();
+        drop(client);
+        for _ in 0..10 {
+            fv.process_io_event();
+        }
+        assert!(fv.reactor.handler(&id).is_none());
+    }
+
+    #[test]
+    fn should_release_subscriptions_of_disconnected_clients() {
+        let offsets = fsuipc::MemoryBackend::new();
+        offsets.poke(0x0330, &[0x2a, 0x00]);
+        let mut fv = flightvars(offsets.clone());
+        let mut client = connect_oacsp(&mut fv);
+        client.send(b"BEGIN 2 arduino\nOBS_OFFSET 0330+2\n").unwrap();
+        assert_eq!(run_until_received(&mut fv, &mut client, 1), "EVENT_OFFSET 330+2 42\n");
+        drop(client);
+        for _ in 0..10 {
+            fv.process_io_event();
+        }
+        offsets.poke(0x0330, &[0x2b, 0x00]);
+        let mut events = Vec::new();
+        fv.domains.with_all_domains(|domain| domain.poll(&mut events)).unwrap();
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn should_send_offset_events_to_udp_peers() {
+        let offsets = fsuipc::MemoryBackend::new();
+        offsets.poke(0x0330, &[0x2a, 0x00]);
+        let mut fv = flightvars(offsets.clone());
+        let udp = OacspUdp::bind("127.0.0.1:0", fv.domains.clone(), Duration::from_secs(60));
+        let udp = udp.unwrap();
+        let addr = udp.local_addr().unwrap();
+        fv.udp_endpoints.push(udp);
+        let peer = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        peer.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        peer.send_to(b"BEGIN 2 panel\nOBS_OFFSET 0330+2\n", addr).unwrap();
+        let mut buf = [0; 1024];
+        for _ in 0..100 {
+            fv.process_datagrams();
+            fv.process_domain_events();
+            if let Ok((nbytes, _)) = peer.recv_from(&mut buf) {
+                assert_eq!(&buf[..nbytes], b"EVENT_OFFSET 330+2 42\n");
+                return;
+            }
+        }
+        panic!("no event received from UDP endpoint");
+    }
+
+    #[test]
+    fn should_attach_serial_ports_again_when_they_come_back() {
+        let offsets = fsuipc::MemoryBackend::new();
+        offsets.poke(0x0330, &[0x2a, 0x00]);
+        let mut fv = flightvars(offsets.clone());
+        let plugged = Rc::new(Cell::new(false));
+        let clients = Rc::new(RefCell::new(Vec::new()));
+        let port = {
+            let plugged = plugged.clone();
+            let clients = clients.clone();
+            SerialPort::new(OsStr::new("COM1"), "OACSP", move |_| {
+                if !plugged.get() {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, "port is not plugged"));
+                }
+                let (dev, client) = try!(loopback("serial"));
+                clients.borrow_mut().push(client);
+                Ok(dev)
+            }, |dev, domains| Box::new(Oacsp::new(dev, domains)))
+        };
+        fv.serial_ports.push(port);
+        fv.supervise_serial_ports();
+        assert!(!fv.serial_ports[0].is_up());
+
+        // The port is not opened again until the backoff expires
+        plugged.set(true);
+        fv.supervise_serial_ports();
+        assert!(!fv.serial_ports[0].is_up());
+        fv.serial_ports[0].next_attempt = Instant::now();
+        fv.supervise_serial_ports();
+        assert!(fv.serial_ports[0].is_up());
+
+        // The port is attached again right after it goes down
+        clients.borrow_mut().clear();
+        for _ in 0..10 {
+            fv.process_io_event();
+        }
+        assert!(!fv.serial_ports[0].is_up());
+        fv.supervise_serial_ports();
+        assert!(fv.serial_ports[0].is_up());
+        let mut client = clients.borrow_mut().pop().unwrap();
+        client.send(b"BEGIN 2 arduino\nOBS_OFFSET 0330+2\n").unwrap();
+        assert_eq!(run_until_received(&mut fv, &mut client, 1), "EVENT_OFFSET 330+2 42\n");
+    }
+
+    fn flightvars(offsets: fsuipc::MemoryBackend) -> FlightVars {
+        let mut domains = DomainDispatcher::new(&DomainSettings { enabled: vec![] }).unwrap();
+        domains.add("fsuipc", fsuipc::Fsuipc::new(offsets));
+        let (_, rx) = mpsc::channel();
+        FlightVars {
+            cmd_channel: rx,
+            domains: domains,
+            reactor: Reactor::new().unwrap(),
+            endpoints: Vec::new(),
+            serial_ports: Vec::new(),
+            serial_discovery: None,
+            udp_endpoints: Vec::new(),
+            mqtt_bridge: None,
+            stop: false,
+        }
+    }
+
+    fn connect_oacsp(fv: &mut FlightVars) -> Loopback {
+        let (dev, client) = loopback("oacsp").unwrap();
+        let proto = Oacsp::new(dev, fv.domains.clone());
+        fv.reactor.attach(Box::new(proto) as Box<Protocol>).unwrap();
+        client
+    }
+
+    fn listen_oacsp_tcp(fv: &mut FlightVars) -> net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        fv.add_endpoint(listener, |dev, domains| Box::new(Oacsp::new(dev, domains)));
+        addr
+    }
+
+    fn connect_tcp(fv: &mut FlightVars, addr: &net::SocketAddr) -> net::TcpStream {
+        let client = net::TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        fv.accept_connections();
+        client
+    }
+
+    fn run_until_read(fv: &mut FlightVars, client: &mut net::TcpStream, lines: usize) -> String {
+        let mut received = Vec::new();
+        let mut buf = [0; 1024];
+        for _ in 0..100 {
+            fv.process_io_event();
+            fv.process_domain_events();
+            if let Ok(n) = client.read(&mut buf) {
+                received.extend_from_slice(&buf[..n]);
+            }
+            if received.iter().filter(|b| **b == b'\n').count() >= lines {
+                break;
+            }
+        }
+        String::from_utf8(received).unwrap()
+    }
+
+    fn run_until_received(fv: &mut FlightVars, client: &mut Loopback, lines: usize) -> String {
+        let mut received = Vec::new();
+        for _ in 0..100 {
+            fv.process_io_event();
+            fv.process_domain_events();
+            received.extend(client.recv().unwrap());
+            if received.iter().filter(|b| **b == b'\n').count() >= lines {
+                break;
+            }
+        }
+        String::from_utf8(received).unwrap()
+    }
 }
@@ -24,13 +24,14 @@ impl Buffer {
     
     pub fn as_slice(&self) -> &[u8] { self.data.as_slice() }
     
+    #[cfg(windows)]
     pub fn as_ptr(&mut self) -> *const u8 { self.data.as_ptr() }
 
     pub fn as_mut_ptr(&mut self) -> *mut u8 { self.data.as_mut_ptr() }
     
     pub fn extend(&mut self, inc: usize) {
         let new_len = self.data.len() + inc;
-        assert!(new_len < self.data.capacity());
+        assert!(new_len <= self.data.capacity());
         unsafe { self.data.set_len(new_len) }; 
     }
     
@@ -39,6 +40,16 @@ impl Buffer {
     }
 }
 
+/// The error of a read request on a device whose read buffer is full.
+///
+/// Reading no bytes would be reported as a disconnection, so the handler is told
+/// instead that its peer sent more data than it can process.
+pub fn buffer_full_error(device: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("read buffer of device {} is full", device))
+}
+
 impl io::Write for Buffer {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         self.data.write(buf)
@@ -69,6 +80,14 @@ mod test {
         assert_eq!(buf.len(), 6);
     }
     
+    #[test]
+    fn should_extend_up_to_capacity() {
+        let mut buf = Buffer::with_capacity(16);
+        buf.extend(16);
+        assert_eq!(buf.len(), 16);
+        assert_eq!(buf.remaining(), 0);
+    }
+    
     #[test]
     fn should_consume() {
         let mut buf = Buffer::with_capacity(16);
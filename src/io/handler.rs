@@ -0,0 +1,46 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::boxed::Box;
+use std::io;
+use std::ops::DerefMut;
+
+use super::Device;
+
+/// An event delivered by the reactor to a device handler.
+///
+/// This is the contract shared by all the reactor backends. Regardless of whether
+/// the platform notifies completions (IOCP) or readiness (epoll), the handler receives
+/// the same events with the same meaning.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Event {
+    /// The device was attached to the reactor and is ready to request IO operations.
+    Ready,
+    /// A read request was completed with the given number of bytes.
+    BytesRead(usize),
+    /// A write request was completed with the given number of bytes.
+    BytesWritten(usize),
+    /// The device was closed and the handler is about to be detached from the reactor.
+    /// It is the last event delivered to the handler, which cannot request IO anymore.
+    Closed,
+}
+
+pub trait DeviceHandler {    
+    fn device(&mut self) -> &mut Device;    
+    fn process_event(&mut self, event: Event) -> io::Result<()>;    
+}
+
+impl<H: DeviceHandler + ?Sized> DeviceHandler for Box<H> {
+    fn device(&mut self) -> &mut Device {
+        self.deref_mut().device()
+    }   
+     
+    fn process_event(&mut self, event: Event) -> io::Result<()> {
+        self.deref_mut().process_event(event)
+    }    
+}
@@ -0,0 +1,23 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io;
+
+use super::Device;
+
+/// A source of devices for the connections requested by remote clients.
+///
+/// Listeners are not attached to the reactor. They are polled for new connections
+/// instead, so they must never block waiting for clients. 
+pub trait Listener {
+    /// A descriptive name of the listener (e.g., its address), used in log messages.
+    fn name(&self) -> &str;
+
+    /// Accepts a pending connection, returning `None` if there is no one.
+    fn accept(&mut self) -> io::Result<Option<Device>>;
+}
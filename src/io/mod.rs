@@ -6,14 +6,29 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-#[macro_use]
-mod ffi;
+//! Asynchronous IO for FlightVars devices.
+//!
+//! The reactor backend is selected at compile time. Windows uses IO completion ports
+//! while Unix systems use epoll. Both backends export a `Reactor` type and a `Device`
+//! type that behave the same from the point of view of a `DeviceHandler`.
 
 mod buffer;
-mod device;
-mod iocp;
-mod serial;
+mod handler;
+mod listener;
+mod tcp;
 
-pub use self::device::*;
-pub use self::iocp::*;
-pub use self::serial::*;
\ No newline at end of file
+#[cfg(windows)]
+mod windows;
+
+#[cfg(unix)]
+mod unix;
+
+pub use self::handler::*;
+pub use self::listener::*;
+pub use self::tcp::*;
+
+#[cfg(windows)]
+pub use self::windows::*;
+
+#[cfg(unix)]
+pub use self::unix::*;
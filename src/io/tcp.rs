@@ -0,0 +1,109 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io;
+use std::net;
+
+use super::Device;
+use super::listener::Listener;
+
+/// A listener that accepts TCP connections as devices.
+pub struct TcpListener {
+    name: String,
+    listener: net::TcpListener,
+}
+
+impl TcpListener {
+    
+    pub fn bind(addr: &str) -> io::Result<TcpListener> {
+        let listener = try!(net::TcpListener::bind(addr));
+        try!(listener.set_nonblocking(true));
+        let name = format!("tcp://{}", try!(listener.local_addr()));
+        Ok(TcpListener { name: name, listener: listener })
+    }
+    
+    #[allow(dead_code)]
+    pub fn local_addr(&self) -> io::Result<net::SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+impl Listener for TcpListener {
+    
+    fn name(&self) -> &str { &self.name }
+    
+    fn accept(&mut self) -> io::Result<Option<Device>> {
+        match self.listener.accept() {
+            Ok((stream, _)) => {
+                try!(stream.set_nodelay(true));
+                Ok(Some(try!(Device::from_tcp_stream(stream))))
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::io;
+    use std::io::Write;
+    use std::net;
+    use std::time::Duration;
+
+    use io::*;
+
+    use super::*;
+
+    #[test]
+    fn should_accept_nothing_when_no_client() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        assert!(listener.accept().unwrap().is_none());
+    }
+
+    #[test]
+    fn should_accept_client_as_device() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let dev = accept_device(&mut listener);
+        let mut reactor = Reactor::new().unwrap();
+        reactor.attach(Reader { dev: dev }).unwrap();
+        client.write_all(b"Hello\n").unwrap();
+        reactor.process_event(&Duration::from_millis(100)).unwrap();
+    }
+
+    fn accept_device(listener: &mut TcpListener) -> Device {
+        for _ in 0..100 {
+            if let Some(dev) = listener.accept().unwrap() {
+                return dev;
+            }
+            ::std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("no connection accepted from client");
+    }
+
+    struct Reader { dev: Device }
+
+    impl DeviceHandler for Reader {
+
+        fn device(&mut self) -> &mut Device { &mut self.dev }
+
+        fn process_event(&mut self, event: Event) -> io::Result<()> {
+            match event {
+                Event::Ready => self.dev.request_read(),
+                Event::BytesRead(n) => {
+                    assert_eq!(n, 6);
+                    assert_eq!(self.dev.recv_bytes(), b"Hello\n");
+                    self.dev.close()
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::cmp;
+use std::io;
+use std::net;
+use std::os::unix::io::{IntoRawFd, RawFd};
+
+use libc;
+
+use io::buffer::{Buffer, buffer_full_error};
+use io::handler::Event;
+use types::*;
+
+const INVALID_FD: RawFd = -1;
+
+/// A pending write request.
+///
+/// Unlike overlapped IO, a readiness based device has to keep track of how many bytes
+/// of the request were already transferred, since the kernel may accept only a part of
+/// them when the device becomes writable.
+struct WriteRequest {
+    data: Vec<u8>,
+    written: usize,
+}
+
+/// A device backed by a non-blocking Unix file descriptor.
+///
+/// Read and write requests are not issued to the kernel immediately. They are recorded
+/// by the device and performed by the reactor when the file descriptor is ready. The
+/// outcome is reported as the same `Event` values the overlapped IO devices produce.
+pub struct Device {
+    name: String,
+    id: DeviceId,
+    fd: RawFd,
+    read_buffer: Buffer,
+    read_request: Option<usize>,
+    read_bytes: usize,
+    fill_reads: bool,
+    write_requests: Vec<WriteRequest>,
+}
+
+impl Device {
+    
+    pub fn name(&self) -> &str { &self.name }
+    
+    /// The ID of the device, which is kept after it is closed.
+    pub fn id(&self) -> DeviceId { self.id }
+    
+    pub fn fd(&self) -> RawFd { self.fd }
+
+    /// Creates a new device from the given file descriptor.
+    ///
+    /// The file descriptor is expected to be configured in non-blocking mode.
+    pub fn new(name: &str, fd: RawFd) -> Device {
+        Device {
+            name: name.to_string(),
+            id: fd as DeviceId,
+            fd: fd,
+            read_buffer: Buffer::with_capacity(4096),
+            read_request: None,
+            read_bytes: 0,
+            fill_reads: false,
+            write_requests: Vec::with_capacity(32),
+        }
+    }
+    
+    /// Creates a new device from a connected TCP stream.
+    pub fn from_tcp_stream(stream: net::TcpStream) -> io::Result<Device> {
+        let name = format!("tcp://{}", try!(stream.peer_addr()));
+        try!(stream.set_nonblocking(true));
+        Ok(Device::new(&name, stream.into_raw_fd()))
+    }
+    
+    pub fn close(&mut self) -> io::Result<()> {
+        let rc = unsafe { libc::close(self.fd) };
+        if rc != 0 { Err(io::Error::last_os_error()) }
+        else {
+            self.fd = INVALID_FD;
+            Ok(())
+        }
+    }
+    
+    pub fn is_closed(&self) -> bool {
+        self.fd == INVALID_FD
+    }
+
+    /// Sets whether read requests complete only when all the requested bytes are read.
+    ///
+    /// By default, a read request completes as soon as any byte is available.
+    pub fn set_fill_reads(&mut self, fill: bool) {
+        self.fill_reads = fill;
+    }
+    
+    pub fn consume_recv_buffer(&mut self, nbytes: usize) {
+        self.read_buffer.consume(nbytes)
+    }
+    
+    pub fn recv_bytes(&self) -> &[u8] {
+        self.read_buffer.as_slice()
+    }
+    
+    pub fn request_read(&mut self) -> io::Result<()>{
+        let nbytes = self.read_buffer.remaining();
+        self.request_read_bytes(nbytes)
+    }
+    
+    pub fn request_read_bytes(&mut self, nbytes: usize) -> io::Result<()>{
+        assert!(self.read_request.is_none());
+        if self.read_buffer.remaining() == 0 {
+            return Err(buffer_full_error(&self.name));
+        }
+        let total_bytes = cmp::min(nbytes, self.read_buffer.remaining());
+        self.read_request = Some(total_bytes);
+        Ok(())
+    }
+    
+    pub fn request_write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write_requests.push(WriteRequest { data: data.to_vec(), written: 0 });
+        Ok(())
+    }
+
+    /// Returns the epoll events this device is interested in.
+    pub fn interest(&self) -> u32 {
+        let mut events = 0;
+        if self.read_request.is_some() {
+            events |= libc::EPOLLIN as u32;
+        }
+        if !self.write_requests.is_empty() {
+            events |= libc::EPOLLOUT as u32;
+        }
+        events
+    }
+    
+    /// Performs the IO operations the given epoll events allow.
+    ///
+    /// At most one operation is completed per call, so the handler receives the events
+    /// one at a time. Reads are prioritized over writes. The reactor works in level
+    /// triggered mode, so any pending operation will be reported again in the next wait. 
+    pub fn process_event(&mut self, events: u32) -> io::Result<Option<Event>> {
+        let hangup = (libc::EPOLLERR | libc::EPOLLHUP) as u32;
+        if self.read_request.is_some() && events & (libc::EPOLLIN as u32 | hangup) != 0 {
+            self.process_read_event().map(|n| n.map(Event::BytesRead))
+        } else if !self.write_requests.is_empty() && events & (libc::EPOLLOUT as u32 | hangup) != 0 {
+            self.process_write_event().map(|n| n.map(Event::BytesWritten))
+        } else {
+            Ok(None)
+        }
+    }
+    
+    fn process_read_event(&mut self) -> io::Result<Option<usize>> {
+        let nbytes = self.read_request.unwrap();
+        let offset = self.read_buffer.len() as isize;
+        let rc = unsafe {
+            libc::read(
+                self.fd,
+                self.read_buffer.as_mut_ptr().offset(offset) as *mut libc::c_void,
+                nbytes as libc::size_t)
+        };
+        if rc < 0 {
+            return would_block_or_error();
+        }
+        let nread = rc as usize;
+        self.read_buffer.extend(nread);
+        self.read_bytes += nread;
+        if self.fill_reads && nread > 0 && nread < nbytes {
+            self.read_request = Some(nbytes - nread);
+            return Ok(None);
+        }
+        let total = self.read_bytes;
+        self.read_request = None;
+        self.read_bytes = 0;
+        Ok(Some(total))
+    }
+
+    fn process_write_event(&mut self) -> io::Result<Option<usize>> {
+        let done = {
+            let req = &mut self.write_requests[0];
+            let pending = &req.data[req.written..];
+            let rc = unsafe {
+                libc::write(
+                    self.fd,
+                    pending.as_ptr() as *const libc::c_void,
+                    pending.len() as libc::size_t)
+            };
+            if rc < 0 {
+                return would_block_or_error();
+            }
+            req.written += rc as usize;
+            req.written == req.data.len()
+        };
+        if done {
+            let req = self.write_requests.remove(0);
+            Ok(Some(req.written))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        if !self.is_closed() {
+            unsafe { libc::close(self.fd) };
+        }
+    }
+}
+
+fn would_block_or_error<T>() -> io::Result<Option<T>> {
+    let error = io::Error::last_os_error();
+    match error.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => Ok(None),
+        _ => Err(error),
+    }
+}
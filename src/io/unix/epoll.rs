@@ -0,0 +1,439 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+use libc;
+
+use io::handler::*;
+use types::*;
+
+/// A readiness based reactor built on top of Linux epoll.
+///
+/// It offers the same interface as the IOCP based `CompletionPort`. The devices record
+/// the read and write requests of their handlers, and the reactor performs them once
+/// epoll reports the corresponding file descriptor is ready. 
+pub struct Epoll<H: DeviceHandler> {
+    fd: RawFd,
+    handlers: HashMap<DeviceId, H>,
+    interests: HashMap<DeviceId, u32>,
+}
+
+impl<H: DeviceHandler> Epoll<H> {
+    
+    pub fn new() -> io::Result<Epoll<H>> {
+        let fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Epoll {
+            fd: fd,
+            handlers: HashMap::new(),
+            interests: HashMap::new(),
+        })
+    }
+    
+    pub fn handler(&mut self, dev: &DeviceId) -> Option<&mut H> {
+        self.handlers.get_mut(dev)
+    }
+        
+    /// Attaches the handler of a new device to the reactor.
+    ///
+    /// File descriptors are reused once closed, so a closed device with the same ID
+    /// which was not detached yet is detached first.
+    pub fn attach(&mut self, mut handler: H) -> io::Result<DeviceId> {
+        let fd = handler.device().fd();
+        let id = handler.device().id();
+        match self.handlers.get_mut(&id).map(|attached| attached.device().is_closed()) {
+            Some(true) => self.detach(id),
+            Some(false) => {
+                let error = io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("device {} is already attached", id));
+                return Err(error);
+            }
+            None => {}
+        }
+        try!(self.control(libc::EPOLL_CTL_ADD, fd, id, 0));
+        if let Err(e) = handler.process_event(Event::Ready) {
+            let _ = self.control(libc::EPOLL_CTL_DEL, fd, id, 0);
+            return Err(e);
+        }
+        self.handlers.insert(id, handler);
+        self.interests.insert(id, 0);
+        Ok(id)
+    }
+    
+    pub fn process_event(&mut self, timeout: &Duration) -> io::Result<DeviceId> {
+        try!(self.update_interests());
+        let mut event = libc::epoll_event { events: 0, u64: 0 };
+        let timeout_millis = 
+        	(timeout.as_secs() as libc::c_int * 1000) + 
+        	(timeout.subsec_nanos() as libc::c_int / 1000000);
+        let rc = unsafe { libc::epoll_wait(self.fd, &mut event, 1, timeout_millis) };
+        if rc < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                return Err(timeout_error());
+            }
+            return Err(error);
+        }
+        if rc == 0 {
+            return Err(timeout_error());
+        }
+        let id = event.u64 as DeviceId;
+        let events = event.events;
+        let was_closed = {
+            let handler = match self.handlers.get_mut(&id) {
+                Some(handler) => handler,
+                None => {
+                    debug!("ignoring IO event from detached device {}", id);
+                    return Ok(id);
+                }
+            };
+            let result = match handler.device().process_event(events) {
+                Ok(Some(event)) => handler.process_event(event),
+                Ok(None) => Ok(()),
+                Err(e) => Err(e),
+            };
+        	if let Err(e) = result {
+        	    error!("unexpected error while processing IO event from device {}: {:?}", 
+        	        handler.device().name(), e);
+        	    info!("closing connection to device {} due to IO errors", 
+        	        handler.device().name());
+        	    try!(handler.device().close());
+        	}        
+          	handler.device().is_closed()  
+        };
+        if was_closed {
+            self.detach(id);
+        }
+        Ok(id)
+    }
+    
+    pub fn is_timeout_error(&self, error: &io::Error) -> bool {
+        error.kind() == io::ErrorKind::TimedOut
+    }
+
+    /// Updates the epoll interest list with the requests issued by the handlers.
+    ///
+    /// Handlers might issue requests out of the reactor loop (e.g., when a domain event
+    /// is sent to them), so the interests are refreshed before waiting for events.
+    fn update_interests(&mut self) -> io::Result<()> {
+        let mut closed = Vec::new();
+        for (id, handler) in self.handlers.iter_mut() {
+            let dev = handler.device();
+            if dev.is_closed() {
+                closed.push(*id);
+                continue;
+            }
+            let interest = dev.interest();
+            let current = self.interests.get_mut(id).unwrap();
+            if *current != interest {
+                try!(control(self.fd, libc::EPOLL_CTL_MOD, dev.fd(), *id, interest));
+                *current = interest;
+            }
+        }
+        for id in closed {
+            self.detach(id);
+        }
+        Ok(())
+    }
+
+    /// Removes the handler of a closed device, notifying it with a `Closed` event.
+    fn detach(&mut self, id: DeviceId) {
+        self.interests.remove(&id);
+        if let Some(mut handler) = self.handlers.remove(&id) {
+            if let Err(e) = handler.process_event(Event::Closed) {
+                error!("unexpected error while closing device {}: {:?}", 
+                    handler.device().name(), e);
+            }
+        }
+    }
+
+    fn control(&self, op: libc::c_int, fd: RawFd, id: DeviceId, events: u32) -> io::Result<()> {
+        control(self.fd, op, fd, id, events)
+    }
+}
+
+fn control(epfd: RawFd, op: libc::c_int, fd: RawFd, id: DeviceId, events: u32) -> io::Result<()> {
+    let mut event = libc::epoll_event { events: events, u64: id as u64 };
+    let rc = unsafe { libc::epoll_ctl(epfd, op, fd, &mut event) };
+    if rc < 0 { Err(io::Error::last_os_error()) } else { Ok(()) }
+}
+
+fn timeout_error() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "no IO event received before timeout")
+}
+
+#[cfg(test)]
+mod test {
+    
+    use std::cell::Cell;
+    use std::io;
+	use std::io::{Read, Write};
+	use std::os::unix::io::IntoRawFd;
+	use std::os::unix::net::UnixStream;
+	use std::rc::Rc;
+	use std::time::Duration;
+
+	use io::*;
+	use types::*;
+
+    use super::*;
+	
+	#[test]
+	fn should_read_device() {
+	    let (dev, mut peer) = device_pair();
+	    let mut epoll = Epoll::new().unwrap();
+	    epoll.attach(Reader::new(dev)).unwrap();
+	    peer.write_all(b"This is a stream with some content").unwrap();
+	    epoll.process_event(&Duration::from_millis(100)).unwrap();
+	}
+	
+	#[test]
+	fn should_write_device() {
+	    let (dev, mut peer) = device_pair();
+	    let mut epoll = Epoll::new().unwrap();
+	    epoll.attach(Writer::new(dev)).unwrap();
+	    epoll.process_event(&Duration::from_millis(100)).unwrap();
+	    assert_stream_contains(&mut peer, "This is a stream with some content");
+	}
+	
+	#[test]
+	fn should_write_device_concurrently() {
+	    let (dev, mut peer) = device_pair();
+	    let mut epoll = Epoll::new().unwrap();
+	    epoll.attach(ParallelWriter::new(dev)).unwrap();
+	    epoll.process_event(&Duration::from_millis(100)).unwrap();
+	    epoll.process_event(&Duration::from_millis(100)).unwrap();
+	    // Unlike files, streams have no offsets, so the writes are sent one after another. 
+	    assert_stream_contains(&mut peer, "This is a stream with some contentThis is Sparta");
+	}
+	
+	#[test]
+	fn should_read_device_until_filled() {
+	    let (mut dev, mut peer) = device_pair();
+	    dev.set_fill_reads(true);
+	    let mut epoll = Epoll::new().unwrap();
+	    epoll.attach(FillReader::new(dev)).unwrap();
+	    peer.write_all(b"This is a stream").unwrap();
+	    // The partial read is buffered without notifying the handler
+	    epoll.process_event(&Duration::from_millis(100)).unwrap();
+	    let error = epoll.process_event(&Duration::from_millis(10)).unwrap_err();
+	    assert!(epoll.is_timeout_error(&error));
+	    peer.write_all(b" with some content").unwrap();
+	    epoll.process_event(&Duration::from_millis(100)).unwrap();
+	}
+	
+	#[test]
+	fn should_notify_handler_when_device_is_closed() {
+	    let (dev, mut peer) = device_pair();
+	    let closed = Rc::new(Cell::new(None));
+	    let mut epoll = Epoll::new().unwrap();
+	    let id = epoll.attach(Closer { dev: dev, closed: closed.clone() }).unwrap();
+	    peer.write_all(b"Bye").unwrap();
+	    epoll.process_event(&Duration::from_millis(100)).unwrap();
+	    assert_eq!(closed.get(), Some(id));
+	    assert!(epoll.handler(&id).is_none());
+	}
+	
+	#[test]
+	fn should_close_device_when_read_buffer_is_full() {
+	    let (dev, mut peer) = device_pair();
+	    let read = Rc::new(Cell::new(0));
+	    let mut epoll = Epoll::new().unwrap();
+	    let id = epoll.attach(GreedyReader { dev: dev, read: read.clone() }).unwrap();
+	    peer.write_all(&[0x2a; 5000]).unwrap();
+	    epoll.process_event(&Duration::from_millis(100)).unwrap();
+	    assert_eq!(read.get(), 4096);
+	    assert!(epoll.handler(&id).is_none());
+	}
+	
+	#[test]
+	fn should_close_device_when_dropped() {
+	    let (dev, mut peer) = device_pair();
+	    drop(dev);
+	    let mut buf = [0; 16];
+	    assert_eq!(peer.read(&mut buf).unwrap(), 0);
+	}
+	
+	#[test]
+	fn should_timeout_when_no_event() {
+	    let (dev, _peer) = device_pair();
+	    let mut epoll = Epoll::new().unwrap();
+	    epoll.attach(Reader::new(dev)).unwrap();
+	    let error = epoll.process_event(&Duration::from_millis(10)).unwrap_err();
+	    assert!(epoll.is_timeout_error(&error));
+	}
+	
+	fn device_pair() -> (Device, UnixStream) {
+	    let (local, peer) = UnixStream::pair().expect("create stream pair");
+	    local.set_nonblocking(true).unwrap();
+	    peer.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+	    (Device::new("test", local.into_raw_fd()), peer)
+	}
+	
+	fn assert_stream_contains(stream: &mut UnixStream, content: &str) {
+	    let mut buf = vec![0; content.len()];
+	    stream.read_exact(&mut buf).expect("read stream");
+	    assert_eq!(&buf[..], content.as_bytes());
+	}
+	
+	struct Reader { dev: Device }
+	
+	impl Reader {
+	    fn new(dev: Device) -> Reader {
+	        Reader { dev: dev }
+	    }
+	}
+	
+	impl DeviceHandler for Reader {
+	    
+	    fn device(&mut self) -> &mut Device { &mut self.dev }
+	        
+    	fn process_event(&mut self, event: Event) -> io::Result<()> {
+    	    match event {
+    	        Event::Ready => self.dev.request_read(), 
+    	        Event::BytesRead(n) => {
+    	            assert_eq!(n, 34);
+    	            assert_eq!(self.dev.recv_bytes(), b"This is a stream with some content");
+    	            self.dev.close()
+    	        }
+    	        _ => Ok(()),
+    	    }
+    	}
+	}
+	
+	
+	struct FillReader { dev: Device }
+	
+	impl FillReader {
+	    fn new(dev: Device) -> FillReader {
+	        FillReader { dev: dev }
+	    }
+	}
+	
+	impl DeviceHandler for FillReader {
+	    
+	    fn device(&mut self) -> &mut Device { &mut self.dev }
+	        
+    	fn process_event(&mut self, event: Event) -> io::Result<()> {
+    	    match event {
+    	        Event::Ready => self.dev.request_read_bytes(34), 
+    	        Event::BytesRead(n) => {
+    	            assert_eq!(n, 34);
+    	            assert_eq!(self.dev.recv_bytes(), b"This is a stream with some content");
+    	            self.dev.close()
+    	        }
+    	        _ => Ok(()),
+    	    }
+    	}
+	}
+	
+	
+	struct Writer { dev: Device }
+	
+	impl Writer {
+	    fn new(dev: Device) -> Writer {
+	        Writer { dev: dev }
+	    }
+	}
+	
+	impl DeviceHandler for Writer {
+	    
+	    fn device(&mut self) -> &mut Device { &mut self.dev }
+	        
+    	fn process_event(&mut self, event: Event) -> io::Result<()> {
+    	    match event {
+    	        Event::Ready => self.dev.request_write(b"This is a stream with some content"), 
+    	        Event::BytesWritten(n) => {
+    	            assert_eq!(n, 34);
+    	            self.dev.close()
+    	        }
+    	        _ => Ok(()),
+    	    }
+    	}
+	}
+	
+	
+	/// A reader that never consumes the bytes it reads.
+	struct GreedyReader { dev: Device, read: Rc<Cell<usize>> }
+	
+	impl DeviceHandler for GreedyReader {
+	    
+	    fn device(&mut self) -> &mut Device { &mut self.dev }
+	        
+    	fn process_event(&mut self, event: Event) -> io::Result<()> {
+    	    match event {
+    	        Event::BytesRead(n) => {
+    	            self.read.set(self.read.get() + n);
+    	            self.dev.request_read()
+    	        }
+    	        Event::Ready => self.dev.request_read(), 
+    	        _ => Ok(()),
+    	    }
+    	}
+	}
+	
+	
+	struct Closer { dev: Device, closed: Rc<Cell<Option<DeviceId>>> }
+	
+	impl DeviceHandler for Closer {
+	    
+	    fn device(&mut self) -> &mut Device { &mut self.dev }
+	        
+    	fn process_event(&mut self, event: Event) -> io::Result<()> {
+    	    match event {
+    	        Event::Ready => self.dev.request_read(), 
+    	        Event::BytesRead(_) => self.dev.close(),
+    	        Event::Closed => {
+    	            self.closed.set(Some(self.dev.id()));
+    	            Ok(())
+    	        }
+    	        _ => Ok(()),
+    	    }
+    	}
+	}
+	
+	
+	struct ParallelWriter { dev: Device, written: usize }
+	
+	impl ParallelWriter {
+	    fn new(dev: Device) -> ParallelWriter {
+	        ParallelWriter { dev: dev, written: 0 }
+	    }
+	}
+	
+	impl DeviceHandler for ParallelWriter {
+	    
+	    fn device(&mut self) -> &mut Device { &mut self.dev }
+	        
+    	fn process_event(&mut self, event: Event) -> io::Result<()> {
+    	    match event {
+    	        Event::Ready => { 
+    	            try!(self.dev.request_write(b"This is a stream with some content")); 
+    	            self.dev.request_write(b"This is Sparta")
+    	        }
+    	        Event::BytesWritten(n) if self.written == 0 => {
+    	            assert_eq!(n, 34);
+    	            self.written += n;
+    	            Ok(())
+    	        }
+    	        Event::BytesWritten(n) if self.written > 0 => {
+    	            assert_eq!(n, 14);
+    	            self.dev.close()
+    	        }
+    	        _ => Ok(()),
+    	    }
+    	}
+	}
+}
@@ -0,0 +1,160 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::IntoRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+
+use io::listener::Listener;
+
+use super::device::*;
+
+/// A listener that accepts local clients connected to a Unix domain socket. 
+pub struct IpcListener {
+    name: String,
+    path: PathBuf,
+    listener: UnixListener,
+}
+
+impl IpcListener {
+    
+    /// Binds a new listener to the Unix domain socket at the given path.
+    ///
+    /// A socket left behind at that path by a previous execution is removed. If some
+    /// other process is still listening on it, it fails with `AddrInUse` instead.
+    pub fn bind(path: &str) -> io::Result<IpcListener> {
+        if let Ok(meta) = fs::metadata(path) {
+            if meta.file_type().is_socket() {
+                match UnixStream::connect(path) {
+                    Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                        debug!("removing stale Unix socket at {}", path);
+                        try!(fs::remove_file(path));
+                    }
+                    Err(e) => return Err(e),
+                    Ok(_) => {
+                        let error = io::Error::new(
+                            io::ErrorKind::AddrInUse,
+                            format!("Unix socket {} is in use by another process", path));
+                        return Err(error);
+                    }
+                }
+            }
+        }
+        let listener = try!(UnixListener::bind(path));
+        try!(listener.set_nonblocking(true));
+        Ok(IpcListener {
+            name: format!("unix://{}", path),
+            path: PathBuf::from(path),
+            listener: listener,
+        })
+    }
+}
+
+impl Listener for IpcListener {
+
+    fn name(&self) -> &str { &self.name }
+
+    fn accept(&mut self) -> io::Result<Option<Device>> {
+        match self.listener.accept() {
+            Ok((stream, _)) => {
+                try!(stream.set_nonblocking(true));
+                Ok(Some(Device::new(&self.name, stream.into_raw_fd())))
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Drop for IpcListener {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::io;
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use tempdir::TempDir;
+
+    use io::*;
+
+    use super::*;
+
+    #[test]
+    fn should_accept_nothing_when_no_client() {
+        let dir = TempDir::new("flightvars").unwrap();
+        let path = dir.path().join("ipc.sock");
+        let mut listener = IpcListener::bind(path.to_str().unwrap()).unwrap();
+        assert!(listener.accept().unwrap().is_none());
+    }
+
+    #[test]
+    fn should_accept_clients_as_devices() {
+        let dir = TempDir::new("flightvars").unwrap();
+        let path = dir.path().join("ipc.sock");
+        let mut listener = IpcListener::bind(path.to_str().unwrap()).unwrap();
+        let mut client1 = UnixStream::connect(&path).unwrap();
+        let mut client2 = UnixStream::connect(&path).unwrap();
+        let mut reactor = Reactor::new().unwrap();
+        reactor.attach(Reader { dev: listener.accept().unwrap().unwrap() }).unwrap();
+        reactor.attach(Reader { dev: listener.accept().unwrap().unwrap() }).unwrap();
+        client1.write_all(b"Hello\n").unwrap();
+        client2.write_all(b"Hello\n").unwrap();
+        reactor.process_event(&Duration::from_millis(100)).unwrap();
+        reactor.process_event(&Duration::from_millis(100)).unwrap();
+    }
+
+    #[test]
+    fn should_replace_stale_socket() {
+        let dir = TempDir::new("flightvars").unwrap();
+        let path = dir.path().join("ipc.sock");
+        let stale = ::std::os::unix::net::UnixListener::bind(&path).unwrap();
+        drop(stale);
+        assert!(IpcListener::bind(path.to_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn should_not_replace_socket_in_use() {
+        let dir = TempDir::new("flightvars").unwrap();
+        let path = dir.path().join("ipc.sock");
+        let _live = ::std::os::unix::net::UnixListener::bind(&path).unwrap();
+        match IpcListener::bind(path.to_str().unwrap()) {
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::AddrInUse),
+            Ok(_) => panic!("socket in use replaced"),
+        }
+        assert!(UnixStream::connect(&path).is_ok());
+    }
+
+    struct Reader { dev: Device }
+
+    impl DeviceHandler for Reader {
+
+        fn device(&mut self) -> &mut Device { &mut self.dev }
+
+        fn process_event(&mut self, event: Event) -> io::Result<()> {
+            match event {
+                Event::Ready => self.dev.request_read(),
+                Event::BytesRead(n) => {
+                    assert_eq!(n, 6);
+                    assert_eq!(self.dev.recv_bytes(), b"Hello\n");
+                    self.dev.close()
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+}
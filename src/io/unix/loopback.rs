@@ -0,0 +1,53 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::io::IntoRawFd;
+use std::os::unix::net::UnixStream;
+
+use super::device::*;
+
+/// The remote end of a loopback device.
+///
+/// What is sent through the loopback is received by the device, and what is written
+/// to the device is received by the loopback. 
+pub struct Loopback {
+    stream: UnixStream,
+}
+
+#[allow(dead_code)]
+impl Loopback {
+    pub fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.stream.write_all(data)
+    }
+
+    /// Receives all the bytes available at this moment, without blocking.
+    pub fn recv(&mut self) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut buf = [0; 1024];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Ok(data),
+                Ok(n) => data.extend_from_slice(&buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(data),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Creates a device connected to an in-memory loopback.
+#[allow(dead_code)]
+pub fn loopback(name: &str) -> io::Result<(Device, Loopback)> {
+    let (local, remote) = try!(UnixStream::pair());
+    try!(local.set_nonblocking(true));
+    try!(remote.set_nonblocking(true));
+    let dev = Device::new(&format!("loopback://{}", name), local.into_raw_fd());
+    Ok((dev, Loopback { stream: remote }))
+}
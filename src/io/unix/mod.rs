@@ -0,0 +1,21 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+mod device;
+mod epoll;
+mod ipc;
+#[cfg(test)]
+mod loopback;
+mod serial;
+
+pub use self::device::*;
+pub use self::epoll::Epoll as Reactor;
+pub use self::ipc::*;
+#[cfg(test)]
+pub use self::loopback::*;
+pub use self::serial::*;
@@ -0,0 +1,288 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::thread;
+use std::time::Duration;
+
+use libc;
+
+use super::device::*;
+
+#[allow(dead_code)]
+pub enum SerialTimeouts {
+    /// Read any byte upon available in input buffers
+    ReadUponAvailable,
+    
+    /// Wait for all requested bytes to be available
+    WaitToFill,
+}
+
+impl SerialTimeouts {
+    /// Obtains the termios `VMIN` and `VTIME` values for these timeouts.
+    ///
+    /// `ReadUponAvailable` returns as soon as one byte is available. `WaitToFill` waits
+    /// with no inter-byte timer for as many bytes as termios allows. Since the device
+    /// is used in non-blocking mode, it also completes its reads only when filled. 
+    fn as_raw(&self) -> (libc::cc_t, libc::cc_t) {
+        match *self {
+            SerialTimeouts::ReadUponAvailable => (1, 0),
+            SerialTimeouts::WaitToFill => (255, 0),
+        }
+    }
+}
+
+pub struct Serial {
+    dev: Device
+}
+
+impl Serial {
+    
+    pub fn open(port: &str) -> io::Result<Serial> {
+        let path = try!(CString::new(port).map_err(|_| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid serial port name '{}'", port))));
+        let fd = unsafe {
+            libc::open(
+                path.as_ptr(), 
+                libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK | libc::O_CLOEXEC)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Serial {
+            dev: Device::new(&format!("serial://{}", port), fd)
+        })
+    }
+    
+    /// Lists the serial ports available in the system (e.g., `/dev/ttyUSB0`).
+    ///
+    /// Every `/dev/tty*` device is listed except the controlling terminal and the
+    /// virtual consoles.
+    pub fn available_ports() -> io::Result<Vec<String>> {
+        let mut ports = Vec::new();
+        for entry in try!(fs::read_dir("/dev")) {
+            let name = try!(entry).file_name();
+            if let Some(name) = name.to_str() {
+                if name.starts_with("tty") && !name[3..].chars().all(|c| c.is_digit(10)) {
+                    ports.push(format!("/dev/{}", name));
+                }
+            }
+        }
+        ports.sort();
+        Ok(ports)
+    }
+
+    pub fn open_arduino(port: &str, baud_rate: usize) ->io::Result<Serial> {
+        let speed = try!(baud_rate_speed(baud_rate));
+        let mut port = try!(Serial::open(port));
+        
+        // Set the serial settings for Arduino: raw mode, 8N1, no flow control
+        let mut tio = try!(port.termios());
+        unsafe { libc::cfmakeraw(&mut tio) };
+        tio.c_cflag &= !(libc::CSIZE | libc::PARENB | libc::CSTOPB | libc::CRTSCTS);
+        tio.c_cflag |= libc::CS8 | libc::CLOCAL | libc::CREAD | libc::HUPCL;
+        try!(check(unsafe { libc::cfsetispeed(&mut tio, speed) }));
+        try!(check(unsafe { libc::cfsetospeed(&mut tio, speed) }));
+        try!(port.set_termios(&tio));
+        
+        // Toggle DTR to reset the board, as Windows does when DTR control is enabled
+        try!(port.set_dtr(false));
+        thread::sleep(Duration::from_millis(10));
+        try!(port.set_dtr(true));
+		
+		// Now wait an instant while the board resets to avoid buffer writes before purge
+		thread::sleep(Duration::from_millis(10));
+		
+		// Purge the buffers to eliminate accumulated messages prior to reset
+        try!(check(unsafe { libc::tcflush(port.fd(), libc::TCIOFLUSH) }));
+		Ok(port)
+    }
+    
+    pub fn set_timeouts(&mut self, timeouts: &SerialTimeouts) -> io::Result<()> {
+        let (vmin, vtime) = timeouts.as_raw();
+        let mut tio = try!(self.termios());
+        tio.c_cc[libc::VMIN] = vmin;
+        tio.c_cc[libc::VTIME] = vtime;
+        try!(self.set_termios(&tio));
+        match *timeouts {
+            SerialTimeouts::ReadUponAvailable => self.dev.set_fill_reads(false),
+            SerialTimeouts::WaitToFill => self.dev.set_fill_reads(true),
+        }
+		Ok(())
+    }
+    
+    pub fn termios(&self) -> io::Result<libc::termios> {
+        let mut tio: libc::termios = unsafe { mem::zeroed() };
+        try!(check(unsafe { libc::tcgetattr(self.dev.fd(), &mut tio) }));
+        Ok(tio)
+    }
+    
+    pub fn set_termios(&mut self, tio: &libc::termios) -> io::Result<()> {
+        try!(check(unsafe { libc::tcsetattr(self.dev.fd(), libc::TCSANOW, tio) }));
+        Ok(())
+    }
+
+    pub fn set_dtr(&mut self, enabled: bool) -> io::Result<()> {
+        let bits: libc::c_int = libc::TIOCM_DTR;
+        let request = if enabled { libc::TIOCMBIS } else { libc::TIOCMBIC };
+        try!(check(unsafe { libc::ioctl(self.dev.fd(), request, &bits) }));
+        Ok(())
+    }
+}
+
+impl From<Serial> for Device {
+    fn from(serial: Serial) -> Device {
+        serial.dev
+    }
+}
+
+impl Deref for Serial {
+    type Target = Device;
+    
+    fn deref(&self) -> &Device {
+        &self.dev
+    }
+}
+
+impl DerefMut for Serial {
+    fn deref_mut(&mut self) -> &mut Device {
+        &mut self.dev
+    }
+}
+
+fn baud_rate_speed(baud_rate: usize) -> io::Result<libc::speed_t> {
+    match baud_rate {
+        1200 => Ok(libc::B1200),
+        2400 => Ok(libc::B2400),
+        4800 => Ok(libc::B4800),
+        9600 => Ok(libc::B9600),
+        19200 => Ok(libc::B19200),
+        38400 => Ok(libc::B38400),
+        57600 => Ok(libc::B57600),
+        115200 => Ok(libc::B115200),
+        _ => {
+            let error = io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported baud rate {}", baud_rate));
+            Err(error)
+        }
+    }
+}
+
+fn check(rc: libc::c_int) -> io::Result<()> {
+    if rc < 0 { Err(io::Error::last_os_error()) } else { Ok(()) }
+}
+
+#[cfg(test)]
+mod test {
+
+	use std::io;
+	use std::{thread, time};
+
+	use libc;
+
+	use io::*;
+
+	use super::*;
+	use super::baud_rate_speed;
+	
+	#[test]
+	fn should_map_baud_rates() {
+	    assert_eq!(baud_rate_speed(9600).unwrap(), libc::B9600);
+	    assert_eq!(baud_rate_speed(115200).unwrap(), libc::B115200);
+	    assert_eq!(baud_rate_speed(1234).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+	}
+	
+	#[test]
+	fn should_fail_to_open_missing_port() {
+	    assert!(Serial::open_arduino("/dev/this-port-does-not-exist", 9600).is_err());
+	}
+	
+	#[test]
+	fn should_reject_unsupported_baud_rate_before_opening() {
+	    match Serial::open_arduino("/dev/null", 1234) {
+	        Err(e) => assert_eq!(e.kind(), io::ErrorKind::InvalidInput),
+	        Ok(_) => panic!("unsupported baud rate accepted"),
+	    }
+	}
+	
+	#[test]
+	fn should_list_available_ports_but_terminals() {
+	    let ports = Serial::available_ports().unwrap();
+	    assert!(ports.iter().all(|p| p.starts_with("/dev/tty")));
+	    assert!(!ports.contains(&"/dev/tty".to_string()));
+	    assert!(!ports.contains(&"/dev/tty0".to_string()));
+	}
+	
+	/// A test that uses an Arduino board connected to serial port to interact with the machine.
+    /// Use the same Arduino sketch suggested for the Windows version of this test.
+    ///
+    /// Remove the #[ignore] attribute and set the correct TTY device to execute it.
+	#[test]
+	#[ignore]
+	fn should_read_and_write() {
+	    let mut epoll = Reactor::new().unwrap();	    
+		let mut port = Serial::open_arduino("/dev/ttyACM0", 9600).unwrap();
+		port.set_timeouts(&SerialTimeouts::WaitToFill).unwrap();
+		epoll.attach(EchoHandler::new(Device::from(port))).unwrap();
+		
+		// Wait for Arduino to completely boot
+		thread::sleep(time::Duration::from_millis(2000));
+		
+		loop {
+		    if epoll.process_event(&time::Duration::from_millis(100)).is_err() {
+		        break
+		    }
+		}
+	}
+	
+	struct EchoHandler {
+	    dev: Device,
+	    hello_received: bool,
+	}
+	
+	impl EchoHandler {
+	    fn new(dev: Device) -> EchoHandler {
+	        EchoHandler { dev: dev, hello_received: false }
+	    }
+	}
+	
+	impl DeviceHandler for EchoHandler {
+
+		fn device(&mut self) -> &mut Device { &mut self.dev }
+	    
+	    fn process_event(&mut self, event: Event) -> io::Result<()> {
+	        match event {
+	            Event::Ready => self.dev.request_read_bytes(6),
+	            Event::BytesRead(nbytes) if !self.hello_received => {
+	                assert_eq!(nbytes, 6);
+	                assert_eq!(self.dev.recv_bytes(), b"Hello\n");
+	                self.dev.consume_recv_buffer(6);
+	                self.hello_received = true;
+	                self.dev.request_write(b"FlightVars")
+	            }
+	            Event::BytesWritten(nbytes) => {
+	                assert_eq!(nbytes, 10);
+	                self.dev.request_read_bytes(19)
+	            }	            
+	            Event::BytesRead(nbytes) if self.hello_received => {
+	                assert_eq!(nbytes, 19);
+	                assert_eq!(self.dev.recv_bytes(), b"Goodbye FlightVars\n");
+	                self.dev.close()
+	            }
+	            Event::Closed => Ok(()),
+	            _ => unreachable!(),
+	        }
+	    }
+	}
+}
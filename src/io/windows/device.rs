@@ -10,22 +10,17 @@ use std::boxed::Box;
 use std::cmp;
 use std::io;
 use std::io::Write;
-use std::ops::DerefMut;
+use std::net;
 use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::IntoRawSocket;
 use std::path::Path;
 
+use io::buffer::{Buffer, buffer_full_error};
+use io::handler::Event;
 use types::*;
 
-use super::buffer::Buffer;
 use super::ffi::*;
 
-#[derive(Debug, Eq, PartialEq)]
-pub enum Event {
-    Ready,
-    BytesRead(usize),
-    BytesWritten(usize),
-}
-
 pub struct DeviceControlBlock {
     buffer: Buffer,
     overlapped: OVERLAPPED,
@@ -42,7 +37,9 @@ impl DeviceControlBlock {
 
 pub struct Device {
     name: String,
+    id: DeviceId,
     handle: HANDLE,
+    socket: bool,
     read_control_block: DeviceControlBlock,
     read_pending: bool,
     write_control_blocks: Vec<Box<DeviceControlBlock>>,
@@ -52,7 +49,8 @@ impl Device {
     
     pub fn name(&self) -> &str { &self.name }
     
-    pub fn id(&self) -> DeviceId { self.handle as DeviceId }
+    /// The ID of the device, which is kept after it is closed.
+    pub fn id(&self) -> DeviceId { self.id }
     
     pub fn handle(&self) -> HANDLE { self.handle }
 
@@ -79,22 +77,40 @@ impl Device {
     pub fn new(name: &str, handle: HANDLE) -> Device {
       Device {
           name: name.to_string(),
+          id: handle as DeviceId,
           handle: handle,
+          socket: false,
           read_control_block: DeviceControlBlock::new(),
           read_pending: false,
           write_control_blocks: Vec::with_capacity(32),
       }  
     }
     
+    pub fn from_tcp_stream(stream: net::TcpStream) -> io::Result<Device> {
+        let name = format!("tcp://{}", try!(stream.peer_addr()));
+        // Accepted sockets inherit the non-blocking mode of their listener, 
+        // but they are driven by overlapped IO once attached to the completion port
+        try!(stream.set_nonblocking(false));
+        let mut dev = Device::new(&name, stream.into_raw_socket() as HANDLE);
+        dev.socket = true;
+        Ok(dev)
+    }
+    
     pub fn close(&mut self) -> io::Result<()> {
-        let rc = unsafe { 
-            CloseHandle(self.handle) 
+        try!(self.close_handle());
+        self.handle = INVALID_HANDLE_VALUE;
+        Ok(())
+    }
+
+    /// Closes the handle of the device, which must be done with `closesocket()`
+    /// for the devices created from sockets.
+    fn close_handle(&self) -> io::Result<()> {
+        let closed = if self.socket {
+            unsafe { closesocket(self.handle as SOCKET) == 0 }
+        } else {
+            unsafe { CloseHandle(self.handle) != 0 }
         };
-        if rc == 0 { Err(io::Error::last_os_error()) } 
-        else {
-            self.handle = INVALID_HANDLE_VALUE; 
-            Ok(()) 
-        }            
+        if closed { Ok(()) } else { Err(io::Error::last_os_error()) }
     }
     
     pub fn is_closed(&self) -> bool {
@@ -116,6 +132,9 @@ impl Device {
     
     pub fn request_read_bytes(&mut self, nbytes: usize) -> io::Result<()>{
         assert!(!self.read_pending);
+        if self.read_control_block.buffer.remaining() == 0 {
+            return Err(buffer_full_error(&self.name));
+        }
         let total_bytes = cmp::min(nbytes, self.read_control_block.buffer.remaining());
         let offset = self.read_control_block.buffer.len() as isize;
         let rc = unsafe {
@@ -188,17 +207,10 @@ impl Device {
     }
 }
 
-pub trait DeviceHandler {    
-    fn device(&mut self) -> &mut Device;    
-    fn process_event(&mut self, event: Event) -> io::Result<()>;    
-}
-
-impl<H: DeviceHandler + ?Sized> DeviceHandler for Box<H> {
-    fn device(&mut self) -> &mut Device {
-        self.deref_mut().device()
-    }   
-     
-    fn process_event(&mut self, event: Event) -> io::Result<()> {
-        self.deref_mut().process_event(event)
-    }    
+impl Drop for Device {
+    fn drop(&mut self) {
+        if !self.is_closed() {
+            let _ = self.close_handle();
+        }
+    }
 }
@@ -34,12 +34,15 @@ pub type LPWSTR = *mut WCHAR;
 pub type HANDLE = *mut LPVOID;
 pub type LPHANDLE = *mut HANDLE;
 
+pub type SOCKET = usize;
+
 pub const MAXDWORD: DWORD = 0xFFFFFFFF;
 
 pub const INVALID_HANDLE_VALUE: HANDLE = !0 as HANDLE;
 
 pub const INFINITE: DWORD = !0 as DWORD;
 pub const ERROR_IO_PENDING: DWORD = 997;
+pub const ERROR_PIPE_CONNECTED: DWORD = 535;
 
 pub const STATUS_WAIT_0: ULONG_PTR 			 	= 0 as ULONG_PTR;
 pub const STATUS_ABANDONED_WAIT_0: ULONG_PTR 	= 128 as ULONG_PTR;
@@ -89,6 +92,12 @@ pub const fRtsControl:       DWORD = 0x00003000;
 pub const fAbortOnError:     DWORD = 0x00004000;
 pub const fDummy2:           DWORD = 0xFFFF8000;
 
+pub const PIPE_ACCESS_DUPLEX: DWORD = 0x00000003;
+pub const PIPE_TYPE_BYTE: DWORD = 0x00000000;
+pub const PIPE_READMODE_BYTE: DWORD = 0x00000000;
+pub const PIPE_WAIT: DWORD = 0x00000000;
+pub const PIPE_UNLIMITED_INSTANCES: DWORD = 255;
+
 pub const PURGE_TXABORT: DWORD = 0x0001;
 pub const PURGE_RXABORT: DWORD = 0x0002;
 pub const PURGE_TXCLEAR: DWORD = 0x0004;
@@ -225,6 +234,10 @@ extern "system" {
     
     pub fn CloseHandle(hObject: HANDLE) -> BOOL;
     
+    pub fn ConnectNamedPipe(
+        hNamedPipe: HANDLE,
+        lpOverlapped: LPOVERLAPPED) -> BOOL;
+
 	pub fn CreateFileW(
   		lpFileName: LPCWSTR,
   		dwDesiredAccess: DWORD,
@@ -234,6 +247,16 @@ extern "system" {
   		dwFlagsAndAttributes: DWORD,
   		hTemplateFile: HANDLE) -> HANDLE;
     
+    pub fn CreateNamedPipeW(
+        lpName: LPCWSTR,
+        dwOpenMode: DWORD,
+        dwPipeMode: DWORD,
+        nMaxInstances: DWORD,
+        nOutBufferSize: DWORD,
+        nInBufferSize: DWORD,
+        nDefaultTimeOut: DWORD,
+        lpSecurityAttributes: LPSECURITY_ATTRIBUTES) -> HANDLE;
+
     pub fn CreateIoCompletionPort(
       	FileHandle: HANDLE,
       	ExistingCompletionPort: HANDLE,
@@ -244,6 +267,8 @@ extern "system" {
         hFile: HANDLE,
         lpDCB: LPDCB) -> BOOL;
     
+    pub fn GetCurrentProcessId() -> DWORD;
+
     pub fn GetLastError() -> DWORD;
     
     pub fn GetQueuedCompletionStatus(
@@ -253,10 +278,23 @@ extern "system" {
      	ppOverlapped: *mut LPOVERLAPPED,
   	 	dwMilliseconds: DWORD) -> BOOL;
     
+    pub fn PeekNamedPipe(
+        hNamedPipe: HANDLE,
+        lpBuffer: LPVOID,
+        nBufferSize: DWORD,
+        lpBytesRead: LPDWORD,
+        lpTotalBytesAvail: LPDWORD,
+        lpBytesLeftThisMessage: LPDWORD) -> BOOL;
+
     pub fn PurgeComm(
         hFile: HANDLE,
         dwFlags: DWORD) -> BOOL;
     
+    pub fn QueryDosDeviceW(
+        lpDeviceName: LPCWSTR,
+        lpTargetPath: LPWSTR,
+        ucchMax: DWORD) -> DWORD;
+
     pub fn ReadFile(
   		File: HANDLE,
       	pBuffer: LPVOID,
@@ -278,4 +316,10 @@ extern "system" {
         nNumberOfBytesToWrite: DWORD,
         lpNumberOfBytesWritten: LPDWORD,
     	lpOverlapped: LPOVERLAPPED) -> BOOL;
-}
\ No newline at end of file
+}
+
+#[link(name = "ws2_32")]
+extern "system" {
+
+    pub fn closesocket(s: SOCKET) -> c_int;
+}
@@ -10,6 +10,7 @@ use std::collections::HashMap;
 use std::io;
 use std::time::Duration;
 
+use io::handler::*;
 use types::*;
 
 use super::device::*;
@@ -40,9 +41,23 @@ impl<H: DeviceHandler> CompletionPort<H> {
         self.handlers.get_mut(dev)
     }
         
+    /// Attaches the handler of a new device to the reactor.
+    ///
+    /// Handles are reused once closed, so a closed device with the same ID which was
+    /// not detached yet is detached first.
     pub fn attach(&mut self, mut handler: H) -> io::Result<DeviceId> {
         let handle = handler.device().handle();
         let id = handler.device().id();
+        match self.handlers.get_mut(&id).map(|attached| attached.device().is_closed()) {
+            Some(true) => self.detach(id),
+            Some(false) => {
+                let error = io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("device {} is already attached", id));
+                return Err(error);
+            }
+            None => {}
+        }
         unsafe {            
             let rc = CreateIoCompletionPort(
                 handle,
@@ -72,13 +87,25 @@ impl<H: DeviceHandler> CompletionPort<H> {
              	&mut key as PULONG_PTR,
              	&mut overlapped as *mut LPOVERLAPPED,
           	 	timeout_millis);
-            if rc == 0 {
+            if rc == 0 && overlapped.is_null() {
                 return Err(io::Error::last_os_error());
             }
+            if rc == 0 {
+                // The operation was dequeued but it failed (e.g., the connection was
+                // reset). It is reported to the device as a completion with no bytes.
+                debug!("IO operation failed for device with key {}: {:?}", 
+                    key, io::Error::last_os_error());
+            }
         };
         let id = key as DeviceId;
         let was_closed = {
-            let handler = self.handlers.get_mut(&id).unwrap();
+            let handler = match self.handlers.get_mut(&id) {
+                Some(handler) => handler,
+                None => {
+                    debug!("ignoring IO event from detached device {}", id);
+                    return Ok(id);
+                }
+            };
             if let Some(event) = handler.device().process_event() {
             	if let Err(e) = handler.process_event(event) {
             	    error!("unexpected error while processing IO event from device {}: {:?}", 
@@ -91,7 +118,7 @@ impl<H: DeviceHandler> CompletionPort<H> {
           	handler.device().is_closed()  
         };
         if was_closed {
-            self.handlers.remove(&id);
+            self.detach(id);
         }
         Ok(id)
     }
@@ -99,6 +126,16 @@ impl<H: DeviceHandler> CompletionPort<H> {
     pub fn is_timeout_error(&self, error: &io::Error) -> bool {
         error.raw_os_error() == Some(258)
     }
+
+    /// Removes the handler of a closed device, notifying it with a `Closed` event.
+    fn detach(&mut self, id: DeviceId) {
+        if let Some(mut handler) = self.handlers.remove(&id) {
+            if let Err(e) = handler.process_event(Event::Closed) {
+                error!("unexpected error while closing device {}: {:?}", 
+                    handler.device().name(), e);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -112,7 +149,7 @@ mod test {
 
     use tempdir::TempDir;
 
-	use io::device::*;
+	use io::*;
 
     use super::*;
 	
@@ -0,0 +1,178 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::boxed::Box;
+use std::ffi::OsStr;
+use std::io;
+use std::os::windows::ffi::OsStrExt;
+
+use io::listener::Listener;
+
+use super::device::*;
+use super::ffi::*;
+
+/// A listener that accepts local clients connected to a named pipe. 
+///
+/// There is always a pipe instance waiting for a client. Once a client connects, the
+/// instance is handed as a device and a new one is created for the next client. 
+pub struct IpcListener {
+    name: String,
+    encoded_name: Vec<u16>,
+    pipe: HANDLE,
+    overlapped: Box<OVERLAPPED>,
+    connecting: bool,
+}
+
+impl IpcListener {
+    
+    /// Binds a new listener to the named pipe with the given name (e.g., `\\.\pipe\foo`).
+    pub fn bind(name: &str) -> io::Result<IpcListener> {
+        let encoded_name: Vec<u16> = OsStr::new(name)
+        	.encode_wide()
+        	.chain(Some(0).into_iter())
+        	.collect();
+        let pipe = try!(create_pipe_instance(&encoded_name));
+        Ok(IpcListener {
+            name: format!("pipe://{}", name),
+            encoded_name: encoded_name,
+            pipe: pipe,
+            overlapped: Box::new(OVERLAPPED::new()),
+            connecting: false,
+        })
+    }
+    
+    fn take_pipe_instance(&mut self) -> io::Result<Device> {
+        let next = try!(create_pipe_instance(&self.encoded_name));
+        let dev = Device::new(&self.name, self.pipe);
+        self.pipe = next;
+        self.overlapped = Box::new(OVERLAPPED::new());
+        self.connecting = false;
+        Ok(dev)
+    }
+    
+    fn replace_pipe_instance(&mut self) -> io::Result<()> {
+        let next = try!(create_pipe_instance(&self.encoded_name));
+        unsafe { CloseHandle(self.pipe) };
+        self.pipe = next;
+        self.overlapped = Box::new(OVERLAPPED::new());
+        self.connecting = false;
+        Ok(())
+    }
+}
+
+impl Listener for IpcListener {
+
+    fn name(&self) -> &str { &self.name }
+
+    fn accept(&mut self) -> io::Result<Option<Device>> {
+        if !self.connecting {
+            let rc = unsafe { ConnectNamedPipe(self.pipe, &mut *self.overlapped) };
+            if rc != 0 {
+                return self.take_pipe_instance().map(Some);
+            }
+            match unsafe { GetLastError() } {
+                ERROR_IO_PENDING => { self.connecting = true; }
+                ERROR_PIPE_CONNECTED => return self.take_pipe_instance().map(Some),
+                _ => {
+                    let error = io::Error::last_os_error();
+                    try!(self.replace_pipe_instance());
+                    return Err(error);
+                }
+            }
+        }
+        if self.overlapped.Internal == STATUS_PENDING {
+            return Ok(None);
+        }
+        if self.overlapped.Internal != STATUS_WAIT_0 {
+            let error = io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                format!("connection to named pipe failed with status {:?}", 
+                    self.overlapped.Internal));
+            try!(self.replace_pipe_instance());
+            return Err(error);
+        }
+        self.take_pipe_instance().map(Some)
+    }
+}
+
+impl Drop for IpcListener {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.pipe) };
+    }
+}
+
+fn create_pipe_instance(encoded_name: &[u16]) -> io::Result<HANDLE> {
+    let pipe = checked_handle! { valid =>
+        CreateNamedPipeW(
+            encoded_name.as_ptr() as LPCWSTR,
+            PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT,
+            PIPE_UNLIMITED_INSTANCES,
+            4096,
+            4096,
+            0,
+            0 as LPSECURITY_ATTRIBUTES)
+    };
+    Ok(pipe)
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::fs::OpenOptions;
+    use std::io;
+    use std::io::Write;
+    use std::time::Duration;
+
+    use io::*;
+
+    use super::*;
+
+    #[test]
+    fn should_accept_nothing_when_no_client() {
+        let mut listener = IpcListener::bind(r"\\.\pipe\flightvars-test-ipc-1").unwrap();
+        assert!(listener.accept().unwrap().is_none());
+    }
+
+    #[test]
+    fn should_accept_clients_as_devices() {
+        let name = r"\\.\pipe\flightvars-test-ipc-2";
+        let mut listener = IpcListener::bind(name).unwrap();
+        assert!(listener.accept().unwrap().is_none());
+        let mut client1 = OpenOptions::new().read(true).write(true).open(name).unwrap();
+        let dev1 = listener.accept().unwrap().unwrap();
+        let mut client2 = OpenOptions::new().read(true).write(true).open(name).unwrap();
+        let dev2 = listener.accept().unwrap().unwrap();
+        let mut iocp = Reactor::new().unwrap();
+        iocp.attach(Reader { dev: dev1 }).unwrap();
+        iocp.attach(Reader { dev: dev2 }).unwrap();
+        client1.write_all(b"Hello\n").unwrap();
+        client2.write_all(b"Hello\n").unwrap();
+        iocp.process_event(&Duration::from_millis(100)).unwrap();
+        iocp.process_event(&Duration::from_millis(100)).unwrap();
+    }
+
+    struct Reader { dev: Device }
+
+    impl DeviceHandler for Reader {
+
+        fn device(&mut self) -> &mut Device { &mut self.dev }
+
+        fn process_event(&mut self, event: Event) -> io::Result<()> {
+            match event {
+                Event::Ready => self.dev.request_read(),
+                Event::BytesRead(n) => {
+                    assert_eq!(n, 6);
+                    assert_eq!(self.dev.recv_bytes(), b"Hello\n");
+                    self.dev.close()
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::{FromRawHandle, RawHandle};
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+use super::device::*;
+use super::ffi::*;
+
+static LOOPBACK_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The remote end of a loopback device.
+///
+/// What is sent through the loopback is received by the device, and what is written
+/// to the device is received by the loopback. 
+pub struct Loopback {
+    handle: HANDLE,
+    file: File,
+}
+
+#[allow(dead_code)]
+impl Loopback {
+    pub fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.write_all(data)
+    }
+
+    /// Receives all the bytes available at this moment, without blocking.
+    pub fn recv(&mut self) -> io::Result<Vec<u8>> {
+        let mut available: DWORD = 0;
+        checked_result!(PeekNamedPipe(
+            self.handle,
+            0 as LPVOID,
+            0,
+            0 as LPDWORD,
+            &mut available as LPDWORD,
+            0 as LPDWORD));
+        let mut data = vec![0; available as usize];
+        try!(self.file.read_exact(&mut data));
+        Ok(data)
+    }
+}
+
+/// Creates a device connected to an in-memory loopback.
+///
+/// The loopback is implemented as a named pipe with a unique name. The device takes
+/// the server end, opened for overlapped IO, so it can be attached to the completion
+/// port. The loopback takes the client end, which is used synchronously.
+#[allow(dead_code)]
+pub fn loopback(name: &str) -> io::Result<(Device, Loopback)> {
+    let pipe_name = format!(r"\\.\pipe\flightvars-loopback-{}-{}",
+        unsafe { GetCurrentProcessId() },
+        LOOPBACK_COUNT.fetch_add(1, Ordering::SeqCst));
+    let encoded_name: Vec<u16> = OsStr::new(&pipe_name)
+    	.encode_wide()
+    	.chain(Some(0).into_iter())
+    	.collect();
+    let server = checked_handle! { valid =>
+        CreateNamedPipeW(
+            encoded_name.as_ptr() as LPCWSTR,
+            PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT,
+            1,
+            4096,
+            4096,
+            0,
+            0 as LPSECURITY_ATTRIBUTES)
+    };
+    let client = checked_handle! { valid =>
+        CreateFileW(
+            encoded_name.as_ptr() as LPCWSTR,
+      		GENERIC_READ | GENERIC_WRITE,
+      		0,
+      		0 as LPSECURITY_ATTRIBUTES,
+       		OPEN_EXISTING,
+      		0,
+      		0 as HANDLE)
+    };
+    let dev = Device::new(&format!("loopback://{}", name), server);
+    let file = unsafe { File::from_raw_handle(client as RawHandle) };
+    Ok((dev, Loopback { handle: client, file: file }))
+}
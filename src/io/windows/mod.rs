@@ -0,0 +1,25 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#[macro_use]
+mod ffi;
+
+mod device;
+mod iocp;
+mod ipc;
+#[cfg(test)]
+mod loopback;
+mod serial;
+
+pub use self::device::*;
+pub use self::iocp::*;
+pub use self::iocp::CompletionPort as Reactor;
+pub use self::ipc::*;
+#[cfg(test)]
+pub use self::loopback::*;
+pub use self::serial::*;
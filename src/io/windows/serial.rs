@@ -61,6 +61,25 @@ impl Serial {
         })
     }
     
+    /// Lists the serial ports available in the system (e.g., `COM3`).
+    pub fn available_ports() -> io::Result<Vec<String>> {
+        let mut devices = vec![0u16; 64 * 1024];
+        let len = unsafe {
+            QueryDosDeviceW(0 as LPCWSTR, devices.as_mut_ptr(), devices.len() as DWORD)
+        };
+        if len == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut ports: Vec<String> = devices[..len as usize]
+            .split(|c| *c == 0)
+            .map(String::from_utf16_lossy)
+            .filter(|name| name.starts_with("COM") && name.len() > 3 && 
+                name[3..].chars().all(|c| c.is_digit(10)))
+            .collect();
+        ports.sort();
+        Ok(ports)
+    }
+
     pub fn open_arduino(port: &str, baud_rate: usize) ->io::Result<Serial> {
         let mut port = try!(Serial::open(port));
         
@@ -125,8 +144,7 @@ mod test {
 	use std::io;
 	use std::{thread, time};
 
-	use io::device::*;
-	use io::iocp::*;
+	use io::*;
 
 	use super::*;
 	
@@ -204,6 +222,7 @@ mod test {
 	                assert_eq!(self.dev.recv_bytes(), b"Goodbye FlightVars\n");
 	                self.dev.close()
 	            }
+	            Event::Closed => Ok(()),
 	            _ => unreachable!(),
 	        }
 	    }
@@ -7,20 +7,23 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 extern crate byteorder;
-extern crate fsuipc;
+#[cfg(windows)] extern crate fsuipc;
 extern crate hex;
 extern crate libc;
 #[macro_use] extern crate log;
 extern crate log4rs;
 extern crate rustc_serialize;
+extern crate sha1;
 extern crate tempdir;    
 extern crate toml;
 
-mod config;
+pub mod config;
 mod domain;
+#[cfg(windows)]
 mod fsx;
-mod fv;
+pub mod fv;
 mod io;
+pub mod logging;
 mod proto;
 mod types;
 
@@ -0,0 +1,51 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::boxed::Box;
+use std::io;
+use std::path::Path;
+
+use log4rs::init_config;
+use log4rs::appender::{ConsoleAppender, FileAppender};
+use log4rs::config::{Appender, Config, Root};
+
+use config::LoggingSettings;
+
+/// Configures the logger with the given settings.
+///
+/// It fails if the log file cannot be opened, so the caller may fall back to other
+/// settings.
+pub fn config_logging(settings: LoggingSettings) -> io::Result<()> {
+    let config = try!(log_config(settings));
+    init_config(config).map_err(|e| io::Error::new(
+        io::ErrorKind::Other,
+        format!("cannot initialize the logger: {:?}", e)))
+}
+
+fn log_config(settings: LoggingSettings) -> io::Result<Config> {
+    let main_appender = if settings.console {
+        let console_appender = ConsoleAppender::builder()
+            .pattern(settings.pattern)
+            .build();
+        Appender::builder("main".to_string(), Box::new(console_appender)).build()
+    } else {
+        let log_path = Path::new(&settings.file);
+        let file_appender = try!(FileAppender::builder(log_path)
+            .pattern(settings.pattern)
+            .build());
+        Appender::builder("main".to_string(), Box::new(file_appender)).build()
+    };
+    let root = Root::builder(settings.level)
+        .appender("main".to_string())
+        .build();
+    let config = Config::builder(root)
+        .appender(main_appender)
+        .build()
+        .unwrap();
+    Ok(config)
+}
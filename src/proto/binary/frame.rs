@@ -0,0 +1,359 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io;
+use std::io::Cursor;
+use std::str;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use types::Value;
+
+/// The largest encoded frame accepted from clients, excluding its delimiter.
+pub const MAX_FRAME_SIZE: usize = 256;
+
+pub const ERROR_INVALID_FRAME: u8 = 1;
+pub const ERROR_UNEXPECTED_MESSAGE: u8 = 2;
+pub const ERROR_UNKNOWN_HANDLE: u8 = 3;
+pub const ERROR_INVALID_VARIABLE: u8 = 4;
+
+const BEGIN: u8 = 0x01;
+const SUBSCRIBE: u8 = 0x02;
+const BIND: u8 = 0x03;
+const UNSUBSCRIBE: u8 = 0x04;
+const WRITE: u8 = 0x05;
+const EVENT: u8 = 0x81;
+const ERROR: u8 = 0x82;
+
+const TYPE_BOOL: u8 = 0x01;
+const TYPE_I8: u8 = 0x02;
+const TYPE_U8: u8 = 0x03;
+const TYPE_I16: u8 = 0x04;
+const TYPE_U16: u8 = 0x05;
+const TYPE_I32: u8 = 0x06;
+const TYPE_U32: u8 = 0x07;
+const TYPE_I64: u8 = 0x08;
+const TYPE_U64: u8 = 0x09;
+const TYPE_FLOAT: u8 = 0x0a;
+const TYPE_STR: u8 = 0x0b;
+const TYPE_BYTES: u8 = 0x0c;
+
+/// A message of the binary protocol.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Begin { version: u8, client_id: String },
+    Subscribe { handle: u8, name: String },
+    Bind { handle: u8, name: String },
+    Unsubscribe { handle: u8 },
+    Write { handle: u8, value: Value },
+    Event { handle: u8, value: Value },
+    Error { code: u8, handle: Option<u8> },
+}
+
+impl Message {
+
+    /// Decodes a message from a frame, excluding its delimiter.
+    ///
+    /// It returns `None` for empty frames, which clients may send to resynchronize.
+    pub fn decode(frame: &[u8]) -> io::Result<Option<Message>> {
+        if frame.is_empty() {
+            return Ok(None);
+        }
+        let data = try!(cobs_decode(frame));
+        if data.len() < 3 {
+            return Err(invalid_frame("frame too short"));
+        }
+        let (content, crc) = data.split_at(data.len() - 2);
+        if try!((&crc[..]).read_u16::<BigEndian>()) != crc16(content) {
+            return Err(invalid_frame("CRC mismatch"));
+        }
+        let mut cursor = Cursor::new(&content[1..]);
+        let message = match content[0] {
+            BEGIN => Message::Begin {
+                version: try!(cursor.read_u8()),
+                client_id: try!(read_str(&mut cursor)),
+            },
+            SUBSCRIBE => Message::Subscribe {
+                handle: try!(cursor.read_u8()),
+                name: try!(read_str(&mut cursor)),
+            },
+            BIND => Message::Bind {
+                handle: try!(cursor.read_u8()),
+                name: try!(read_str(&mut cursor)),
+            },
+            UNSUBSCRIBE => Message::Unsubscribe { handle: try!(cursor.read_u8()) },
+            WRITE => Message::Write {
+                handle: try!(cursor.read_u8()),
+                value: try!(read_value(&mut cursor)),
+            },
+            EVENT => Message::Event {
+                handle: try!(cursor.read_u8()),
+                value: try!(read_value(&mut cursor)),
+            },
+            ERROR => Message::Error {
+                code: try!(cursor.read_u8()),
+                handle: cursor.read_u8().ok(),
+            },
+            other => return Err(invalid_frame(&format!("unknown message type {:x}", other))),
+        };
+        Ok(Some(message))
+    }
+
+    /// Encodes the message as a frame, including its delimiter.
+    pub fn encode(&self, buf: &mut Vec<u8>) -> io::Result<()> {
+        let mut content = Vec::new();
+        match *self {
+            Message::Begin { version, ref client_id } => {
+                content.push(BEGIN);
+                content.push(version);
+                content.extend_from_slice(client_id.as_bytes());
+            }
+            Message::Subscribe { handle, ref name } => {
+                content.push(SUBSCRIBE);
+                content.push(handle);
+                content.extend_from_slice(name.as_bytes());
+            }
+            Message::Bind { handle, ref name } => {
+                content.push(BIND);
+                content.push(handle);
+                content.extend_from_slice(name.as_bytes());
+            }
+            Message::Unsubscribe { handle } => {
+                content.push(UNSUBSCRIBE);
+                content.push(handle);
+            }
+            Message::Write { handle, ref value } => {
+                content.push(WRITE);
+                content.push(handle);
+                try!(write_value(value, &mut content));
+            }
+            Message::Event { handle, ref value } => {
+                content.push(EVENT);
+                content.push(handle);
+                try!(write_value(value, &mut content));
+            }
+            Message::Error { code, handle } => {
+                content.push(ERROR);
+                content.push(code);
+                if let Some(handle) = handle {
+                    content.push(handle);
+                }
+            }
+        }
+        let crc = crc16(&content);
+        try!(content.write_u16::<BigEndian>(crc));
+        cobs_encode(&content, buf);
+        buf.push(0);
+        Ok(())
+    }
+}
+
+/// Encodes the given data using Consistent Overhead Byte Stuffing.
+pub fn cobs_encode(data: &[u8], buf: &mut Vec<u8>) {
+    let mut code_index = buf.len();
+    let mut code = 1u8;
+    buf.push(0);
+    for &byte in data {
+        if byte != 0 {
+            buf.push(byte);
+            code += 1;
+        }
+        if byte == 0 || code == 0xff {
+            buf[code_index] = code;
+            code_index = buf.len();
+            code = 1;
+            buf.push(0);
+        }
+    }
+    buf[code_index] = code;
+}
+
+/// Decodes the given data encoded with Consistent Overhead Byte Stuffing.
+pub fn cobs_decode(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let code = data[i] as usize;
+        if code == 0 || i + code > data.len() {
+            return Err(invalid_frame("invalid COBS encoding"));
+        }
+        decoded.extend_from_slice(&data[i + 1..i + code]);
+        i += code;
+        if code < 0xff && i < data.len() {
+            decoded.push(0);
+        }
+    }
+    Ok(decoded)
+}
+
+/// Computes the CRC-16/CCITT-FALSE of the given data.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffffu16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+fn read_str(cursor: &mut Cursor<&[u8]>) -> io::Result<String> {
+    let from = cursor.position() as usize;
+    let data = &cursor.get_ref()[from..];
+    str::from_utf8(data)
+        .map(|s| s.to_string())
+        .map_err(|_| invalid_frame("string is not valid UTF-8"))
+}
+
+/// Reads a value as a type tag followed by its big-endian representation.
+///
+/// Strings and byte blocks take the rest of the frame.
+fn read_value(cursor: &mut Cursor<&[u8]>) -> io::Result<Value> {
+    let value = match try!(cursor.read_u8()) {
+        TYPE_BOOL => Value::Bool(try!(cursor.read_u8()) != 0),
+        TYPE_I8 => Value::I8(try!(cursor.read_i8())),
+        TYPE_U8 => Value::U8(try!(cursor.read_u8())),
+        TYPE_I16 => Value::I16(try!(cursor.read_i16::<BigEndian>())),
+        TYPE_U16 => Value::U16(try!(cursor.read_u16::<BigEndian>())),
+        TYPE_I32 => Value::I32(try!(cursor.read_i32::<BigEndian>())),
+        TYPE_U32 => Value::U32(try!(cursor.read_u32::<BigEndian>())),
+        TYPE_I64 => Value::I64(try!(cursor.read_i64::<BigEndian>())),
+        TYPE_U64 => Value::U64(try!(cursor.read_u64::<BigEndian>())),
+        TYPE_FLOAT => Value::Float(try!(cursor.read_f64::<BigEndian>())),
+        TYPE_STR => Value::Str(try!(read_str(cursor))),
+        TYPE_BYTES => {
+            let from = cursor.position() as usize;
+            Value::Bytes(cursor.get_ref()[from..].to_vec())
+        }
+        other => return Err(invalid_frame(&format!("unknown value type {:x}", other))),
+    };
+    Ok(value)
+}
+
+fn write_value(value: &Value, buf: &mut Vec<u8>) -> io::Result<()> {
+    match *value {
+        Value::Bool(v) => { buf.push(TYPE_BOOL); buf.push(if v { 1 } else { 0 }); }
+        Value::I8(v) => { buf.push(TYPE_I8); try!(buf.write_i8(v)); }
+        Value::U8(v) => { buf.push(TYPE_U8); buf.push(v); }
+        Value::I16(v) => { buf.push(TYPE_I16); try!(buf.write_i16::<BigEndian>(v)); }
+        Value::U16(v) => { buf.push(TYPE_U16); try!(buf.write_u16::<BigEndian>(v)); }
+        Value::I32(v) => { buf.push(TYPE_I32); try!(buf.write_i32::<BigEndian>(v)); }
+        Value::U32(v) => { buf.push(TYPE_U32); try!(buf.write_u32::<BigEndian>(v)); }
+        Value::I64(v) => { buf.push(TYPE_I64); try!(buf.write_i64::<BigEndian>(v)); }
+        Value::U64(v) => { buf.push(TYPE_U64); try!(buf.write_u64::<BigEndian>(v)); }
+        Value::Float(v) => { buf.push(TYPE_FLOAT); try!(buf.write_f64::<BigEndian>(v)); }
+        Value::Str(ref v) => { buf.push(TYPE_STR); buf.extend_from_slice(v.as_bytes()); }
+        Value::Bytes(ref v) => { buf.push(TYPE_BYTES); buf.extend_from_slice(v); }
+    }
+    Ok(())
+}
+
+fn invalid_frame(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid binary frame: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use types::Value;
+
+    use super::*;
+
+    #[test]
+    fn should_encode_cobs() {
+        assert_eq!(cobs(&[0x00]), vec![0x01, 0x01]);
+        assert_eq!(cobs(&[0x00, 0x00]), vec![0x01, 0x01, 0x01]);
+        assert_eq!(cobs(&[0x11, 0x22, 0x00, 0x33]), vec![0x03, 0x11, 0x22, 0x02, 0x33]);
+        assert_eq!(cobs(&[0x11, 0x00, 0x00, 0x00]), vec![0x02, 0x11, 0x01, 0x01, 0x01]);
+        let data: Vec<u8> = (1..255).map(|i| i as u8).collect();
+        let encoded = cobs(&data);
+        assert_eq!(encoded.len(), 256);
+        assert_eq!(encoded[0], 0xff);
+    }
+
+    #[test]
+    fn should_decode_cobs() {
+        assert_eq!(cobs_decode(&[0x01, 0x01]).unwrap(), vec![0x00]);
+        assert_eq!(
+            cobs_decode(&[0x03, 0x11, 0x22, 0x02, 0x33]).unwrap(),
+            vec![0x11, 0x22, 0x00, 0x33]);
+        let data: Vec<u8> = (0..600).map(|i| (i % 7) as u8).collect();
+        assert_eq!(cobs_decode(&cobs(&data)).unwrap(), data);
+        let data: Vec<u8> = (0..600).map(|i| (i % 250 + 1) as u8).collect();
+        assert_eq!(cobs_decode(&cobs(&data)).unwrap(), data);
+        assert!(cobs_decode(&[0x05, 0x11]).is_err());
+        assert!(cobs_decode(&[0x00]).is_err());
+    }
+
+    #[test]
+    fn should_compute_crc16() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+    }
+
+    #[test]
+    fn should_encode_and_decode_messages() {
+        let messages = vec![
+            Message::Begin { version: 1, client_id: "arduino".to_string() },
+            Message::Subscribe { handle: 0, name: "fsuipc/0BC8+2".to_string() },
+            Message::Bind { handle: 1, name: "lvar/A320_Gear_Lever".to_string() },
+            Message::Unsubscribe { handle: 2 },
+            Message::Write { handle: 3, value: Value::I32(-1) },
+            Message::Write { handle: 3, value: Value::Bool(true) },
+            Message::Event { handle: 4, value: Value::U16(16383) },
+            Message::Event { handle: 4, value: Value::I8(-2) },
+            Message::Event { handle: 4, value: Value::U8(200) },
+            Message::Event { handle: 4, value: Value::I16(-300) },
+            Message::Event { handle: 4, value: Value::U32(0xffff_0000) },
+            Message::Event { handle: 4, value: Value::I64(-1 << 40) },
+            Message::Event { handle: 4, value: Value::U64(1 << 63) },
+            Message::Event { handle: 4, value: Value::Float(245.5) },
+            Message::Event { handle: 4, value: Value::Str("Airbus A320".to_string()) },
+            Message::Event { handle: 4, value: Value::Bytes(vec![0x00, 0x01, 0x00]) },
+            Message::Error { code: ERROR_UNKNOWN_HANDLE, handle: Some(5) },
+            Message::Error { code: ERROR_INVALID_FRAME, handle: None },
+        ];
+        for message in messages {
+            let mut buf = Vec::new();
+            message.encode(&mut buf).unwrap();
+            assert_eq!(buf.last(), Some(&0));
+            assert!(!buf[..buf.len() - 1].contains(&0));
+            let decoded = Message::decode(&buf[..buf.len() - 1]).unwrap();
+            assert_eq!(decoded, Some(message));
+        }
+    }
+
+    #[test]
+    fn should_fail_decode_corrupted_messages() {
+        let mut buf = Vec::new();
+        Message::Event { handle: 4, value: Value::U16(16383) }.encode(&mut buf).unwrap();
+        buf[3] ^= 0x01;
+        assert!(Message::decode(&buf[..buf.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn should_fail_decode_unknown_value_types() {
+        let mut content = vec![0x81, 0x04, 0x7f, 0x00];
+        let crc = crc16(&content);
+        content.write_u16::<BigEndian>(crc).unwrap();
+        let mut buf = Vec::new();
+        cobs_encode(&content, &mut buf);
+        assert!(Message::decode(&buf).is_err());
+    }
+
+    #[test]
+    fn should_ignore_empty_frames() {
+        assert_eq!(Message::decode(&[]).unwrap(), None);
+    }
+
+    fn cobs(data: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        cobs_encode(data, &mut buf);
+        buf
+    }
+}
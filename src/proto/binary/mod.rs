@@ -0,0 +1,340 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A compact binary protocol for low-bandwidth links, like serial ports.
+//!
+//! Each frame carries a message type, its arguments and a CRC-16/CCITT-FALSE of all
+//! of them in big-endian order. Frames are encoded with COBS and delimited by a zero
+//! byte, so a receiver can always resynchronize at the next delimiter.
+//!
+//! After a `BEGIN`, clients bind a handle of their choice to a domain-qualified
+//! variable (e.g., `fsuipc/0BC8+2` or `lvar/A320_Gear_Lever`) with `SUBSCRIBE` or
+//! `BIND`. The former also observes the variable, whose updates are then sent as
+//! `EVENT` messages for the handle. `WRITE` takes the handle instead of the name of
+//! the variable. Values are sent as a type tag followed by their big-endian
+//! representation, so any value of the domains can be read and written. Failed
+//! messages are answered with an `ERROR` message instead of closing the link.
+
+use std::collections::HashMap;
+use std::io;
+
+use domain::DomainDispatcher;
+use io::*;
+use proto::*;
+use proto::{domain_var, qualified_var};
+use types::*;
+
+mod frame;
+
+pub use self::frame::*;
+
+const PROTOCOL_VERSION: u8 = 1;
+
+struct Binding {
+    domain: String,
+    variable: Var,
+    observed: bool,
+}
+
+pub struct Binary {
+    dev: Device,
+    domains: DomainDispatcher,
+    client_id: Option<String>,
+    bindings: HashMap<u8, Binding>,
+}
+
+impl Binary {
+
+    pub fn new(dev: Device, domains: DomainDispatcher) -> Binary {
+        Binary { dev: dev, domains: domains, client_id: None, bindings: HashMap::new() }
+    }
+
+    fn process_input(&mut self) -> io::Result<()> {
+        loop {
+            let delimiter = self.dev.recv_bytes().iter().position(|b| *b == 0);
+            let len = match delimiter {
+                Some(len) => len,
+                None if self.dev.recv_bytes().len() > MAX_FRAME_SIZE => {
+                    warn!("discarding {} bytes with no frame delimiter from {}",
+                        self.dev.recv_bytes().len(), self.dev.name());
+                    let nbytes = self.dev.recv_bytes().len();
+                    self.dev.consume_recv_buffer(nbytes);
+                    return Ok(());
+                }
+                None => return Ok(()),
+            };
+            let decoded = Message::decode(&self.dev.recv_bytes()[..len]);
+            self.dev.consume_recv_buffer(len + 1);
+            match decoded {
+                Ok(Some(message)) => try!(self.process_message(message)),
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("discarding invalid frame from {}: {:?}", self.dev.name(), e);
+                    try!(self.send(&Message::Error { code: ERROR_INVALID_FRAME, handle: None }));
+                }
+            }
+        }
+    }
+
+    fn process_message(&mut self, message: Message) -> io::Result<()> {
+        let begin_received = self.client_id.is_some();
+        match (message, begin_received) {
+            (Message::Begin { version, client_id }, false) => {
+                if version != PROTOCOL_VERSION {
+                    error!("client {} uses unsupported binary protocol version {}",
+                        client_id, version);
+                    return self.send_error(ERROR_UNEXPECTED_MESSAGE, None);
+                }
+                info!("received a begin message from client {}", client_id);
+                self.client_id = Some(client_id);
+                Ok(())
+            }
+            (Message::Subscribe { handle, name }, true) => self.bind(handle, &name, true),
+            (Message::Bind { handle, name }, true) => self.bind(handle, &name, false),
+            (Message::Unsubscribe { handle }, true) => {
+                match self.bindings.remove(&handle) {
+                    Some(binding) => self.release(binding),
+                    None => self.send_error(ERROR_UNKNOWN_HANDLE, Some(handle)),
+                }
+            }
+            (Message::Write { handle, value }, true) => {
+                let result = match self.bindings.get(&handle) {
+                    Some(binding) => {
+                        debug!("received a write message from client {}: {:?} <- {}",
+                            self.client_id(), binding.variable, value);
+                        let variable = &binding.variable;
+                        self.domains.with_domain(&binding.domain, |dom| dom.write(variable, &value))
+                    }
+                    None => return self.send_error(ERROR_UNKNOWN_HANDLE, Some(handle)),
+                };
+                if let Err(e) = result {
+                    error!("cannot write handle {} from client {}: {:?}",
+                        handle, self.client_id(), e);
+                    return self.send_error(ERROR_INVALID_VARIABLE, Some(handle));
+                }
+                Ok(())
+            }
+            (message, _) => {
+                error!("unexpected message from client {}: {:?}", self.client_id(), message);
+                self.send_error(ERROR_UNEXPECTED_MESSAGE, None)
+            }
+        }
+    }
+
+    fn bind(&mut self, handle: u8, name: &str, observe: bool) -> io::Result<()> {
+        if let Some(binding) = self.bindings.remove(&handle) {
+            try!(self.release(binding));
+        }
+        let result = qualified_var(name).and_then(|(domain, name)| {
+            let variable = try!(domain_var(&domain, &name));
+            if observe && !self.is_observed(&domain, &variable) {
+                let dev_id = self.dev.id();
+                try!(self.domains.with_domain(&domain, |dom| dom.subscribe(dev_id, &variable)));
+            }
+            Ok(Binding { domain: domain, variable: variable, observed: observe })
+        });
+        match result {
+            Ok(binding) => {
+                debug!("client {} bound handle {} to {}", self.client_id(), handle, name);
+                self.bindings.insert(handle, binding);
+                Ok(())
+            }
+            Err(e) => {
+                error!("cannot bind handle {} to {} for client {}: {:?}",
+                    handle, name, self.client_id(), e);
+                self.send_error(ERROR_INVALID_VARIABLE, Some(handle))
+            }
+        }
+    }
+
+    /// Unsubscribes from the variable of a removed binding, unless other handle observes it.
+    fn release(&mut self, binding: Binding) -> io::Result<()> {
+        if binding.observed && !self.is_observed(&binding.domain, &binding.variable) {
+            let dev_id = self.dev.id();
+            let variable = &binding.variable;
+            try!(self.domains.with_domain(&binding.domain, |dom| dom.unsubscribe(dev_id, variable)));
+        }
+        Ok(())
+    }
+
+    fn is_observed(&self, domain: &str, variable: &Var) -> bool {
+        self.bindings.values().any(|b| b.observed && b.domain == domain && b.variable == *variable)
+    }
+
+    fn send_error(&mut self, code: u8, handle: Option<u8>) -> io::Result<()> {
+        self.send(&Message::Error { code: code, handle: handle })
+    }
+
+    fn send(&mut self, message: &Message) -> io::Result<()> {
+        let mut buf = Vec::new();
+        try!(message.encode(&mut buf));
+        self.dev.request_write(&buf)
+    }
+
+    fn client_id(&self) -> &str {
+        self.client_id.as_ref().map(String::as_str).unwrap_or("none")
+    }
+}
+
+impl DeviceHandler for Binary {
+    fn device(&mut self) -> &mut Device { &mut self.dev }
+
+    fn process_event(&mut self, event: Event) -> io::Result<()> {
+        match event {
+            Event::Ready => self.dev.request_read(),
+            Event::BytesRead(0) => {
+                info!("device {} was disconnected by client {}", self.dev.name(), self.client_id());
+                self.dev.close()
+            }
+            Event::BytesRead(_) => {
+                try!(self.process_input());
+                self.dev.request_read()
+            }
+            Event::BytesWritten(_) => Ok(()),
+            Event::Closed => {
+                let dev_id = self.dev.id();
+                self.domains.unsubscribe_all(dev_id)
+            }
+        }
+    }
+}
+
+impl Protocol for Binary {
+
+    fn send_update(&mut self, domain: &str, variable: Var, value: Value) -> io::Result<()> {
+        let handles: Vec<u8> = self.bindings.iter()
+            .filter(|&(_, b)| b.observed && b.domain == domain && b.variable == variable)
+            .map(|(handle, _)| *handle)
+            .collect();
+        if handles.is_empty() {
+            return Ok(());
+        }
+        for handle in handles {
+            try!(self.send(&Message::Event { handle: handle, value: value.clone() }));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use config::DomainSettings;
+    use domain::*;
+    use io::*;
+    use proto::test_util::*;
+
+    use super::*;
+
+    #[test]
+    fn should_send_events_for_subscribed_handles() {
+        let (mut reactor, mut client, fsuipc) = binary_client();
+        fsuipc.poke(0x0bc8, &[0xff, 0x3f]);
+        send(&mut reactor, &mut client, &[
+            Message::Begin { version: 1, client_id: "arduino".to_string() },
+            Message::Subscribe { handle: 7, name: "fsuipc/0BC8+2".to_string() },
+        ]);
+        reactor.poll_domains();
+        assert_eq!(
+            recv(&mut reactor, &mut client),
+            vec![Message::Event { handle: 7, value: Value::I16(0x3fff) }]);
+    }
+
+    #[test]
+    fn should_send_events_of_any_value() {
+        let (mut reactor, mut client, fsuipc) = binary_client();
+        fsuipc.poke(0x0560, &[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40]);
+        fsuipc.poke(0x3d00, b"A320\0");
+        send(&mut reactor, &mut client, &[
+            Message::Begin { version: 1, client_id: "arduino".to_string() },
+            Message::Subscribe { handle: 1, name: "fsuipc/0560+8:f64".to_string() },
+            Message::Subscribe { handle: 2, name: "fsuipc/3D00+16:str".to_string() },
+        ]);
+        reactor.poll_domains();
+        let mut events = recv(&mut reactor, &mut client);
+        events.sort_by_key(|m| match *m { Message::Event { handle, .. } => handle, _ => 0 });
+        assert_eq!(events, vec![
+            Message::Event { handle: 1, value: Value::Float(2.0) },
+            Message::Event { handle: 2, value: Value::Str("A320".to_string()) },
+        ]);
+    }
+
+    #[test]
+    fn should_write_bound_handles() {
+        let (mut reactor, mut client, fsuipc) = binary_client();
+        send(&mut reactor, &mut client, &[
+            Message::Begin { version: 1, client_id: "arduino".to_string() },
+            Message::Bind { handle: 1, name: "fsuipc/0BC8/2".to_string() },
+            Message::Write { handle: 1, value: Value::I32(0x0102) },
+        ]);
+        reactor.poll_domains();
+        assert_eq!(fsuipc.peek(0x0bc8, 2), vec![0x02, 0x01]);
+        assert!(recv(&mut reactor, &mut client).is_empty());
+    }
+
+    #[test]
+    fn should_not_send_events_after_unsubscribe() {
+        let (mut reactor, mut client, _) = binary_client();
+        send(&mut reactor, &mut client, &[
+            Message::Begin { version: 1, client_id: "arduino".to_string() },
+            Message::Subscribe { handle: 1, name: "fsuipc/0BC8+2".to_string() },
+            Message::Subscribe { handle: 2, name: "fsuipc/0BC8+2".to_string() },
+            Message::Unsubscribe { handle: 1 },
+        ]);
+        reactor.poll_domains();
+        assert_eq!(
+            recv(&mut reactor, &mut client),
+            vec![Message::Event { handle: 2, value: Value::I16(0) }]);
+    }
+
+    #[test]
+    fn should_answer_errors() {
+        let (mut reactor, mut client, _) = binary_client();
+        send(&mut reactor, &mut client, &[
+            Message::Write { handle: 1, value: Value::I32(0) },
+            Message::Begin { version: 1, client_id: "arduino".to_string() },
+            Message::Write { handle: 1, value: Value::I32(0) },
+            Message::Subscribe { handle: 2, name: "foo/bar".to_string() },
+        ]);
+        client.send(&[0x02, 0x42, 0x00]).unwrap();
+        reactor.run();
+        assert_eq!(recv(&mut reactor, &mut client), vec![
+            Message::Error { code: ERROR_UNEXPECTED_MESSAGE, handle: None },
+            Message::Error { code: ERROR_UNKNOWN_HANDLE, handle: Some(1) },
+            Message::Error { code: ERROR_INVALID_VARIABLE, handle: Some(2) },
+            Message::Error { code: ERROR_INVALID_FRAME, handle: None },
+        ]);
+    }
+
+    fn binary_client() -> (TestReactor, Loopback, fsuipc::MemoryBackend) {
+        let mem = fsuipc::MemoryBackend::new();
+        let mut domains = DomainDispatcher::new(&DomainSettings { enabled: vec![] }).unwrap();
+        domains.add("fsuipc", fsuipc::Fsuipc::new(mem.clone()));
+        let (dev, client) = loopback("binary").unwrap();
+        let mut reactor = TestReactor::new(domains);
+        reactor.attach(|domains| Binary::new(dev, domains));
+        (reactor, client, mem)
+    }
+
+    fn send(reactor: &mut TestReactor, client: &mut Loopback, messages: &[Message]) {
+        let mut buf = Vec::new();
+        for message in messages {
+            message.encode(&mut buf).unwrap();
+        }
+        client.send(&buf).unwrap();
+        reactor.run();
+    }
+
+    fn recv(reactor: &mut TestReactor, client: &mut Loopback) -> Vec<Message> {
+        reactor.run();
+        let data = client.recv().unwrap();
+        data.split(|b| *b == 0)
+            .filter_map(|frame| Message::decode(frame).unwrap())
+            .collect()
+    }
+}
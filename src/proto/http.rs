@@ -0,0 +1,288 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A minimal HTTP/1.1 codec, just enough to serve the protocols built on top of it.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::str;
+
+/// The largest request head accepted from clients.
+///
+/// Together with `MAX_BODY_SIZE`, it fits in the 4096 bytes read buffer of the devices,
+/// so requests over the limits are detected before the buffer fills.
+pub const MAX_HEAD_SIZE: usize = 2048;
+
+/// The largest request body accepted from clients.
+pub const MAX_BODY_SIZE: usize = 2048;
+
+/// The error of a request that exceeds the limits of the codec.
+#[derive(Debug)]
+pub enum TooLarge {
+    /// The head of the request is larger than `MAX_HEAD_SIZE`.
+    Head,
+    /// The body of the request is larger than `MAX_BODY_SIZE`.
+    Body,
+}
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid HTTP request: {}", self.description())
+    }
+}
+
+impl Error for TooLarge {
+    fn description(&self) -> &str {
+        match *self {
+            TooLarge::Head => "request head too large",
+            TooLarge::Body => "request body too large",
+        }
+    }
+}
+
+/// Returns the status of the response to a request that cannot be decoded.
+pub fn invalid_request_status(error: &io::Error) -> (u16, &'static str) {
+    match error.get_ref().and_then(|e| e.downcast_ref::<TooLarge>()) {
+        Some(&TooLarge::Head) => (431, "Request Header Fields Too Large"),
+        Some(&TooLarge::Body) => (413, "Payload Too Large"),
+        None => (400, "Bad Request"),
+    }
+}
+
+/// A HTTP request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+
+    /// Decodes a request from the given buffer.
+    ///
+    /// It returns the request and the number of bytes it takes, or `None` if the buffer
+    /// does not contain a complete request yet.
+    pub fn decode(buf: &[u8]) -> io::Result<Option<(Request, usize)>> {
+        let head_len = match buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(pos) if pos + 4 <= MAX_HEAD_SIZE => pos + 4,
+            None if buf.len() <= MAX_HEAD_SIZE => return Ok(None),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, TooLarge::Head)),
+        };
+        let head = try!(str::from_utf8(&buf[..head_len])
+            .map_err(|_| invalid_request("request head is not valid UTF-8")));
+        let mut lines = head.split("\r\n");
+        let request_line = lines.next().unwrap_or("");
+        let mut parts = request_line.split(' ');
+        let (method, path, version) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(path), Some(version)) => (method, path, version),
+            _ => return Err(invalid_request("malformed request line")),
+        };
+        if !version.starts_with("HTTP/1.") {
+            return Err(invalid_request("unsupported HTTP version"));
+        }
+        let mut headers = Vec::new();
+        for line in lines.take_while(|line| !line.is_empty()) {
+            let mut header = line.splitn(2, ':');
+            match (header.next(), header.next()) {
+                (Some(name), Some(value)) =>
+                    headers.push((name.trim().to_string(), value.trim().to_string())),
+                _ => return Err(invalid_request("malformed header")),
+            }
+        }
+        let mut request = Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: headers,
+            body: Vec::new(),
+        };
+        let body_len = match request.header("Content-Length") {
+            Some(len) => try!(len.parse().map_err(|_| invalid_request("invalid content length"))),
+            None => 0,
+        };
+        if body_len > MAX_BODY_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, TooLarge::Body));
+        }
+        if buf.len() < head_len + body_len {
+            return Ok(None);
+        }
+        request.body.extend_from_slice(&buf[head_len..head_len + body_len]);
+        Ok(Some((request, head_len + body_len)))
+    }
+
+    /// Returns the value of the given header, ignoring the case of its name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|&&(ref n, _)| n.to_lowercase() == name.to_lowercase())
+            .map(|&(_, ref v)| v.as_str())
+    }
+
+    /// Checks whether the given header contains the given token, ignoring case.
+    pub fn header_has_token(&self, name: &str, token: &str) -> bool {
+        self.header(name)
+            .map(|value| {
+                value.split(',').any(|t| t.trim().to_lowercase() == token.to_lowercase())
+            })
+            .unwrap_or(false)
+    }
+}
+
+/// A HTTP response.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+
+    pub fn new(status: u16, reason: &str) -> Response {
+        Response {
+            status: status,
+            reason: reason.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_body(mut self, content_type: &str, body: Vec<u8>) -> Response {
+        self.headers.push(("Content-Type".to_string(), content_type.to_string()));
+        self.body = body;
+        self
+    }
+
+    /// Encodes the response into the given buffer.
+    ///
+    /// A `Content-Length` header is added for responses other than `101 Switching Protocols`.
+    pub fn encode(&self, buf: &mut Vec<u8>) -> io::Result<()> {
+        try!(self.encode_status_and_headers(buf));
+        if self.status != 101 {
+            try!(write!(buf, "Content-Length: {}\r\n", self.body.len()));
+        }
+        try!(write!(buf, "\r\n"));
+        buf.extend_from_slice(&self.body);
+        Ok(())
+    }
+
+    /// Encodes the head of a response whose body is streamed until the connection closes.
+    pub fn encode_stream_head(&self, buf: &mut Vec<u8>) -> io::Result<()> {
+        try!(self.encode_status_and_headers(buf));
+        try!(write!(buf, "\r\n"));
+        Ok(())
+    }
+
+    fn encode_status_and_headers(&self, buf: &mut Vec<u8>) -> io::Result<()> {
+        try!(write!(buf, "HTTP/1.1 {} {}\r\n", self.status, self.reason));
+        for &(ref name, ref value) in &self.headers {
+            try!(write!(buf, "{}: {}\r\n", name, value));
+        }
+        Ok(())
+    }
+}
+
+fn invalid_request(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid HTTP request: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::iter;
+
+    use super::*;
+
+    #[test]
+    fn should_decode_request() {
+        let buf = b"GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\r\nextra";
+        let (request, nbytes) = Request::decode(buf).unwrap().unwrap();
+        assert_eq!(nbytes, buf.len() - 5);
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/ws");
+        assert_eq!(request.header("upgrade"), Some("websocket"));
+        assert_eq!(request.header("Origin"), None);
+    }
+
+    #[test]
+    fn should_decode_request_with_body() {
+        let buf = b"PUT /x HTTP/1.1\r\nContent-Length: 2\r\n\r\n42";
+        let (request, nbytes) = Request::decode(buf).unwrap().unwrap();
+        assert_eq!(nbytes, buf.len());
+        assert_eq!(request.body, b"42");
+    }
+
+    #[test]
+    fn should_wait_for_complete_request() {
+        assert_eq!(Request::decode(b"GET / HTTP/1.1\r\nHost: x\r\n").unwrap(), None);
+        assert_eq!(
+            Request::decode(b"PUT / HTTP/1.1\r\nContent-Length: 2\r\n\r\n4").unwrap(),
+            None);
+    }
+
+    #[test]
+    fn should_fail_decode_malformed_request() {
+        assert!(Request::decode(b"GET\r\n\r\n").is_err());
+        assert!(Request::decode(b"GET / SPDY/3\r\n\r\n").is_err());
+        assert!(Request::decode(b"GET / HTTP/1.1\r\nfoobar\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn should_fail_decode_too_large_request() {
+        let path: String = iter::repeat('x').take(MAX_HEAD_SIZE).collect();
+        let head = format!("GET /{} HTTP/1.1\r\n", path);
+        let error = Request::decode(head.as_bytes()).unwrap_err();
+        assert_eq!(invalid_request_status(&error).0, 431);
+        let complete_head = format!("{}\r\n", head);
+        let error = Request::decode(complete_head.as_bytes()).unwrap_err();
+        assert_eq!(invalid_request_status(&error).0, 431);
+        let body = format!("PUT / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_SIZE + 1);
+        let error = Request::decode(body.as_bytes()).unwrap_err();
+        assert_eq!(invalid_request_status(&error).0, 413);
+        let error = Request::decode(b"GET\r\n\r\n").unwrap_err();
+        assert_eq!(invalid_request_status(&error).0, 400);
+    }
+
+    #[test]
+    fn should_match_header_tokens() {
+        let buf = b"GET / HTTP/1.1\r\nConnection: keep-alive, Upgrade\r\n\r\n";
+        let (request, _) = Request::decode(buf).unwrap().unwrap();
+        assert!(request.header_has_token("connection", "upgrade"));
+        assert!(!request.header_has_token("connection", "close"));
+    }
+
+    #[test]
+    fn should_encode_response() {
+        let mut buf = Vec::new();
+        Response::new(404, "Not Found")
+            .with_body("text/plain", b"nope".to_vec())
+            .encode(&mut buf)
+            .unwrap();
+        assert_eq!(
+            &buf[..],
+            &b"HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: 4\r\n\r\nnope"[..]);
+    }
+
+    #[test]
+    fn should_encode_stream_head() {
+        let mut buf = Vec::new();
+        Response::new(200, "OK")
+            .with_header("Content-Type", "text/event-stream")
+            .encode_stream_head(&mut buf)
+            .unwrap();
+        assert_eq!(&buf[..], &b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n"[..]);
+    }
+}
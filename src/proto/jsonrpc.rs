@@ -0,0 +1,400 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! JSON-RPC 2.0 over newline-delimited JSON.
+//!
+//! Each line carries a request, a notification or a batch of them. The methods are
+//! `subscribe`, `unsubscribe` and `read`, taking the `domain` and the `var` as
+//! parameters, and `write`, which also takes the `value`. Parameters may be given
+//! by name or by position. Variables are named as in the REST API (e.g., `0BC8+2`
+//! for FSUIPC offsets). Updates of subscribed variables are sent as `event`
+//! notifications with the same parameters as `write`.
+//!
+//! Failed requests are answered with an error object, and the connection remains open.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::io::BufRead;
+
+use rustc_serialize::json::{Json, ToJson};
+
+use domain::DomainDispatcher;
+use io::*;
+use proto::*;
+use proto::domain_var;
+use types::*;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+/// The error code for unknown domains and variables.
+pub const NOT_FOUND: i64 = -32001;
+
+/// A JSON-RPC error object.
+#[derive(Debug, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: &str) -> RpcError {
+        RpcError { code: code, message: message.to_string() }
+    }
+}
+
+impl From<io::Error> for RpcError {
+    fn from(error: io::Error) -> RpcError {
+        let code = match error.kind() {
+            io::ErrorKind::NotFound => NOT_FOUND,
+            io::ErrorKind::InvalidInput => INVALID_PARAMS,
+            io::ErrorKind::InvalidData => INVALID_REQUEST,
+            _ => INTERNAL_ERROR,
+        };
+        RpcError::new(code, &format!("{}", error))
+    }
+}
+
+impl ToJson for RpcError {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        object.insert("code".to_string(), Json::I64(self.code));
+        object.insert("message".to_string(), Json::String(self.message.clone()));
+        Json::Object(object)
+    }
+}
+
+pub struct JsonRpc {
+    dev: Device,
+    domains: DomainDispatcher,
+    subscriptions: HashMap<(String, Var), String>,
+}
+
+impl JsonRpc {
+
+    pub fn new(dev: Device, domains: DomainDispatcher) -> JsonRpc {
+        JsonRpc { dev: dev, domains: domains, subscriptions: HashMap::new() }
+    }
+
+    fn line_is_ready(&self) -> bool {
+        self.dev.recv_bytes().contains(&b'\n')
+    }
+
+    fn process_input(&mut self) -> io::Result<usize> {
+        assert!(self.line_is_ready());
+        let mut line = String::new();
+        let nbytes = {
+            let mut buf = io::BufReader::new(self.dev.recv_bytes());
+            try!(buf.read_line(&mut line))
+        };
+        if !line.trim().is_empty() {
+            if let Some(response) = self.process_line(&line) {
+                try!(self.send(&response));
+            }
+        }
+        Ok(nbytes)
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<Json> {
+        let json = match Json::from_str(line) {
+            Ok(json) => json,
+            Err(e) => {
+                let error = RpcError::new(PARSE_ERROR, &format!("{}", e));
+                return Some(error_response(Json::Null, error));
+            }
+        };
+        match json {
+            Json::Array(ref requests) if requests.is_empty() => {
+                let error = RpcError::new(INVALID_REQUEST, "empty batch");
+                Some(error_response(Json::Null, error))
+            }
+            Json::Array(requests) => {
+                let responses: Vec<Json> = requests.into_iter()
+                    .filter_map(|request| self.process_request(request))
+                    .collect();
+                if responses.is_empty() { None } else { Some(Json::Array(responses)) }
+            }
+            request => self.process_request(request),
+        }
+    }
+
+    fn process_request(&mut self, request: Json) -> Option<Json> {
+        let mut request = match request {
+            Json::Object(request) => request,
+            _ => {
+                let error = RpcError::new(INVALID_REQUEST, "request is not an object");
+                return Some(error_response(Json::Null, error));
+            }
+        };
+        let id = request.remove("id");
+        let is_v2 = request.get("jsonrpc").and_then(|v| v.as_string()) == Some("2.0");
+        let method = match request.remove("method") {
+            Some(Json::String(ref method)) if is_v2 => method.clone(),
+            _ => {
+                let error = RpcError::new(INVALID_REQUEST, "not a JSON-RPC 2.0 request");
+                return Some(error_response(id.unwrap_or(Json::Null), error));
+            }
+        };
+        let params = request.remove("params").unwrap_or(Json::Null);
+        let result = self.call(&method, &params);
+        match id {
+            Some(id) => Some(match result {
+                Ok(result) => success_response(id, result),
+                Err(error) => error_response(id, error),
+            }),
+            None => {
+                if let Err(error) = result {
+                    error!("notification {} from {} failed: {:?}", method, self.dev.name(), error);
+                }
+                None
+            }
+        }
+    }
+
+    fn call(&mut self, method: &str, params: &Json) -> Result<Json, RpcError> {
+        let dev_id = self.dev.id();
+        match method {
+            "subscribe" => {
+                let (domain, name, variable) = try!(var_params(params));
+                let key = (domain, variable);
+                if !self.subscriptions.contains_key(&key) {
+                    try!(self.domains.with_domain(&key.0, |dom| dom.subscribe(dev_id, &key.1)));
+                    self.subscriptions.insert(key, name);
+                }
+                Ok(Json::Boolean(true))
+            }
+            "unsubscribe" => {
+                let (domain, _, variable) = try!(var_params(params));
+                let key = (domain, variable);
+                if self.subscriptions.remove(&key).is_some() {
+                    try!(self.domains.with_domain(&key.0, |dom| {
+                        dom.unsubscribe(dev_id, &key.1)
+                    }));
+                }
+                Ok(Json::Boolean(true))
+            }
+            "read" => {
+                let (domain, _, variable) = try!(var_params(params));
+                let value = try!(self.domains.with_domain(&domain, |dom| dom.read(&variable)));
+                Ok(value.to_json())
+            }
+            "write" => {
+                let (domain, _, variable) = try!(var_params(params));
+                let value = try!(param(params, "value", 2)
+                    .and_then(Value::from_json)
+                    .ok_or(RpcError::new(INVALID_PARAMS, "missing or invalid value")));
+                try!(self.domains.with_domain(&domain, |dom| dom.write(&variable, &value)));
+                Ok(Json::Boolean(true))
+            }
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, &format!("no such method '{}'", method))),
+        }
+    }
+
+    fn send(&mut self, message: &Json) -> io::Result<()> {
+        let mut buf = message.to_string().into_bytes();
+        buf.push(b'\n');
+        self.dev.request_write(&buf)
+    }
+}
+
+impl DeviceHandler for JsonRpc {
+    fn device(&mut self) -> &mut Device { &mut self.dev }
+
+    fn process_event(&mut self, event: Event) -> io::Result<()> {
+        match event {
+            Event::Ready => self.dev.request_read(),
+            Event::BytesRead(0) => {
+                info!("device {} was disconnected by JSON-RPC client", self.dev.name());
+                self.dev.close()
+            }
+            Event::BytesRead(_) => {
+                while self.line_is_ready() {
+                    let nread = try!(self.process_input());
+                    self.dev.consume_recv_buffer(nread);
+                }
+                self.dev.request_read()
+            }
+            Event::BytesWritten(_) => Ok(()),
+            Event::Closed => {
+                let dev_id = self.dev.id();
+                self.domains.unsubscribe_all(dev_id)
+            }
+        }
+    }
+}
+
+impl Protocol for JsonRpc {
+
+    fn send_update(&mut self, domain: &str, variable: Var, value: Value) -> io::Result<()> {
+        let key = (domain.to_string(), variable);
+        let name = match self.subscriptions.get(&key) {
+            Some(name) => name.clone(),
+            None => return Ok(()),
+        };
+        let mut params = BTreeMap::new();
+        params.insert("domain".to_string(), Json::String(key.0));
+        params.insert("var".to_string(), Json::String(name));
+        params.insert("value".to_string(), value.to_json());
+        let mut notification = BTreeMap::new();
+        notification.insert("jsonrpc".to_string(), Json::String("2.0".to_string()));
+        notification.insert("method".to_string(), Json::String("event".to_string()));
+        notification.insert("params".to_string(), Json::Object(params));
+        self.send(&Json::Object(notification))
+    }
+}
+
+/// Obtains a parameter given either by name or by position.
+fn param<'a>(params: &'a Json, name: &str, index: usize) -> Option<&'a Json> {
+    match *params {
+        Json::Object(ref params) => params.get(name),
+        Json::Array(ref params) => params.get(index),
+        _ => None,
+    }
+}
+
+/// Obtains the domain, the name and the variable referenced by the given parameters.
+fn var_params(params: &Json) -> Result<(String, String, Var), RpcError> {
+    let domain = param(params, "domain", 0).and_then(|d| d.as_string());
+    let name = param(params, "var", 1).and_then(|v| v.as_string());
+    match (domain, name) {
+        (Some(domain), Some(name)) => {
+            let variable = try!(domain_var(domain, name));
+            Ok((domain.to_string(), name.to_string(), variable))
+        }
+        _ => Err(RpcError::new(INVALID_PARAMS, "missing or invalid domain or var")),
+    }
+}
+
+fn success_response(id: Json, result: Json) -> Json {
+    let mut response = BTreeMap::new();
+    response.insert("jsonrpc".to_string(), Json::String("2.0".to_string()));
+    response.insert("id".to_string(), id);
+    response.insert("result".to_string(), result);
+    Json::Object(response)
+}
+
+fn error_response(id: Json, error: RpcError) -> Json {
+    let mut response = BTreeMap::new();
+    response.insert("jsonrpc".to_string(), Json::String("2.0".to_string()));
+    response.insert("id".to_string(), id);
+    response.insert("error".to_string(), error.to_json());
+    Json::Object(response)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::io;
+
+    use domain::*;
+    use io::*;
+    use proto::test_util::*;
+
+    use super::*;
+
+    #[test]
+    fn should_map_io_errors_to_rpc_errors() {
+        let error = io::Error::new(io::ErrorKind::NotFound, "no such domain 'foo'");
+        assert_eq!(RpcError::from(error), RpcError::new(NOT_FOUND, "no such domain 'foo'"));
+        let error = io::Error::new(io::ErrorKind::InvalidInput, "invalid offset");
+        assert_eq!(RpcError::from(error).code, INVALID_PARAMS);
+        let error = io::Error::new(io::ErrorKind::Other, "oops");
+        assert_eq!(RpcError::from(error).code, INTERNAL_ERROR);
+    }
+
+    #[test]
+    fn should_write_and_read_variables() {
+        let (mut reactor, mut client, lvars) = rpc_client();
+        lvars.define("foobar", 0.0);
+        assert_eq!(
+            call(&mut reactor, &mut client,
+                r#"{"jsonrpc":"2.0","id":1,"method":"write","params":["lvar","foobar",42]}"#),
+            r#"{"id":1,"jsonrpc":"2.0","result":true}"#);
+        reactor.poll_domains();
+        assert_eq!(lvars.value("foobar"), Some(42.0));
+        assert_eq!(
+            call(&mut reactor, &mut client,
+                r#"{"jsonrpc":"2.0","id":"r","method":"read","params":{"domain":"lvar","var":"foobar"}}"#),
+            r#"{"id":"r","jsonrpc":"2.0","result":42}"#);
+    }
+
+    #[test]
+    fn should_send_event_notifications() {
+        let (mut reactor, mut client, lvars) = rpc_client();
+        lvars.define("foobar", 42.0);
+        call(&mut reactor, &mut client,
+            r#"{"jsonrpc":"2.0","method":"subscribe","params":{"domain":"lvar","var":"foobar"}}"#);
+        reactor.poll_domains();
+        reactor.run();
+        assert_eq!(
+            String::from_utf8(client.recv().unwrap()).unwrap(),
+            "{\"jsonrpc\":\"2.0\",\"method\":\"event\",\
+            \"params\":{\"domain\":\"lvar\",\"value\":42,\"var\":\"foobar\"}}\n");
+    }
+
+    #[test]
+    fn should_not_send_events_after_unsubscribe() {
+        let (mut reactor, mut client, lvars) = rpc_client();
+        lvars.define("foobar", 42.0);
+        call(&mut reactor, &mut client,
+            r#"{"jsonrpc":"2.0","method":"subscribe","params":["lvar","foobar"]}"#);
+        call(&mut reactor, &mut client,
+            r#"{"jsonrpc":"2.0","method":"unsubscribe","params":["lvar","foobar"]}"#);
+        reactor.poll_domains();
+        reactor.run();
+        assert!(client.recv().unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_answer_errors() {
+        let (mut reactor, mut client, _) = rpc_client();
+        let response = call(&mut reactor, &mut client, "foobar");
+        assert!(response.starts_with(r#"{"error":{"code":-32700,"#));
+        assert!(response.ends_with(r#""id":null,"jsonrpc":"2.0"}"#));
+        assert!(call(&mut reactor, &mut client, r#"{"id":1,"method":"read"}"#)
+            .contains("-32600"));
+        assert!(call(&mut reactor, &mut client, r#"{"jsonrpc":"2.0","id":1,"method":"foo"}"#)
+            .contains("-32601"));
+        assert!(call(&mut reactor, &mut client,
+            r#"{"jsonrpc":"2.0","id":1,"method":"read","params":["fsuipc","foo"]}"#)
+            .contains("-32602"));
+        assert!(call(&mut reactor, &mut client,
+            r#"{"jsonrpc":"2.0","id":1,"method":"read","params":["lvar","foo"]}"#)
+            .contains("-32001"));
+    }
+
+    #[test]
+    fn should_process_batches() {
+        let (mut reactor, mut client, lvars) = rpc_client();
+        lvars.define("foobar", 7.0);
+        let response = call(&mut reactor, &mut client,
+            r#"[{"jsonrpc":"2.0","id":1,"method":"read","params":["lvar","foobar"]},
+            {"jsonrpc":"2.0","method":"write","params":["lvar","foobar",1]}]"#);
+        assert_eq!(response, r#"[{"id":1,"jsonrpc":"2.0","result":7}]"#);
+    }
+
+    fn rpc_client() -> (TestReactor, Loopback, lvar::MemoryBackend) {
+        let (mut domains, lvars) = lvar_domains();
+        domains.add("fsuipc", fsuipc::Fsuipc::new(fsuipc::MemoryBackend::new()));
+        let (dev, client) = loopback("jsonrpc").unwrap();
+        let mut reactor = TestReactor::new(domains);
+        reactor.attach(|domains| JsonRpc::new(dev, domains));
+        (reactor, client, lvars)
+    }
+
+    fn call(reactor: &mut TestReactor, client: &mut Loopback, request: &str) -> String {
+        let line = request.replace("\n", "") + "\n";
+        client.send(line.as_bytes()).unwrap();
+        reactor.run();
+        let response = String::from_utf8(client.recv().unwrap()).unwrap();
+        response.trim().to_string()
+    }
+}
@@ -8,9 +8,20 @@
 
 use std::io;
 
+mod binary;
+mod http;
+mod jsonrpc;
+mod mqtt;
 mod oacsp;
+mod rest;
+mod ws;
 
-pub use self::oacsp::Oacsp;
+pub use self::binary::Binary;
+pub use self::jsonrpc::JsonRpc;
+pub use self::mqtt::{Broker, Mqtt, MqttBridge};
+pub use self::oacsp::{Oacsp, OacspDiscovery, OacspUdp};
+pub use self::rest::Rest;
+pub use self::ws::WebSocket;
 
 use io::DeviceHandler;
 use types::{Value, Var};
@@ -18,4 +29,124 @@ use types::{Value, Var};
 pub trait Protocol : DeviceHandler {
 
 	fn send_update(&mut self, domain: &str, variable: Var, value: Value) -> io::Result<()>;    
+
+	/// Performs periodic tasks, like sending keep-alive messages.
+	fn tick(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+/// Maps a variable name onto a variable of the given domain.
+///
+/// FSUIPC offsets are written as in OACSP (e.g., `0BC8+2`), while any other domain
+/// uses named variables.
+fn domain_var(domain: &str, name: &str) -> io::Result<Var> {
+    if name.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty variable name"));
+    }
+    if domain == "fsuipc" {
+        Ok(Var::Offset(try!(name.parse())))
+    } else {
+        Ok(Var::Named(name.to_string()))
+    }
+}
+
+/// Splits a domain-qualified variable name into the domain and the variable name.
+///
+/// As in MQTT topics, the address and the size of FSUIPC offsets may also be
+/// separated by `/` (e.g., `fsuipc/0BC8/2`).
+fn qualified_var(qualified: &str) -> io::Result<(String, String)> {
+    let mut parts = qualified.splitn(2, '/');
+    match (parts.next(), parts.next()) {
+        (Some("fsuipc"), Some(name)) => Ok(("fsuipc".to_string(), name.replace("/", "+"))),
+        (Some(domain), Some(name)) if !domain.is_empty() =>
+            Ok((domain.to_string(), name.to_string())),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("variable '{}' is not qualified by its domain", qualified))),
+    }
+}
+
+/// Fixture shared by the tests of the protocols.
+#[cfg(test)]
+mod test_util {
+
+    use std::boxed::Box;
+    use std::time::Duration;
+
+    use config::DomainSettings;
+    use domain::*;
+    use io::*;
+    use proto::Protocol;
+
+    /// A reactor for protocol handlers and the domains they operate on.
+    pub struct TestReactor {
+        pub reactor: Reactor<Box<Protocol>>,
+        pub domains: DomainDispatcher,
+    }
+
+    impl TestReactor {
+        pub fn new(domains: DomainDispatcher) -> TestReactor {
+            TestReactor { reactor: Reactor::new().unwrap(), domains: domains }
+        }
+
+        /// Attaches the handler built from the dispatcher of this reactor.
+        pub fn attach<P, F>(&mut self, make_protocol: F)
+            where P: Protocol + 'static, F: FnOnce(DomainDispatcher) -> P {
+            let protocol = make_protocol(self.domains.clone());
+            self.reactor.attach(Box::new(protocol) as Box<Protocol>).unwrap();
+        }
+
+        pub fn run(&mut self) {
+            run(&mut self.reactor);
+        }
+
+        /// Polls the domains and sends the resulting updates to their handlers.
+        pub fn poll_domains(&mut self) {
+            let mut events = Vec::new();
+            self.domains.with_all_domains(|d| d.poll(&mut events)).unwrap();
+            for ev in events {
+                let handler = self.reactor.handler(&ev.device).unwrap();
+                handler.send_update(&ev.domain, ev.variable, ev.value).unwrap();
+            }
+        }
+    }
+
+    /// Returns a dispatcher with a `lvar` domain backed by memory.
+    pub fn lvar_domains() -> (DomainDispatcher, lvar::MemoryBackend) {
+        let lvars = lvar::MemoryBackend::new();
+        let mut domains = DomainDispatcher::new(&DomainSettings { enabled: vec![] }).unwrap();
+        domains.add("lvar", lvar::LVar::new(lvars.clone()));
+        (domains, lvars)
+    }
+
+    /// Processes the events of the reactor for a while.
+    pub fn run(reactor: &mut Reactor<Box<Protocol>>) {
+        run_with(reactor, |_| {});
+    }
+
+    /// Processes the events of the reactor for a while, calling `f` after each one.
+    pub fn run_with<F>(reactor: &mut Reactor<Box<Protocol>>, mut f: F)
+        where F: FnMut(&mut Reactor<Box<Protocol>>) {
+        for _ in 0..10 {
+            let _ = reactor.process_event(&Duration::from_millis(10));
+            f(reactor);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::qualified_var;
+
+    #[test]
+    fn should_split_qualified_vars() {
+        assert_eq!(
+            qualified_var("lvar/A320_Gear_Lever").unwrap(),
+            ("lvar".to_string(), "A320_Gear_Lever".to_string()));
+        assert_eq!(
+            qualified_var("fsuipc/0BC8/2").unwrap(),
+            ("fsuipc".to_string(), "0BC8+2".to_string()));
+        assert!(qualified_var("foobar").is_err());
+        assert!(qualified_var("/foobar").is_err());
+    }
 }
@@ -0,0 +1,575 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::boxed::Box;
+use std::cell::Cell;
+use std::cmp;
+use std::io;
+use std::net;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use config::MqttBridgeSettings;
+use domain::DomainDispatcher;
+use io::*;
+use proto::*;
+use types::*;
+
+use super::{payload_value, topic_var};
+use super::packet::*;
+
+const MIN_BACKOFF_SECS: u64 = 1;
+const MAX_BACKOFF_SECS: u64 = 60;
+const CONNECT_TIMEOUT_SECS: u64 = 10;
+
+/// A variable published to the external broker.
+#[derive(Clone)]
+struct Published {
+    domain: String,
+    variable: Var,
+    topic: String,
+}
+
+/// The options of a bridge, shared by all its connections.
+#[derive(Clone)]
+struct BridgeOptions {
+    client_id: String,
+    prefix: String,
+    keep_alive: u16,
+    published: Vec<Published>,
+}
+
+impl BridgeOptions {
+    fn status_topic(&self) -> String {
+        format!("{}/status", self.prefix)
+    }
+}
+
+/// A bridge to an external MQTT broker.
+///
+/// The bridge connects to the broker as a regular client. The configured variables are
+/// published as retained messages under the topic prefix (e.g., `flightvars/lvar/foo`),
+/// and the messages published by other clients to `<prefix>/<domain>/<variable>/set`
+/// are written to the domains. The status of the bridge is published to
+/// `<prefix>/status` as `online` when connected, and the broker publishes `offline`
+/// as the bridge last will.
+///
+/// The connection is not attached to the reactor by the bridge owner, but by the
+/// bridge itself when `poll()` is called. The TCP connection is established in a
+/// separate thread, so an unreachable broker does not block the reactor. Lost
+/// connections are reestablished with an exponential backoff.
+pub struct MqttBridge {
+    broker: String,
+    options: BridgeOptions,
+    connection: Option<(DeviceId, Rc<Cell<bool>>)>,
+    connecting: Option<(mpsc::Receiver<io::Result<net::TcpStream>>, Instant)>,
+    backoff: Duration,
+    next_attempt: Instant,
+}
+
+impl MqttBridge {
+
+    pub fn new(settings: &MqttBridgeSettings) -> io::Result<MqttBridge> {
+        let mut published = Vec::with_capacity(settings.publish.len());
+        for topic in &settings.publish {
+            let (domain, variable) = try!(topic_var(topic));
+            published.push(Published {
+                domain: domain,
+                variable: variable,
+                topic: format!("{}/{}", settings.prefix, topic),
+            });
+        }
+        Ok(MqttBridge {
+            broker: settings.broker.clone(),
+            options: BridgeOptions {
+                client_id: settings.client_id.clone(),
+                prefix: settings.prefix.clone(),
+                keep_alive: settings.keep_alive,
+                published: published,
+            },
+            connection: None,
+            connecting: None,
+            backoff: Duration::from_secs(MIN_BACKOFF_SECS),
+            next_attempt: Instant::now(),
+        })
+    }
+
+    pub fn broker(&self) -> &str { &self.broker }
+
+    /// Checks the connection to the broker, reconnecting if needed.
+    pub fn poll(&mut self, reactor: &mut Reactor<Box<Protocol>>, domains: &mut DomainDispatcher) {
+        if let Some((id, online)) = self.connection.take() {
+            if let Some(handler) = reactor.handler(&id) {
+                if let Err(e) = handler.tick() {
+                    error!("unexpected error in connection to MQTT broker {}: {:?}", self.broker, e);
+                }
+                self.connection = Some((id, online));
+                return;
+            }
+            info!("connection to MQTT broker {} was lost", self.broker);
+            if online.get() {
+                self.backoff = Duration::from_secs(MIN_BACKOFF_SECS);
+            }
+            self.schedule_reconnection();
+            return;
+        }
+        if let Some((receiver, deadline)) = self.connecting.take() {
+            let result = match receiver.try_recv() {
+                Ok(result) => result.and_then(|stream| self.attach(stream, reactor, domains)),
+                Err(mpsc::TryRecvError::Empty) if Instant::now() < deadline => {
+                    self.connecting = Some((receiver, deadline));
+                    return;
+                }
+                Err(mpsc::TryRecvError::Empty) => Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "connection attempt timed out")),
+                Err(mpsc::TryRecvError::Disconnected) => Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "connection attempt was aborted")),
+            };
+            match result {
+                Ok(connection) => {
+                    info!("connected to MQTT broker {}", self.broker);
+                    self.connection = Some(connection);
+                }
+                Err(e) => {
+                    error!("cannot connect to MQTT broker {}: {:?}", self.broker, e);
+                    self.schedule_reconnection();
+                }
+            }
+            return;
+        }
+        if Instant::now() >= self.next_attempt {
+            self.connect();
+        }
+    }
+
+    /// Schedules the next connection attempt, doubling the backoff for the next one.
+    fn schedule_reconnection(&mut self) {
+        info!("reconnecting to MQTT broker {} in {} seconds",
+            self.broker, self.backoff.as_secs());
+        self.next_attempt = Instant::now() + self.backoff;
+        let backoff = cmp::min(self.backoff.as_secs() * 2, MAX_BACKOFF_SECS);
+        self.backoff = Duration::from_secs(backoff);
+    }
+
+    /// Starts a connection attempt, whose outcome is checked by the next polls.
+    fn connect(&mut self) {
+        debug!("connecting to MQTT broker {}", self.broker);
+        let (sender, receiver) = mpsc::channel();
+        let broker = self.broker.clone();
+        thread::spawn(move || {
+            let _ = sender.send(net::TcpStream::connect(&broker[..]));
+        });
+        let deadline = Instant::now() + Duration::from_secs(CONNECT_TIMEOUT_SECS);
+        self.connecting = Some((receiver, deadline));
+    }
+
+    fn attach(&self,
+              stream: net::TcpStream,
+              reactor: &mut Reactor<Box<Protocol>>,
+              domains: &DomainDispatcher) -> io::Result<(DeviceId, Rc<Cell<bool>>)> {
+        try!(stream.set_nodelay(true));
+        let dev = try!(Device::from_tcp_stream(stream));
+        let online = Rc::new(Cell::new(false));
+        let client = BridgeClient::new(dev, domains.clone(), self.options.clone(), online.clone());
+        let id = try!(reactor.attach(Box::new(client)));
+        Ok((id, online))
+    }
+}
+
+/// The connection of a bridge to the external broker.
+struct BridgeClient {
+    dev: Device,
+    domains: DomainDispatcher,
+    options: BridgeOptions,
+    online: Rc<Cell<bool>>,
+    last_sent: Instant,
+    next_packet_id: u16,
+}
+
+impl BridgeClient {
+
+    fn new(dev: Device,
+           domains: DomainDispatcher,
+           options: BridgeOptions,
+           online: Rc<Cell<bool>>) -> BridgeClient {
+        BridgeClient {
+            dev: dev,
+            domains: domains,
+            options: options,
+            online: online,
+            last_sent: Instant::now(),
+            next_packet_id: 1,
+        }
+    }
+
+    fn process_input(&mut self) -> io::Result<()> {
+        while let Some((packet, nbytes)) = try!(Packet::decode(self.dev.recv_bytes())) {
+            self.dev.consume_recv_buffer(nbytes);
+            try!(self.process_packet(packet));
+        }
+        Ok(())
+    }
+
+    fn process_packet(&mut self, packet: Packet) -> io::Result<()> {
+        match packet {
+            Packet::ConnAck { return_code: CONNACK_ACCEPTED, .. } => self.start_session(),
+            Packet::ConnAck { return_code, .. } => {
+                Err(io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    format!("MQTT broker refused the connection with code {}", return_code)))
+            }
+            Packet::Publish { topic, qos, packet_id, payload, .. } => {
+                self.process_publish(&topic, &payload);
+                match (qos, packet_id) {
+                    (1, Some(id)) => self.send(&Packet::PubAck(id)),
+                    (2, Some(id)) => self.send(&Packet::PubRec(id)),
+                    _ => Ok(()),
+                }
+            }
+            Packet::PubRel(id) => self.send(&Packet::PubComp(id)),
+            Packet::SubAck { return_codes, .. } => {
+                if return_codes.contains(&SUBACK_FAILURE) {
+                    error!("MQTT broker refused the subscription to {}/#",
+                        self.options.prefix);
+                }
+                Ok(())
+            }
+            Packet::PubAck(_) | Packet::PubRec(_) | Packet::PubComp(_) | Packet::PingResp => Ok(()),
+            other => {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected packet from MQTT broker: {:?}", other)))
+            }
+        }
+    }
+
+    fn start_session(&mut self) -> io::Result<()> {
+        self.online.set(true);
+        let status = Packet::Publish {
+            topic: self.options.status_topic(),
+            qos: 0,
+            retain: true,
+            dup: false,
+            packet_id: None,
+            payload: b"online".to_vec(),
+        };
+        try!(self.send(&status));
+        // Variables may take several topic levels (e.g., `fsuipc/0BC8/2`), so the
+        // `set` topics are filtered once received
+        let packet_id = self.packet_id();
+        let subscribe = Packet::Subscribe {
+            packet_id: packet_id,
+            filters: vec![(format!("{}/#", self.options.prefix), 1)],
+        };
+        try!(self.send(&subscribe));
+        let dev_id = self.dev.id();
+        for published in &self.options.published {
+            let variable = &published.variable;
+            try!(self.domains.with_domain(&published.domain, |dom| dom.subscribe(dev_id, variable)));
+        }
+        Ok(())
+    }
+
+    fn process_publish(&mut self, topic: &str, payload: &[u8]) {
+        let prefix = format!("{}/", self.options.prefix);
+        if !topic.starts_with(&prefix) || !topic.ends_with("/set") {
+            return;
+        }
+        let var_topic = &topic[prefix.len()..topic.len() - 4];
+        let result = topic_var(var_topic).and_then(|(domain, variable)| {
+            let value = try!(payload_value(payload));
+            debug!("MQTT broker requests {} <- {}", var_topic, value);
+            self.domains.with_domain(&domain, |dom| dom.write(&variable, &value))
+        });
+        if let Err(e) = result {
+            error!("cannot process publish from MQTT broker to topic {}: {:?}", topic, e);
+        }
+    }
+
+    fn send(&mut self, packet: &Packet) -> io::Result<()> {
+        let mut buf = Vec::new();
+        try!(packet.encode(&mut buf));
+        self.last_sent = Instant::now();
+        self.dev.request_write(&buf)
+    }
+
+    fn packet_id(&mut self) -> u16 {
+        let id = self.next_packet_id;
+        self.next_packet_id = if id == u16::max_value() { 1 } else { id + 1 };
+        id
+    }
+}
+
+impl DeviceHandler for BridgeClient {
+    fn device(&mut self) -> &mut Device { &mut self.dev }
+
+    fn process_event(&mut self, event: Event) -> io::Result<()> {
+        match event {
+            Event::Ready => {
+                let connect = Packet::Connect {
+                    protocol: "MQTT".to_string(),
+                    level: PROTOCOL_LEVEL,
+                    client_id: self.options.client_id.clone(),
+                    clean_session: true,
+                    keep_alive: self.options.keep_alive,
+                    will: Some(Will {
+                        topic: self.options.status_topic(),
+                        payload: b"offline".to_vec(),
+                        qos: 1,
+                        retain: true,
+                    }),
+                };
+                try!(self.send(&connect));
+                self.dev.request_read()
+            }
+            Event::BytesRead(0) => {
+                info!("connection to MQTT broker was closed by the broker");
+                self.dev.close()
+            }
+            Event::BytesRead(_) => {
+                try!(self.process_input());
+                self.dev.request_read()
+            }
+            Event::BytesWritten(_) => Ok(()),
+            Event::Closed => {
+                let dev_id = self.dev.id();
+                self.domains.unsubscribe_all(dev_id)
+            }
+        }
+    }
+}
+
+impl Protocol for BridgeClient {
+
+    fn send_update(&mut self, domain: &str, variable: Var, value: Value) -> io::Result<()> {
+        let topic = self.options.published.iter()
+            .find(|p| p.domain == domain && p.variable == variable)
+            .map(|p| p.topic.clone());
+        match topic {
+            Some(topic) => self.send(&Packet::Publish {
+                topic: topic,
+                qos: 0,
+                retain: true,
+                dup: false,
+                packet_id: None,
+                payload: format!("{}", value).into_bytes(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn tick(&mut self) -> io::Result<()> {
+        let keep_alive = Duration::from_secs(self.options.keep_alive as u64);
+        if self.options.keep_alive > 0 && self.last_sent.elapsed() >= keep_alive / 2 {
+            try!(self.send(&Packet::PingReq));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::{Read, Write};
+    use std::net;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use config::MqttBridgeSettings;
+    use domain::*;
+    use io::*;
+    use proto::*;
+    use proto::test_util::*;
+
+    use super::*;
+
+    #[test]
+    fn should_connect_with_last_will() {
+        let mut test = BridgeTest::new();
+        let mut broker = test.connect();
+        match broker.recv().remove(0) {
+            Packet::Connect { client_id, will, .. } => {
+                assert_eq!(client_id, "flightvars");
+                assert_eq!(will, Some(Will {
+                    topic: "sim/status".to_string(),
+                    payload: b"offline".to_vec(),
+                    qos: 1,
+                    retain: true,
+                }));
+            }
+            other => panic!("unexpected packet {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_publish_status_and_subscribe_on_connack() {
+        let mut test = BridgeTest::new();
+        let mut broker = test.connect();
+        broker.recv();
+        broker.send(&Packet::ConnAck { session_present: false, return_code: 0 });
+        test.run();
+        assert_eq!(broker.recv(), vec![
+            Packet::Publish {
+                topic: "sim/status".to_string(),
+                qos: 0,
+                retain: true,
+                dup: false,
+                packet_id: None,
+                payload: b"online".to_vec(),
+            },
+            Packet::Subscribe { packet_id: 1, filters: vec![("sim/#".to_string(), 1)] },
+        ]);
+    }
+
+    #[test]
+    fn should_write_set_messages() {
+        let mut test = BridgeTest::new();
+        let mut broker = test.connect();
+        broker.send(&Packet::ConnAck { session_present: false, return_code: 0 });
+        broker.send(&Packet::Publish {
+            topic: "sim/lvar/foobar/set".to_string(),
+            qos: 0,
+            retain: false,
+            dup: false,
+            packet_id: None,
+            payload: b"42".to_vec(),
+        });
+        test.run();
+        let mut events = Vec::new();
+        test.domains.with_all_domains(|d| d.poll(&mut events)).unwrap();
+        assert_eq!(test.lvars.value("foobar"), Some(42.0));
+    }
+
+    #[test]
+    fn should_write_set_messages_of_multilevel_variables() {
+        let mut test = BridgeTest::new();
+        test.lvars.define("A320/Gear", 0.0);
+        let mut broker = test.connect();
+        broker.send(&Packet::ConnAck { session_present: false, return_code: 0 });
+        broker.send(&Packet::Publish {
+            topic: "sim/lvar/A320/Gear/set".to_string(),
+            qos: 0,
+            retain: false,
+            dup: false,
+            packet_id: None,
+            payload: b"1".to_vec(),
+        });
+        test.run();
+        let mut events = Vec::new();
+        test.domains.with_all_domains(|d| d.poll(&mut events)).unwrap();
+        assert_eq!(test.lvars.value("A320/Gear"), Some(1.0));
+    }
+
+    #[test]
+    fn should_back_off_when_broker_is_unavailable() {
+        let mut test = BridgeTest::new();
+        drop(test.listener.take());
+        test.poll_connecting();
+        assert!(test.bridge.connection.is_none());
+        assert_eq!(test.bridge.backoff, Duration::from_secs(2));
+        assert!(test.bridge.next_attempt > Instant::now());
+        test.bridge.poll(&mut test.reactor, &mut test.domains);
+        assert_eq!(test.bridge.backoff, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn should_schedule_reconnection_when_connection_is_lost() {
+        let mut test = BridgeTest::new();
+        let broker = test.connect();
+        drop(broker);
+        test.run();
+        test.bridge.poll(&mut test.reactor, &mut test.domains);
+        assert!(test.bridge.connection.is_none());
+        assert!(test.bridge.next_attempt > Instant::now());
+    }
+
+    struct BridgeTest {
+        listener: Option<net::TcpListener>,
+        bridge: MqttBridge,
+        reactor: Reactor<Box<Protocol>>,
+        domains: DomainDispatcher,
+        lvars: lvar::MemoryBackend,
+    }
+
+    impl BridgeTest {
+        fn new() -> BridgeTest {
+            let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let settings = MqttBridgeSettings {
+                broker: format!("{}", listener.local_addr().unwrap()),
+                client_id: "flightvars".to_string(),
+                prefix: "sim".to_string(),
+                keep_alive: 30,
+                publish: vec!["lvar/foobar".to_string()],
+            };
+            let (domains, lvars) = lvar_domains();
+            lvars.define("foobar", 0.0);
+            BridgeTest {
+                listener: Some(listener),
+                bridge: MqttBridge::new(&settings).unwrap(),
+                reactor: Reactor::new().unwrap(),
+                domains: domains,
+                lvars: lvars,
+            }
+        }
+
+        fn connect(&mut self) -> FakeBroker {
+            self.bridge.poll(&mut self.reactor, &mut self.domains);
+            let (stream, _) = self.listener.as_ref().unwrap().accept().unwrap();
+            stream.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+            self.poll_connecting();
+            assert!(self.bridge.connection.is_some());
+            self.run();
+            FakeBroker { stream: stream }
+        }
+
+        /// Polls the bridge until its connection attempt finishes.
+        fn poll_connecting(&mut self) {
+            self.bridge.poll(&mut self.reactor, &mut self.domains);
+            while self.bridge.connecting.is_some() {
+                thread::sleep(Duration::from_millis(1));
+                self.bridge.poll(&mut self.reactor, &mut self.domains);
+            }
+        }
+
+        fn run(&mut self) {
+            run(&mut self.reactor);
+        }
+    }
+
+    struct FakeBroker {
+        stream: net::TcpStream,
+    }
+
+    impl FakeBroker {
+        fn send(&mut self, packet: &Packet) {
+            let mut buf = Vec::new();
+            packet.encode(&mut buf).unwrap();
+            self.stream.write_all(&buf).unwrap();
+        }
+
+        fn recv(&mut self) -> Vec<Packet> {
+            let mut data = Vec::new();
+            let mut buf = [0; 1024];
+            while let Ok(n) = self.stream.read(&mut buf) {
+                if n == 0 { break; }
+                data.extend_from_slice(&buf[..n]);
+            }
+            let mut packets = Vec::new();
+            let mut offset = 0;
+            while let Some((packet, nbytes)) = Packet::decode(&data[offset..]).unwrap() {
+                packets.push(packet);
+                offset += nbytes;
+            }
+            packets
+        }
+    }
+}
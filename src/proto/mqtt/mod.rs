@@ -0,0 +1,514 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! An embedded MQTT 3.1.1 broker.
+//!
+//! Topics are mapped onto domain variables. The first level of the topic is the name
+//! of the domain, and the rest is the variable. FSUIPC offsets are written as in OACSP,
+//! with the address and the size separated either by `+` or `/` (e.g., `fsuipc/0BC8+2`
+//! or `fsuipc/0BC8/2`). Any other domain uses named variables (e.g.,
+//! `lvar/A320_Gear_Lever`). Payloads are the values as text, like `42` or `true`.
+//!
+//! Publishing to a topic writes the variable, and subscribing to a topic subscribes
+//! to the variable in its domain. Wildcards are not supported in topic filters.
+
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::HashMap;
+use std::io;
+use std::rc::Rc;
+use std::str;
+
+use domain::DomainDispatcher;
+use io::*;
+use proto::*;
+use types::*;
+
+mod bridge;
+mod packet;
+
+pub use self::bridge::MqttBridge;
+pub use self::packet::*;
+
+/// The state shared by all the clients connected to the broker.
+///
+/// The broker keeps the last value published for each variable. It is sent as a
+/// retained message to any client subscribing to the variable afterwards.
+#[derive(Clone)]
+pub struct Broker {
+    retained: Rc<RefCell<HashMap<(String, Var), Value>>>,
+}
+
+impl Broker {
+    pub fn new() -> Broker {
+        Broker { retained: Rc::new(RefCell::new(HashMap::new())) }
+    }
+
+    fn retain(&self, domain: &str, variable: &Var, value: Value) {
+        self.retained.borrow_mut().insert((domain.to_string(), variable.clone()), value);
+    }
+
+    fn retained(&self, domain: &str, variable: &Var) -> Option<Value> {
+        self.retained.borrow().get(&(domain.to_string(), variable.clone())).cloned()
+    }
+}
+
+struct Subscription {
+    topic: String,
+    qos: u8,
+}
+
+pub struct Mqtt {
+    dev: Device,
+    domains: DomainDispatcher,
+    broker: Broker,
+    client_id: Option<String>,
+    subscriptions: HashMap<(String, Var), Subscription>,
+    next_packet_id: u16,
+    close_on_written: bool,
+}
+
+impl Mqtt {
+
+    pub fn new(dev: Device, domains: DomainDispatcher, broker: Broker) -> Mqtt {
+        Mqtt {
+            dev: dev,
+            domains: domains,
+            broker: broker,
+            client_id: None,
+            subscriptions: HashMap::new(),
+            next_packet_id: 1,
+            close_on_written: false,
+        }
+    }
+
+    fn process_input(&mut self) -> io::Result<()> {
+        loop {
+            let decoded = try!(Packet::decode(self.dev.recv_bytes()));
+            match decoded {
+                Some((packet, nbytes)) => {
+                    self.dev.consume_recv_buffer(nbytes);
+                    try!(self.process_packet(packet));
+                    if self.dev.is_closed() {
+                        return Ok(());
+                    }
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn process_packet(&mut self, packet: Packet) -> io::Result<()> {
+        if self.client_id.is_none() {
+            return self.process_connect(packet);
+        }
+        match packet {
+            Packet::Publish { topic, qos, packet_id, payload, .. } => {
+                self.process_publish(&topic, &payload);
+                match (qos, packet_id) {
+                    (1, Some(id)) => self.send(&Packet::PubAck(id)),
+                    (2, Some(id)) => self.send(&Packet::PubRec(id)),
+                    _ => Ok(()),
+                }
+            }
+            Packet::PubRel(id) => self.send(&Packet::PubComp(id)),
+            Packet::PubAck(_) | Packet::PubRec(_) | Packet::PubComp(_) => Ok(()),
+            Packet::Subscribe { packet_id, filters } => {
+                self.process_subscribe(packet_id, filters)
+            }
+            Packet::Unsubscribe { packet_id, filters } => {
+                self.process_unsubscribe(packet_id, filters)
+            }
+            Packet::PingReq => self.send(&Packet::PingResp),
+            Packet::Disconnect => {
+                info!("MQTT client {} disconnected", self.client_id_str());
+                self.dev.close()
+            }
+            other => {
+                let error = io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected MQTT packet from client {}: {:?}",
+                        self.client_id_str(), other));
+                Err(error)
+            }
+        }
+    }
+
+    fn process_connect(&mut self, packet: Packet) -> io::Result<()> {
+        match packet {
+            Packet::Connect { protocol, level, client_id, .. } => {
+                if protocol != "MQTT" || level != PROTOCOL_LEVEL {
+                    error!("MQTT client {} uses an unsupported protocol {} level {}",
+                        client_id, protocol, level);
+                    // The connection is closed once the client receives the response
+                    self.close_on_written = true;
+                    return self.send(&Packet::ConnAck {
+                        session_present: false,
+                        return_code: CONNACK_UNACCEPTABLE_PROTOCOL,
+                    });
+                }
+                info!("MQTT client {} connected from {}", client_id, self.dev.name());
+                self.client_id = Some(client_id);
+                self.send(&Packet::ConnAck {
+                    session_present: false,
+                    return_code: CONNACK_ACCEPTED,
+                })
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected MQTT packet while waiting for connect")),
+        }
+    }
+
+    fn process_publish(&mut self, topic: &str, payload: &[u8]) {
+        let result = topic_var(topic).and_then(|(domain, variable)| {
+            let value = try!(payload_value(payload));
+            debug!("client {} publishes {} <- {}", self.client_id_str(), topic, value);
+            self.domains.with_domain(&domain, |dom| dom.write(&variable, &value))
+        });
+        // MQTT 3.1.1 has no way to report a failed publish, so it is just logged
+        if let Err(e) = result {
+            error!("cannot process publish from client {} to topic {}: {:?}",
+                self.client_id_str(), topic, e);
+        }
+    }
+
+    fn process_subscribe(&mut self, packet_id: u16, filters: Vec<(String, u8)>) -> io::Result<()> {
+        let dev_id = self.dev.id();
+        let mut return_codes = Vec::with_capacity(filters.len());
+        let mut granted = Vec::new();
+        for (filter, qos) in filters {
+            let key = match topic_var(&filter) {
+                Ok(key) => key,
+                Err(e) => {
+                    error!("cannot subscribe client {} to {}: {:?}",
+                        self.client_id_str(), filter, e);
+                    return_codes.push(SUBACK_FAILURE);
+                    continue;
+                }
+            };
+            if !self.subscriptions.contains_key(&key) {
+                let result = self.domains.with_domain(&key.0, |dom| dom.subscribe(dev_id, &key.1));
+                if let Err(e) = result {
+                    error!("cannot subscribe client {} to {}: {:?}",
+                        self.client_id_str(), filter, e);
+                    return_codes.push(SUBACK_FAILURE);
+                    continue;
+                }
+            }
+            let qos = cmp::min(qos, 1);
+            debug!("client {} subscribed to {} with QoS {}", self.client_id_str(), filter, qos);
+            self.subscriptions.insert(key.clone(), Subscription { topic: filter, qos: qos });
+            return_codes.push(qos);
+            granted.push(key);
+        }
+        try!(self.send(&Packet::SubAck { packet_id: packet_id, return_codes: return_codes }));
+        for (domain, variable) in granted {
+            if let Some(value) = self.broker.retained(&domain, &variable) {
+                try!(self.publish(&domain, &variable, value, true));
+            }
+        }
+        Ok(())
+    }
+
+    fn process_unsubscribe(&mut self, packet_id: u16, filters: Vec<String>) -> io::Result<()> {
+        let dev_id = self.dev.id();
+        for filter in filters {
+            let key = match topic_var(&filter) {
+                Ok(key) => key,
+                Err(_) => continue,
+            };
+            if self.subscriptions.remove(&key).is_some() {
+                let result = self.domains.with_domain(&key.0, |dom| dom.unsubscribe(dev_id, &key.1));
+                if let Err(e) = result {
+                    error!("cannot unsubscribe client {} from {}: {:?}",
+                        self.client_id_str(), filter, e);
+                }
+                debug!("client {} unsubscribed from {}", self.client_id_str(), filter);
+            }
+        }
+        self.send(&Packet::UnsubAck(packet_id))
+    }
+
+    fn publish(&mut self, domain: &str, variable: &Var, value: Value, retain: bool) -> io::Result<()> {
+        let (topic, qos) = match self.subscriptions.get(&(domain.to_string(), variable.clone())) {
+            Some(sub) => (sub.topic.clone(), sub.qos),
+            None => return Ok(()),
+        };
+        let packet_id = if qos > 0 { Some(self.packet_id()) } else { None };
+        self.send(&Packet::Publish {
+            topic: topic,
+            qos: qos,
+            retain: retain,
+            dup: false,
+            packet_id: packet_id,
+            payload: format!("{}", value).into_bytes(),
+        })
+    }
+
+    fn send(&mut self, packet: &Packet) -> io::Result<()> {
+        let mut buf = Vec::new();
+        try!(packet.encode(&mut buf));
+        self.dev.request_write(&buf)
+    }
+
+    fn packet_id(&mut self) -> u16 {
+        let id = self.next_packet_id;
+        self.next_packet_id = if id == u16::max_value() { 1 } else { id + 1 };
+        id
+    }
+
+    fn client_id_str(&self) -> &str {
+        self.client_id.as_ref().map(String::as_str).unwrap_or("none")
+    }
+}
+
+impl DeviceHandler for Mqtt {
+    fn device(&mut self) -> &mut Device { &mut self.dev }
+
+    fn process_event(&mut self, event: Event) -> io::Result<()> {
+        match event {
+            Event::Ready => self.dev.request_read(),
+            Event::BytesRead(0) => {
+                info!("device {} was disconnected by MQTT client {}",
+                    self.dev.name(), self.client_id_str());
+                self.dev.close()
+            }
+            Event::BytesRead(_) => {
+                try!(self.process_input());
+                if self.dev.is_closed() || self.close_on_written { Ok(()) }
+                else { self.dev.request_read() }
+            }
+            Event::BytesWritten(_) if self.close_on_written => self.dev.close(),
+            Event::BytesWritten(_) => Ok(()),
+            Event::Closed => {
+                let dev_id = self.dev.id();
+                self.domains.unsubscribe_all(dev_id)
+            }
+        }
+    }
+}
+
+impl Protocol for Mqtt {
+
+    fn send_update(&mut self, domain: &str, variable: Var, value: Value) -> io::Result<()> {
+        self.broker.retain(domain, &variable, value.clone());
+        self.publish(domain, &variable, value, false)
+    }
+}
+
+/// Maps a topic name onto a domain and a variable.
+fn topic_var(topic: &str) -> io::Result<(String, Var)> {
+    let invalid_topic = || io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("topic '{}' does not map to any variable", topic));
+    let mut levels = topic.splitn(2, '/');
+    let domain = try!(levels.next().ok_or_else(&invalid_topic));
+    let name = try!(levels.next().ok_or_else(&invalid_topic));
+    if domain.is_empty() || name.is_empty() || topic.contains('#') ||
+        topic.split('/').any(|level| level == "+") {
+        return Err(invalid_topic());
+    }
+    let variable = if domain == "fsuipc" {
+        Var::Offset(try!(name.replace("/", "+").parse()))
+    } else {
+        Var::Named(name.to_string())
+    };
+    Ok((domain.to_string(), variable))
+}
+
+/// Parses the value carried by a publish payload.
+fn payload_value(payload: &[u8]) -> io::Result<Value> {
+    let text = str::from_utf8(payload).ok().map(|s| s.trim());
+    match text {
+        Some(value) => value.parse().map_err(|_| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid value '{}' in publish payload", value))),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "publish payload is not valid UTF-8 text")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use domain::*;
+    use io::*;
+    use proto::test_util::*;
+
+    use super::*;
+    use super::{payload_value, topic_var};
+
+    #[test]
+    fn should_map_topics_to_variables() {
+        assert_eq!(
+            topic_var("fsuipc/0BC8+2").unwrap(),
+            ("fsuipc".to_string(), Var::offset(0x0bc8, 2).unwrap()));
+        assert_eq!(
+            topic_var("fsuipc/0BC8/2").unwrap(),
+            ("fsuipc".to_string(), Var::offset(0x0bc8, 2).unwrap()));
+        assert_eq!(
+            topic_var("lvar/A320_Gear_Lever").unwrap(),
+            ("lvar".to_string(), Var::named("A320_Gear_Lever")));
+        assert!(topic_var("lvar").is_err());
+        assert!(topic_var("lvar/#").is_err());
+        assert!(topic_var("lvar/+").is_err());
+        assert!(topic_var("fsuipc/foobar").is_err());
+    }
+
+    #[test]
+    fn should_parse_payload_values() {
+        assert_eq!(payload_value(b"42").unwrap(), Value::I64(42));
+        assert_eq!(payload_value(b"-1\n").unwrap(), Value::I64(-1));
+        assert_eq!(payload_value(b"true").unwrap(), Value::Bool(true));
+        assert_eq!(payload_value(b"245.5").unwrap(), Value::Float(245.5));
+        assert!(payload_value(b"foobar").is_err());
+    }
+
+    #[test]
+    fn should_accept_connect() {
+        let (mut reactor, mut client, _) = broker_client();
+        send(&mut reactor, &mut client, &connect(4));
+        assert_eq!(
+            recv(&mut reactor, &mut client),
+            vec![Packet::ConnAck { session_present: false, return_code: 0 }]);
+    }
+
+    #[test]
+    fn should_reject_unsupported_protocol_level() {
+        let (mut reactor, mut client, _) = broker_client();
+        send(&mut reactor, &mut client, &connect(3));
+        assert_eq!(
+            recv(&mut reactor, &mut client),
+            vec![Packet::ConnAck { session_present: false, return_code: 1 }]);
+    }
+
+    #[test]
+    fn should_write_published_values() {
+        let (mut reactor, mut client, lvars) = broker_client();
+        lvars.define("foobar", 0.0);
+        send(&mut reactor, &mut client, &connect(4));
+        send(&mut reactor, &mut client, &Packet::Publish {
+            topic: "lvar/foobar".to_string(),
+            qos: 1,
+            retain: false,
+            dup: false,
+            packet_id: Some(10),
+            payload: b"42".to_vec(),
+        });
+        assert_eq!(recv(&mut reactor, &mut client)[1], Packet::PubAck(10));
+        reactor.poll_domains();
+        assert_eq!(lvars.value("foobar"), Some(42.0));
+    }
+
+    #[test]
+    fn should_publish_domain_events_to_subscribers() {
+        let (mut reactor, mut client, lvars) = broker_client();
+        lvars.define("foobar", 42.0);
+        send(&mut reactor, &mut client, &connect(4));
+        send(&mut reactor, &mut client, &Packet::Subscribe {
+            packet_id: 1,
+            filters: vec![("lvar/foobar".to_string(), 1), ("lvar/#".to_string(), 0)],
+        });
+        reactor.poll_domains();
+        assert_eq!(recv(&mut reactor, &mut client), vec![
+            Packet::ConnAck { session_present: false, return_code: 0 },
+            Packet::SubAck { packet_id: 1, return_codes: vec![1, SUBACK_FAILURE] },
+            Packet::Publish {
+                topic: "lvar/foobar".to_string(),
+                qos: 1,
+                retain: false,
+                dup: false,
+                packet_id: Some(1),
+                payload: b"42".to_vec(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn should_publish_retained_values_on_subscribe() {
+        let broker = Broker::new();
+        broker.retain("lvar", &Var::named("foobar"), Value::I64(7));
+        let (mut reactor, mut client, _) = broker_client_with(broker);
+        send(&mut reactor, &mut client, &connect(4));
+        send(&mut reactor, &mut client, &Packet::Subscribe {
+            packet_id: 1,
+            filters: vec![("lvar/foobar".to_string(), 0)],
+        });
+        assert_eq!(recv(&mut reactor, &mut client)[2], Packet::Publish {
+            topic: "lvar/foobar".to_string(),
+            qos: 0,
+            retain: true,
+            dup: false,
+            packet_id: None,
+            payload: b"7".to_vec(),
+        });
+    }
+
+    #[test]
+    fn should_not_publish_domain_events_after_unsubscribe() {
+        let (mut reactor, mut client, lvars) = broker_client();
+        lvars.define("foobar", 42.0);
+        send(&mut reactor, &mut client, &connect(4));
+        send(&mut reactor, &mut client, &Packet::Subscribe {
+            packet_id: 1,
+            filters: vec![("lvar/foobar".to_string(), 0)],
+        });
+        send(&mut reactor, &mut client, &Packet::Unsubscribe {
+            packet_id: 2,
+            filters: vec!["lvar/foobar".to_string()],
+        });
+        assert_eq!(recv(&mut reactor, &mut client)[2], Packet::UnsubAck(2));
+        let mut events = Vec::new();
+        reactor.domains.with_all_domains(|d| d.poll(&mut events)).unwrap();
+        assert!(events.is_empty());
+    }
+
+    fn broker_client() -> (TestReactor, Loopback, lvar::MemoryBackend) {
+        broker_client_with(Broker::new())
+    }
+
+    fn broker_client_with(broker: Broker) -> (TestReactor, Loopback, lvar::MemoryBackend) {
+        let (domains, lvars) = lvar_domains();
+        let (dev, client) = loopback("mqtt").unwrap();
+        let mut reactor = TestReactor::new(domains);
+        reactor.attach(|domains| Mqtt::new(dev, domains, broker));
+        (reactor, client, lvars)
+    }
+
+    fn connect(level: u8) -> Packet {
+        Packet::Connect {
+            protocol: "MQTT".to_string(),
+            level: level,
+            client_id: "test".to_string(),
+            clean_session: true,
+            keep_alive: 60,
+            will: None,
+        }
+    }
+
+    fn send(reactor: &mut TestReactor, client: &mut Loopback, packet: &Packet) {
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+        client.send(&buf).unwrap();
+        reactor.run();
+    }
+
+    fn recv(reactor: &mut TestReactor, client: &mut Loopback) -> Vec<Packet> {
+        reactor.run();
+        let data = client.recv().unwrap();
+        let mut packets = Vec::new();
+        let mut offset = 0;
+        while let Some((packet, nbytes)) = Packet::decode(&data[offset..]).unwrap() {
+            packets.push(packet);
+            offset += nbytes;
+        }
+        packets
+    }
+}
@@ -0,0 +1,435 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io;
+use std::io::{Cursor, Read};
+use std::str;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+/// The protocol level of MQTT 3.1.1.
+pub const PROTOCOL_LEVEL: u8 = 4;
+
+/// The largest packet accepted from clients, including its fixed header.
+pub const MAX_PACKET_SIZE: usize = 2048;
+
+pub const CONNACK_ACCEPTED: u8 = 0x00;
+pub const CONNACK_UNACCEPTABLE_PROTOCOL: u8 = 0x01;
+pub const SUBACK_FAILURE: u8 = 0x80;
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const PUBREC: u8 = 5;
+const PUBREL: u8 = 6;
+const PUBCOMP: u8 = 7;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const UNSUBSCRIBE: u8 = 10;
+const UNSUBACK: u8 = 11;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+/// The message the broker publishes on behalf of a client that disconnects abruptly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Will {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: u8,
+    pub retain: bool,
+}
+
+/// A MQTT 3.1.1 control packet.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Packet {
+    Connect {
+        protocol: String,
+        level: u8,
+        client_id: String,
+        clean_session: bool,
+        keep_alive: u16,
+        will: Option<Will>,
+    },
+    ConnAck { session_present: bool, return_code: u8 },
+    Publish {
+        topic: String,
+        qos: u8,
+        retain: bool,
+        dup: bool,
+        packet_id: Option<u16>,
+        payload: Vec<u8>,
+    },
+    PubAck(u16),
+    PubRec(u16),
+    PubRel(u16),
+    PubComp(u16),
+    Subscribe { packet_id: u16, filters: Vec<(String, u8)> },
+    SubAck { packet_id: u16, return_codes: Vec<u8> },
+    Unsubscribe { packet_id: u16, filters: Vec<String> },
+    UnsubAck(u16),
+    PingReq,
+    PingResp,
+    Disconnect,
+}
+
+impl Packet {
+
+    /// Decodes a packet from the given bytes.
+    ///
+    /// It returns the packet and the number of bytes it occupies, or `None` if the bytes
+    /// do not contain a complete packet yet.
+    pub fn decode(buf: &[u8]) -> io::Result<Option<(Packet, usize)>> {
+        let (len, header_len) = match try!(decode_remaining_length(buf)) {
+            Some(result) => result,
+            None => return Ok(None),
+        };
+        let total_len = header_len + len;
+        if total_len > MAX_PACKET_SIZE {
+            return Err(protocol_error("packet is too large"));
+        }
+        if buf.len() < total_len {
+            return Ok(None);
+        }
+        let packet_type = buf[0] >> 4;
+        let flags = buf[0] & 0x0f;
+        let mut body = Cursor::new(&buf[header_len..total_len]);
+        let packet = try!(match packet_type {
+            CONNECT => decode_connect(&mut body),
+            CONNACK => {
+                let flags = try!(body.read_u8());
+                let return_code = try!(body.read_u8());
+                Ok(Packet::ConnAck { session_present: flags & 0x01 != 0, return_code: return_code })
+            }
+            PUBLISH => decode_publish(flags, &mut body),
+            PUBACK => Ok(Packet::PubAck(try!(body.read_u16::<BigEndian>()))),
+            PUBREC => Ok(Packet::PubRec(try!(body.read_u16::<BigEndian>()))),
+            PUBREL => Ok(Packet::PubRel(try!(body.read_u16::<BigEndian>()))),
+            PUBCOMP => Ok(Packet::PubComp(try!(body.read_u16::<BigEndian>()))),
+            SUBSCRIBE => decode_subscribe(&mut body),
+            SUBACK => {
+                let packet_id = try!(body.read_u16::<BigEndian>());
+                let mut return_codes = Vec::new();
+                try!(body.read_to_end(&mut return_codes));
+                Ok(Packet::SubAck { packet_id: packet_id, return_codes: return_codes })
+            }
+            UNSUBSCRIBE => decode_unsubscribe(&mut body),
+            UNSUBACK => Ok(Packet::UnsubAck(try!(body.read_u16::<BigEndian>()))),
+            PINGREQ => Ok(Packet::PingReq),
+            PINGRESP => Ok(Packet::PingResp),
+            DISCONNECT => Ok(Packet::Disconnect),
+            _ => Err(protocol_error(&format!("unknown packet type {}", packet_type))),
+        });
+        Ok(Some((packet, total_len)))
+    }
+
+    /// Encodes this packet, appending its bytes to the given vector.
+    pub fn encode(&self, out: &mut Vec<u8>) -> io::Result<()> {
+        let mut body = Vec::new();
+        let header = match *self {
+            Packet::Connect { 
+                ref protocol, level, ref client_id, clean_session, keep_alive, ref will 
+            } => {
+                let mut flags = if clean_session { 0x02 } else { 0x00 };
+                if let Some(ref will) = *will {
+                    flags |= 0x04 | (will.qos << 3);
+                    if will.retain { flags |= 0x20; }
+                }
+                try!(encode_str(&mut body, protocol));
+                try!(body.write_u8(level));
+                try!(body.write_u8(flags));
+                try!(body.write_u16::<BigEndian>(keep_alive));
+                try!(encode_str(&mut body, client_id));
+                if let Some(ref will) = *will {
+                    try!(encode_str(&mut body, &will.topic));
+                    try!(encode_bytes(&mut body, &will.payload));
+                }
+                CONNECT << 4
+            }
+            Packet::ConnAck { session_present, return_code } => {
+                try!(body.write_u8(if session_present { 0x01 } else { 0x00 }));
+                try!(body.write_u8(return_code));
+                CONNACK << 4
+            }
+            Packet::Publish { ref topic, qos, retain, dup, packet_id, ref payload } => {
+                try!(encode_str(&mut body, topic));
+                if let Some(id) = packet_id {
+                    try!(body.write_u16::<BigEndian>(id));
+                }
+                body.extend_from_slice(payload);
+                let mut header = (PUBLISH << 4) | (qos << 1);
+                if dup { header |= 0x08; }
+                if retain { header |= 0x01; }
+                header
+            }
+            Packet::PubAck(id) => { try!(body.write_u16::<BigEndian>(id)); PUBACK << 4 }
+            Packet::PubRec(id) => { try!(body.write_u16::<BigEndian>(id)); PUBREC << 4 }
+            Packet::PubRel(id) => { try!(body.write_u16::<BigEndian>(id)); (PUBREL << 4) | 0x02 }
+            Packet::PubComp(id) => { try!(body.write_u16::<BigEndian>(id)); PUBCOMP << 4 }
+            Packet::Subscribe { packet_id, ref filters } => {
+                try!(body.write_u16::<BigEndian>(packet_id));
+                for &(ref filter, qos) in filters {
+                    try!(encode_str(&mut body, filter));
+                    try!(body.write_u8(qos));
+                }
+                (SUBSCRIBE << 4) | 0x02
+            }
+            Packet::SubAck { packet_id, ref return_codes } => {
+                try!(body.write_u16::<BigEndian>(packet_id));
+                body.extend_from_slice(return_codes);
+                SUBACK << 4
+            }
+            Packet::Unsubscribe { packet_id, ref filters } => {
+                try!(body.write_u16::<BigEndian>(packet_id));
+                for filter in filters {
+                    try!(encode_str(&mut body, filter));
+                }
+                (UNSUBSCRIBE << 4) | 0x02
+            }
+            Packet::UnsubAck(id) => { try!(body.write_u16::<BigEndian>(id)); UNSUBACK << 4 }
+            Packet::PingReq => PINGREQ << 4,
+            Packet::PingResp => PINGRESP << 4,
+            Packet::Disconnect => DISCONNECT << 4,
+        };
+        out.push(header);
+        encode_remaining_length(out, body.len());
+        out.extend_from_slice(&body);
+        Ok(())
+    }
+}
+
+fn decode_connect(body: &mut Cursor<&[u8]>) -> io::Result<Packet> {
+    let protocol = try!(decode_str(body));
+    let level = try!(body.read_u8());
+    let flags = try!(body.read_u8());
+    let keep_alive = try!(body.read_u16::<BigEndian>());
+    let client_id = try!(decode_str(body));
+    let will = if flags & 0x04 != 0 {
+        Some(Will {
+            topic: try!(decode_str(body)),
+            payload: try!(decode_bytes(body)),
+            qos: (flags >> 3) & 0x03,
+            retain: flags & 0x20 != 0,
+        })
+    } else { None };
+    // The credentials are not used, but they must be well formed
+    if flags & 0x80 != 0 {
+        try!(decode_str(body));
+    }
+    if flags & 0x40 != 0 {
+        try!(decode_bytes(body));
+    }
+    Ok(Packet::Connect {
+        protocol: protocol,
+        level: level,
+        client_id: client_id,
+        clean_session: flags & 0x02 != 0,
+        keep_alive: keep_alive,
+        will: will,
+    })
+}
+
+fn decode_publish(flags: u8, body: &mut Cursor<&[u8]>) -> io::Result<Packet> {
+    let qos = (flags >> 1) & 0x03;
+    if qos > 2 {
+        return Err(protocol_error("invalid QoS level in publish packet"));
+    }
+    let topic = try!(decode_str(body));
+    let packet_id = if qos > 0 { Some(try!(body.read_u16::<BigEndian>())) } else { None };
+    let mut payload = Vec::new();
+    try!(body.read_to_end(&mut payload));
+    Ok(Packet::Publish {
+        topic: topic,
+        qos: qos,
+        retain: flags & 0x01 != 0,
+        dup: flags & 0x08 != 0,
+        packet_id: packet_id,
+        payload: payload,
+    })
+}
+
+fn decode_subscribe(body: &mut Cursor<&[u8]>) -> io::Result<Packet> {
+    let packet_id = try!(body.read_u16::<BigEndian>());
+    let mut filters = Vec::new();
+    while (body.position() as usize) < body.get_ref().len() {
+        let filter = try!(decode_str(body));
+        let qos = try!(body.read_u8());
+        filters.push((filter, qos));
+    }
+    if filters.is_empty() {
+        return Err(protocol_error("subscribe packet with no topic filters"));
+    }
+    Ok(Packet::Subscribe { packet_id: packet_id, filters: filters })
+}
+
+fn decode_unsubscribe(body: &mut Cursor<&[u8]>) -> io::Result<Packet> {
+    let packet_id = try!(body.read_u16::<BigEndian>());
+    let mut filters = Vec::new();
+    while (body.position() as usize) < body.get_ref().len() {
+        filters.push(try!(decode_str(body)));
+    }
+    if filters.is_empty() {
+        return Err(protocol_error("unsubscribe packet with no topic filters"));
+    }
+    Ok(Packet::Unsubscribe { packet_id: packet_id, filters: filters })
+}
+
+/// Decodes the remaining length of the fixed header.
+///
+/// It returns the remaining length and the length of the fixed header, or `None` if
+/// the header is not complete.
+fn decode_remaining_length(buf: &[u8]) -> io::Result<Option<(usize, usize)>> {
+    let mut len = 0;
+    let mut multiplier = 1;
+    for i in 1..5 {
+        if i >= buf.len() {
+            return Ok(None);
+        }
+        let byte = buf[i];
+        len += (byte & 0x7f) as usize * multiplier;
+        if byte & 0x80 == 0 {
+            return Ok(Some((len, i + 1)));
+        }
+        multiplier *= 128;
+    }
+    Err(protocol_error("malformed remaining length"))
+}
+
+fn encode_remaining_length(out: &mut Vec<u8>, mut len: usize) {
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+}
+
+fn decode_bytes(body: &mut Cursor<&[u8]>) -> io::Result<Vec<u8>> {
+    let len = try!(body.read_u16::<BigEndian>()) as usize;
+    let mut bytes = vec![0; len];
+    try!(body.read_exact(&mut bytes));
+    Ok(bytes)
+}
+
+fn decode_str(body: &mut Cursor<&[u8]>) -> io::Result<String> {
+    let bytes = try!(decode_bytes(body));
+    String::from_utf8(bytes).map_err(|_| protocol_error("invalid UTF-8 string"))
+}
+
+fn encode_bytes(out: &mut Vec<u8>, bytes: &[u8]) -> io::Result<()> {
+    try!(out.write_u16::<BigEndian>(bytes.len() as u16));
+    out.extend_from_slice(bytes);
+    Ok(())
+}
+
+fn encode_str(out: &mut Vec<u8>, s: &str) -> io::Result<()> {
+    encode_bytes(out, s.as_bytes())
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("MQTT protocol error: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn should_decode_connect() {
+        let buf = [
+            0x10, 0x10, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0x02, 0x00, 0x3c,
+            0x00, 0x04, b'p', b'a', b'n', b'l'];
+        let (packet, len) = Packet::decode(&buf).unwrap().unwrap();
+        assert_eq!(len, 18);
+        assert_eq!(packet, Packet::Connect {
+            protocol: "MQTT".to_string(),
+            level: 4,
+            client_id: "panl".to_string(),
+            clean_session: true,
+            keep_alive: 60,
+            will: None,
+        });
+    }
+
+    #[test]
+    fn should_encode_and_decode_connect_with_will() {
+        let packet = Packet::Connect {
+            protocol: "MQTT".to_string(),
+            level: 4,
+            client_id: "flightvars".to_string(),
+            clean_session: true,
+            keep_alive: 30,
+            will: Some(Will {
+                topic: "flightvars/status".to_string(),
+                payload: b"offline".to_vec(),
+                qos: 1,
+                retain: true,
+            }),
+        };
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+        assert_eq!(buf[9], 0x2e);
+        assert_eq!(Packet::decode(&buf).unwrap().unwrap(), (packet, buf.len()));
+    }
+
+    #[test]
+    fn should_not_decode_incomplete_packet() {
+        let buf = [0x10, 0x10, 0x00, 0x04, b'M', b'Q'];
+        assert!(Packet::decode(&buf).unwrap().is_none());
+        assert!(Packet::decode(&buf[..1]).unwrap().is_none());
+    }
+
+    #[test]
+    fn should_fail_to_decode_too_large_packet() {
+        let buf = [0x30, 0xff, 0xff, 0x03];
+        assert!(Packet::decode(&buf).is_err());
+    }
+
+    #[test]
+    fn should_encode_and_decode_publish() {
+        let packet = Packet::Publish {
+            topic: "lvar/foobar".to_string(),
+            qos: 1,
+            retain: true,
+            dup: false,
+            packet_id: Some(7),
+            payload: b"42".to_vec(),
+        };
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+        assert_eq!(buf[0], 0x33);
+        assert_eq!(Packet::decode(&buf).unwrap().unwrap(), (packet, buf.len()));
+    }
+
+    #[test]
+    fn should_encode_and_decode_subscribe() {
+        let packet = Packet::Subscribe {
+            packet_id: 1,
+            filters: vec![("fsuipc/0BC8+2".to_string(), 1), ("lvar/foo".to_string(), 0)],
+        };
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+        assert_eq!(Packet::decode(&buf).unwrap().unwrap(), (packet, buf.len()));
+    }
+
+    #[test]
+    fn should_encode_long_remaining_length() {
+        let packet = Packet::SubAck { packet_id: 1, return_codes: vec![0; 200] };
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+        assert_eq!(&buf[..3], &[0x90, 0xca, 0x01]);
+        assert_eq!(Packet::decode(&buf).unwrap().unwrap(), (packet, buf.len()));
+    }
+}
@@ -0,0 +1,198 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io;
+use std::io::Write;
+
+use types::*;
+
+use super::input::{MessageParser, RawInputMessage};
+use super::output::RawOutputMessage;
+
+/// The syntax of the messages of an OACSP version.
+///
+/// * OACSP v1 writes offsets as `<address>:<size>` and has no LVAR messages.
+/// * OACSP v2 writes offsets as `<address>+<size>` and adds LVAR messages.
+/// * OACSP v3 adds UNOBS and READ messages, replies to every message, and sends
+///   values other than integers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dialect { V1, V2, V3 }
+
+impl Dialect {
+
+    /// The dialect of the latest OACSP version supported by FlightVars.
+    pub fn latest() -> Dialect { Dialect::V3 }
+
+    /// Negotiates the highest common version with a client that supports up to the
+    /// given one, or `None` if there is no such version.
+    pub fn negotiate(version: u16) -> Option<Dialect> {
+        match version {
+            0 => None,
+            1 => Some(Dialect::V1),
+            2 => Some(Dialect::V2),
+            _ => Some(Dialect::V3),
+        }
+    }
+
+    pub fn version(&self) -> u16 {
+        match *self {
+            Dialect::V1 => 1,
+            Dialect::V2 => 2,
+            Dialect::V3 => 3,
+        }
+    }
+
+    /// Whether the server replies to every message received from the client.
+    pub fn replies(&self) -> bool { *self == Dialect::V3 }
+
+    pub fn parse(&self, line: &str) -> io::Result<RawInputMessage> {
+        let msg = try!(MessageParser::new(line, *self).parse());
+        if !self.supports(&msg) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("message '{}' is not supported by OACSP version {}",
+                    line.trim(), self.version())));
+        }
+        Ok(msg)
+    }
+
+    pub fn parse_offset(&self, s: &str) -> io::Result<Offset> {
+        match *self {
+            Dialect::V1 => s.replace(":", "+").parse(),
+            _ => s.parse(),
+        }
+    }
+
+    /// Encodes the given message as a line of this dialect.
+    ///
+    /// It fails with `InvalidData` if the message has a value this dialect cannot send.
+    pub fn encode(&self, msg: &RawOutputMessage, buf: &mut Vec<u8>) -> io::Result<()> {
+        match (*self, msg) {
+            (Dialect::V3, _) => write!(buf, "{}\n", msg),
+            (Dialect::V1, &RawOutputMessage::EventOffset { offset: Offset(addr, size, _), ref value }) =>
+                write!(buf, "EVENT_OFFSET {:x}:{} {}\n", addr, size, try!(self.event_value(value))),
+            (_, &RawOutputMessage::EventOffset { ref offset, ref value }) =>
+                write!(buf, "EVENT_OFFSET {} {}\n", offset, try!(self.event_value(value))),
+            (_, &RawOutputMessage::EventLvar { ref lvar, ref value }) =>
+                write!(buf, "EVENT_LVAR {} {}\n", lvar, try!(self.event_value(value))),
+            _ => write!(buf, "{}\n", msg),
+        }
+    }
+
+    /// Converts the value of an event into one the clients of this dialect can parse.
+    ///
+    /// Clients of OACSP v1 and v2 only parse integers, so decimal numbers are rounded
+    /// and booleans are sent as `1` or `0`. Strings and byte blocks cannot be sent.
+    fn event_value(&self, value: &Value) -> io::Result<Value> {
+        match (*self, value) {
+            (Dialect::V3, _) => Ok(value.clone()),
+            (_, &Value::Bool(b)) => Ok(Value::I64(if b { 1 } else { 0 })),
+            (_, &Value::Float(f)) => i64::from_value(&Value::Float(f.round())).map(Value::I64),
+            (_, &Value::Str(_)) | (_, &Value::Bytes(_)) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("value {} cannot be sent to OACSP version {} clients",
+                    value, self.version()))),
+            _ => Ok(value.clone()),
+        }
+    }
+
+    fn supports(&self, msg: &RawInputMessage) -> bool {
+        match (*self, msg) {
+            (Dialect::V3, _) => true,
+            (_, &RawInputMessage::UnobserveLvar { .. }) |
+            (_, &RawInputMessage::UnobserveOffset { .. }) |
+            (_, &RawInputMessage::ReadLvar { .. }) |
+            (_, &RawInputMessage::ReadOffset { .. }) => false,
+            (Dialect::V1, &RawInputMessage::WriteLvar { .. }) |
+            (Dialect::V1, &RawInputMessage::ObserveLvar { .. }) => false,
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::io;
+
+    use super::*;
+    use super::super::input::RawInputMessage;
+    use super::super::output::RawOutputMessage;
+
+    #[test]
+    fn should_negotiate_highest_common_version() {
+        assert_eq!(Dialect::negotiate(0), None);
+        assert_eq!(Dialect::negotiate(1), Some(Dialect::V1));
+        assert_eq!(Dialect::negotiate(2), Some(Dialect::V2));
+        assert_eq!(Dialect::negotiate(3), Some(Dialect::V3));
+        assert_eq!(Dialect::negotiate(7), Some(Dialect::V3));
+    }
+
+    #[test]
+    fn should_parse_v1_offsets() {
+        assert_eq!(
+            Dialect::V1.parse("OBS_OFFSET 0330:2").unwrap(),
+            RawInputMessage::obs_offset(Offset::from(0x0330, 2).unwrap()));
+    }
+
+    #[test]
+    fn should_reject_unsupported_messages() {
+        assert!(Dialect::V1.parse("OBS_LVAR foobar").is_err());
+        assert!(Dialect::V2.parse("READ_LVAR foobar").is_err());
+        assert!(Dialect::V3.parse("READ_LVAR foobar").is_ok());
+    }
+
+    #[test]
+    fn should_encode_v1_offsets() {
+        let msg = RawOutputMessage::event_offset(Offset::from(0x0330, 2).unwrap(), Value::I64(42));
+        let mut buf = Vec::new();
+        Dialect::V1.encode(&msg, &mut buf).unwrap();
+        assert_eq!(buf, b"EVENT_OFFSET 330:2 42\n");
+        buf.clear();
+        Dialect::V2.encode(&msg, &mut buf).unwrap();
+        assert_eq!(buf, b"EVENT_OFFSET 330+2 42\n");
+    }
+
+    #[test]
+    fn should_encode_v1_values_as_integers() {
+        let offset = Offset::from(0x0330, 2).unwrap();
+        assert_eq!(encode(Dialect::V1, offset, Value::Float(245.5)).unwrap(), b"EVENT_OFFSET 330:2 246\n");
+        assert_eq!(encode(Dialect::V1, offset, Value::Bool(true)).unwrap(), b"EVENT_OFFSET 330:2 1\n");
+        assert_eq!(encode(Dialect::V1, offset, Value::U8(7)).unwrap(), b"EVENT_OFFSET 330:2 7\n");
+        assert!(encode(Dialect::V1, offset, Value::Bytes(vec![1])).is_err());
+    }
+
+    #[test]
+    fn should_encode_v2_values_as_integers() {
+        let msg = RawOutputMessage::event_lvar("foobar", Value::Float(-1.4));
+        let mut buf = Vec::new();
+        Dialect::V2.encode(&msg, &mut buf).unwrap();
+        assert_eq!(buf, b"EVENT_LVAR foobar -1\n");
+        let offset = Offset::from(0x0330, 2).unwrap();
+        assert_eq!(encode(Dialect::V2, offset, Value::Bool(false)).unwrap(), b"EVENT_OFFSET 330+2 0\n");
+        let error = encode(Dialect::V2, offset, Value::Str("A320".to_string())).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn should_encode_v3_values_as_they_are() {
+        let offset = Offset::from(0x0330, 2).unwrap();
+        assert_eq!(encode(Dialect::V3, offset, Value::Float(245.5)).unwrap(), b"EVENT_OFFSET 330+2 245.5\n");
+        assert_eq!(encode(Dialect::V3, offset, Value::Bool(true)).unwrap(), b"EVENT_OFFSET 330+2 true\n");
+        assert_eq!(
+            encode(Dialect::V3, offset, Value::Str("A320".to_string())).unwrap(),
+            b"EVENT_OFFSET 330+2 \"A320\"\n");
+        assert_eq!(encode(Dialect::V3, offset, Value::Bytes(vec![1])).unwrap(), b"EVENT_OFFSET 330+2 0x01\n");
+    }
+
+    fn encode(dialect: Dialect, offset: Offset, value: Value) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        try!(dialect.encode(&RawOutputMessage::event_offset(offset, value), &mut buf));
+        Ok(buf)
+    }
+}
@@ -0,0 +1,432 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::boxed::Box;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use config::OacspSerialSettings;
+use domain::DomainDispatcher;
+use io::*;
+use proto::*;
+use types::*;
+
+const SCAN_INTERVAL_SECS: u64 = 5;
+const RETRY_SECS: u64 = 30;
+
+/// The discovery of OACSP clients connected to serial ports.
+///
+/// The serial ports of the system are enumerated periodically, and every port that is
+/// neither configured explicitly nor already probed is opened and served with OACSP.
+/// Ports whose client sends no `BEGIN` message before the handshake timeout expires,
+/// or whose client ID is not among the accepted ones, are closed and skipped until the
+/// enumerated ports change, since opening a port resets the board connected to it.
+/// The same applies to ports that cannot be opened, unless the error is transient, in
+/// which case the port is probed again some time later. This way, clients are matched
+/// by their ID regardless of the port they are connected to.
+///
+/// As the MQTT bridge, the discovery attaches its devices to the reactor by itself
+/// when `poll()` is called.
+pub struct OacspDiscovery {
+    clients: Vec<String>,
+    excluded: Vec<OsString>,
+    handshake_timeout: Duration,
+    enumerate: Box<Fn() -> io::Result<Vec<OsString>>>,
+    open: Box<Fn(&OsStr) -> io::Result<Device>>,
+    probes: HashMap<OsString, Probe>,
+    retries: HashMap<OsString, Instant>,
+    skipped: Vec<OsString>,
+    enumerated: Vec<OsString>,
+    next_scan: Instant,
+}
+
+impl OacspDiscovery {
+
+    /// Creates a discovery that enumerates the serial ports with `enumerate` and opens
+    /// them with `open`. The `excluded` ports are never probed.
+    pub fn new<E, O>(settings: &OacspSerialSettings,
+                     excluded: Vec<OsString>,
+                     enumerate: E,
+                     open: O) -> OacspDiscovery
+    where E: Fn() -> io::Result<Vec<OsString>> + 'static,
+          O: Fn(&OsStr) -> io::Result<Device> + 'static {
+        OacspDiscovery {
+            clients: settings.clients.clone(),
+            excluded: excluded,
+            handshake_timeout: settings.handshake_timeout,
+            enumerate: Box::new(enumerate),
+            open: Box::new(open),
+            probes: HashMap::new(),
+            retries: HashMap::new(),
+            skipped: Vec::new(),
+            enumerated: Vec::new(),
+            next_scan: Instant::now(),
+        }
+    }
+
+    /// Checks the handshake of the probed ports, and probes new ports if it is time to.
+    pub fn poll(&mut self, reactor: &mut Reactor<Box<Protocol>>, domains: &DomainDispatcher) {
+        self.check_probes(reactor);
+        let now = Instant::now();
+        if now >= self.next_scan {
+            self.next_scan = now + Duration::from_secs(SCAN_INTERVAL_SECS);
+            self.scan(reactor, domains);
+        }
+    }
+
+    fn check_probes(&mut self, reactor: &mut Reactor<Box<Protocol>>) {
+        let now = Instant::now();
+        let mut accepted: Vec<String> = self.probes.values()
+            .filter_map(|p| p.client.clone())
+            .collect();
+        let mut closed = Vec::new();
+        for (port, probe) in self.probes.iter_mut() {
+            let state = probe.state.borrow().clone();
+            match state {
+                ProbeState::Closed => closed.push(port.clone()),
+                _ if probe.client.is_some() || probe.closing => {}
+                ProbeState::Begun(client) => {
+                    if !self.clients.is_empty() && !self.clients.contains(&client) {
+                        warn!("closing serial port {:?} of unexpected OACSP client {}",
+                            port, client);
+                        probe.close(reactor);
+                    } else if accepted.contains(&client) {
+                        warn!("closing serial port {:?} of OACSP client {}, which is \
+                            already connected to another port", port, client);
+                        probe.close(reactor);
+                    } else {
+                        info!("OACSP client {} found on serial port {:?}", client, port);
+                        probe.client = Some(client.clone());
+                        accepted.push(client);
+                    }
+                }
+                ProbeState::Handshaking if now >= probe.deadline => {
+                    debug!("closing serial port {:?} since no OACSP handshake was received",
+                        port);
+                    probe.close(reactor);
+                }
+                ProbeState::Handshaking => {}
+            }
+        }
+        for port in closed {
+            let probe = self.probes.remove(&port).unwrap();
+            match probe.client {
+                Some(client) => {
+                    info!("OACSP client {} on serial port {:?} is down", client, port);
+                }
+                None => self.skipped.push(port),
+            }
+        }
+    }
+
+    fn scan(&mut self, reactor: &mut Reactor<Box<Protocol>>, domains: &DomainDispatcher) {
+        let ports = match (self.enumerate)() {
+            Ok(ports) => ports,
+            Err(e) => {
+                error!("cannot enumerate serial ports: {:?}", e);
+                return;
+            }
+        };
+        if ports != self.enumerated {
+            self.skipped.clear();
+            self.enumerated = ports.clone();
+        }
+        let now = Instant::now();
+        let expired: Vec<OsString> = self.retries.iter()
+            .filter(|&(_, until)| *until <= now)
+            .map(|(port, _)| port.clone())
+            .collect();
+        for port in expired {
+            self.retries.remove(&port);
+        }
+        for port in ports {
+            if self.excluded.contains(&port) ||
+                    self.probes.contains_key(&port) ||
+                    self.retries.contains_key(&port) ||
+                    self.skipped.contains(&port) {
+                continue;
+            }
+            match self.probe(&port, reactor, domains) {
+                Ok(probe) => {
+                    debug!("probing serial port {:?} for OACSP clients", port);
+                    self.probes.insert(port, probe);
+                }
+                Err(ref e) if is_transient(e) => {
+                    debug!("cannot probe serial port {:?}, retrying later: {:?}", port, e);
+                    self.retries.insert(port, now + Duration::from_secs(RETRY_SECS));
+                }
+                Err(e) => {
+                    debug!("cannot probe serial port {:?}: {:?}", port, e);
+                    self.skipped.push(port);
+                }
+            }
+        }
+    }
+
+    fn probe(&self,
+             port: &OsStr,
+             reactor: &mut Reactor<Box<Protocol>>,
+             domains: &DomainDispatcher) -> io::Result<Probe> {
+        let dev = try!((self.open)(port));
+        let state = Rc::new(RefCell::new(ProbeState::Handshaking));
+        let handler = Probed {
+            proto: Oacsp::new(dev, domains.clone()),
+            state: state.clone(),
+        };
+        let id = try!(reactor.attach(Box::new(handler)));
+        Ok(Probe {
+            id: id,
+            state: state,
+            deadline: Instant::now() + self.handshake_timeout,
+            client: None,
+            closing: false,
+        })
+    }
+}
+
+/// Whether the given error opening a port may go away by itself.
+///
+/// A port in use by another application is reported as a permission error on Windows.
+fn is_transient(error: &io::Error) -> bool {
+    match error.kind() {
+        io::ErrorKind::Interrupted |
+        io::ErrorKind::WouldBlock |
+        io::ErrorKind::TimedOut |
+        io::ErrorKind::PermissionDenied => true,
+        _ => false,
+    }
+}
+
+/// The state of a probed port as reported by its handler.
+#[derive(Clone, Debug, PartialEq)]
+enum ProbeState {
+    /// The `BEGIN` message of the client was not received yet.
+    Handshaking,
+    /// The client sent a `BEGIN` message with the given client ID.
+    Begun(String),
+    /// The device of the port was closed and detached from the reactor.
+    Closed,
+}
+
+/// A probed port, whose client is known once it is accepted.
+struct Probe {
+    id: DeviceId,
+    state: Rc<RefCell<ProbeState>>,
+    deadline: Instant,
+    client: Option<String>,
+    closing: bool,
+}
+
+impl Probe {
+    fn close(&mut self, reactor: &mut Reactor<Box<Protocol>>) {
+        self.closing = true;
+        if let Some(handler) = reactor.handler(&self.id) {
+            if let Err(e) = handler.device().close() {
+                error!("cannot close serial port {}: {:?}", handler.device().name(), e);
+            }
+        }
+    }
+}
+
+/// An OACSP handler that reports the state of the probe of its port.
+struct Probed {
+    proto: Oacsp,
+    state: Rc<RefCell<ProbeState>>,
+}
+
+impl DeviceHandler for Probed {
+    fn device(&mut self) -> &mut Device { self.proto.device() }
+
+    fn process_event(&mut self, event: Event) -> io::Result<()> {
+        let closed = event == Event::Closed;
+        let result = self.proto.process_event(event);
+        let mut state = self.state.borrow_mut();
+        if closed {
+            *state = ProbeState::Closed;
+        } else if *state == ProbeState::Handshaking {
+            if let Some(client) = self.proto.client() {
+                *state = ProbeState::Begun(client.to_string());
+            }
+        }
+        result
+    }
+}
+
+impl Protocol for Probed {
+    fn send_update(&mut self, domain: &str, variable: Var, value: Value) -> io::Result<()> {
+        self.proto.send_update(domain, variable, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::ffi::OsString;
+    use std::io;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    use config::{DomainSettings, OacspSerialSettings};
+    use domain::*;
+    use io::*;
+    use proto::*;
+    use proto::test_util::*;
+
+    use super::*;
+
+    #[test]
+    fn should_accept_clients_on_any_port() {
+        let mut test = discovery(vec!["mcp", "fcu"], 1000);
+        test.run();
+        test.send("/dev/ttyA", b"BEGIN 2 fcu\n");
+        test.send("/dev/ttyB", b"BEGIN 2 mcp\n");
+        test.run();
+        assert_eq!(test.client("/dev/ttyA"), Some("fcu".to_string()));
+        assert_eq!(test.client("/dev/ttyB"), Some("mcp".to_string()));
+        assert!(!test.devices.borrow().contains_key(&OsString::from("/dev/ttyC")));
+    }
+
+    #[test]
+    fn should_reject_unexpected_clients() {
+        let mut test = discovery(vec!["mcp"], 1000);
+        test.run();
+        test.send("/dev/ttyA", b"BEGIN 2 fcu\n");
+        test.run();
+        assert!(!test.discovery.probes.contains_key(&OsString::from("/dev/ttyA")));
+        assert!(test.discovery.skipped.contains(&OsString::from("/dev/ttyA")));
+    }
+
+    #[test]
+    fn should_reject_clients_already_connected() {
+        let mut test = discovery(vec![], 1000);
+        test.run();
+        test.send("/dev/ttyA", b"BEGIN 2 mcp\n");
+        test.run();
+        test.send("/dev/ttyB", b"BEGIN 2 mcp\n");
+        test.run();
+        assert_eq!(test.client("/dev/ttyA"), Some("mcp".to_string()));
+        assert!(test.discovery.skipped.contains(&OsString::from("/dev/ttyB")));
+    }
+
+    #[test]
+    fn should_close_ports_with_no_handshake() {
+        let mut test = discovery(vec![], 0);
+        test.run();
+        assert!(test.discovery.probes.is_empty());
+        assert_eq!(test.discovery.skipped.len(), 2);
+    }
+
+    #[test]
+    fn should_not_probe_skipped_ports_again_until_enumeration_changes() {
+        let mut test = discovery(vec![], 0);
+        test.run();
+        test.discovery.next_scan = Instant::now();
+        test.run();
+        assert_eq!(*test.opened.borrow(), 2);
+        test.ports.borrow_mut().retain(|p| p != "/dev/ttyB");
+        test.discovery.next_scan = Instant::now();
+        test.run();
+        assert_eq!(*test.opened.borrow(), 3);
+    }
+
+    #[test]
+    fn should_skip_ports_that_cannot_be_opened() {
+        let mut test = discovery(vec![], 1000);
+        test.fail_open.borrow_mut().push(io::ErrorKind::NotFound);
+        test.fail_open.borrow_mut().push(io::ErrorKind::PermissionDenied);
+        test.run();
+        assert!(test.discovery.skipped.contains(&OsString::from("/dev/ttyA")));
+        assert!(test.discovery.retries.contains_key(&OsString::from("/dev/ttyB")));
+    }
+
+    #[test]
+    fn should_probe_ports_again_when_accepted_client_goes_down() {
+        let mut test = discovery(vec![], 1000);
+        test.run();
+        test.send("/dev/ttyA", b"BEGIN 2 mcp\n");
+        test.run();
+        test.devices.borrow_mut().remove(&OsString::from("/dev/ttyA"));
+        test.run();
+        assert!(!test.discovery.probes.contains_key(&OsString::from("/dev/ttyA")));
+        assert!(!test.discovery.skipped.contains(&OsString::from("/dev/ttyA")));
+    }
+
+    struct DiscoveryTest {
+        discovery: OacspDiscovery,
+        reactor: Reactor<Box<Protocol>>,
+        domains: DomainDispatcher,
+        devices: Rc<RefCell<HashMap<OsString, Loopback>>>,
+        ports: Rc<RefCell<Vec<OsString>>>,
+        fail_open: Rc<RefCell<Vec<io::ErrorKind>>>,
+        opened: Rc<RefCell<usize>>,
+    }
+
+    impl DiscoveryTest {
+        fn run(&mut self) {
+            let (discovery, domains) = (&mut self.discovery, &self.domains);
+            run_with(&mut self.reactor, |reactor| discovery.poll(reactor, domains));
+        }
+
+        fn send(&mut self, port: &str, data: &[u8]) {
+            let mut devices = self.devices.borrow_mut();
+            devices.get_mut(&OsString::from(port)).unwrap().send(data).unwrap();
+        }
+
+        fn client(&self, port: &str) -> Option<String> {
+            self.discovery.probes.get(&OsString::from(port)).and_then(|p| p.client.clone())
+        }
+    }
+
+    /// Discovers clients on ports A, B and C, excluding the latter.
+    ///
+    /// Opening a port fails with the first error kind in `fail_open`, if any.
+    fn discovery(clients: Vec<&str>, timeout_millis: u64) -> DiscoveryTest {
+        let settings = OacspSerialSettings {
+            ports: vec![],
+            discover: true,
+            clients: clients.iter().map(|c| c.to_string()).collect(),
+            handshake_timeout: Duration::from_millis(timeout_millis),
+        };
+        let devices = Rc::new(RefCell::new(HashMap::new()));
+        let ports = Rc::new(RefCell::new(vec!["/dev/ttyA", "/dev/ttyB", "/dev/ttyC"]
+            .into_iter().map(OsString::from).collect::<Vec<_>>()));
+        let fail_open = Rc::new(RefCell::new(Vec::new()));
+        let opened = Rc::new(RefCell::new(0));
+        let (enumerated, open_devices, open_failures, open_count) =
+            (ports.clone(), devices.clone(), fail_open.clone(), opened.clone());
+        let discovery = OacspDiscovery::new(
+            &settings,
+            vec![OsString::from("/dev/ttyC")],
+            move || Ok(enumerated.borrow().clone()),
+            move |port| {
+                if !open_failures.borrow().is_empty() {
+                    let kind = open_failures.borrow_mut().remove(0);
+                    return Err(io::Error::new(kind, "cannot open port"));
+                }
+                let (dev, client) = try!(loopback("serial"));
+                open_devices.borrow_mut().insert(port.to_os_string(), client);
+                *open_count.borrow_mut() += 1;
+                Ok(dev)
+            });
+        DiscoveryTest {
+            discovery: discovery,
+            reactor: Reactor::new().unwrap(),
+            domains: DomainDispatcher::new(&DomainSettings { enabled: vec![] }).unwrap(),
+            devices: devices,
+            ports: ports,
+            fail_open: fail_open,
+            opened: opened,
+        }
+    }
+}
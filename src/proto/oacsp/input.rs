@@ -11,6 +11,8 @@ use std::str::FromStr;
 
 use types::*;
 
+use super::dialect::Dialect;
+
 #[derive(Debug, PartialEq)]
 pub enum RawInputMessage {
     Begin { version: u16, client_id: String },
@@ -18,6 +20,10 @@ pub enum RawInputMessage {
     WriteOffset { offset: Offset, value: Value },
     ObserveLvar { lvar: String },
     ObserveOffset { offset: Offset },
+    UnobserveLvar { lvar: String },
+    UnobserveOffset { offset: Offset },
+    ReadLvar { lvar: String },
+    ReadOffset { offset: Offset },
 }
 
 impl RawInputMessage {
@@ -41,24 +47,41 @@ impl RawInputMessage {
     pub fn obs_offset(offset: Offset) -> RawInputMessage {
         RawInputMessage::ObserveOffset { offset: offset }
     }
+
+    pub fn unobs_lvar(lvar: &str) -> RawInputMessage {
+        RawInputMessage::UnobserveLvar { lvar: lvar.to_string() }
+    }
+
+    pub fn unobs_offset(offset: Offset) -> RawInputMessage {
+        RawInputMessage::UnobserveOffset { offset: offset }
+    }
+
+    pub fn read_lvar(lvar: &str) -> RawInputMessage {
+        RawInputMessage::ReadLvar { lvar: lvar.to_string() }
+    }
+
+    pub fn read_offset(offset: Offset) -> RawInputMessage {
+        RawInputMessage::ReadOffset { offset: offset }
+    }
 }
 
 impl FromStr for RawInputMessage {
     type Err = io::Error;
     fn from_str(s: &str) -> io::Result<RawInputMessage> {
-        let deco = MessageParser::new(s);
+        let deco = MessageParser::new(s, Dialect::latest());
         deco.parse()
     }
 }
 
 
 pub struct MessageParser<'a> {
-    input: &'a str
+    input: &'a str,
+    dialect: Dialect,
 }
 
 impl<'a> MessageParser<'a> {
-    pub fn new(input: &'a str) -> MessageParser {
-        MessageParser { input: input }
+    pub fn new(input: &'a str, dialect: Dialect) -> MessageParser {
+        MessageParser { input: input, dialect: dialect }
     }
 
     pub fn parse(self) -> io::Result<RawInputMessage> {
@@ -66,7 +89,10 @@ impl<'a> MessageParser<'a> {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput, "cannot parse oacsp message from empty line"));
         }
-        let args: Vec<&str> = self.input.split_whitespace().collect();
+        let args = try!(self.split_args());
+        if args.is_empty() {
+            return Err(self.input_error());
+        }
         let cmd = args[0];
         let args = &args[1..];
         match &cmd.to_uppercase()[..] {
@@ -75,6 +101,10 @@ impl<'a> MessageParser<'a> {
             "WRITE_OFFSET" => self.parse_write_offset(&args),
             "OBS_LVAR" => self.parse_obs_lvar(&args),
             "OBS_OFFSET" => self.parse_obs_offset(&args),
+            "UNOBS_LVAR" => self.parse_unobs_lvar(&args),
+            "UNOBS_OFFSET" => self.parse_unobs_offset(&args),
+            "READ_LVAR" => self.parse_read_lvar(&args),
+            "READ_OFFSET" => self.parse_read_offset(&args),
             _ => Err(self.input_error()),
         }
     }
@@ -88,14 +118,14 @@ impl<'a> MessageParser<'a> {
     fn parse_write_lvar(self, args: &[&str]) -> io::Result<RawInputMessage> {
         try!(self.require_argc(args, 2));
         let lvar = args[0];
-        let value = try!(args[1].parse().map(Value::Number).map_err(|_| self.input_error()));
+        let value = try!(args[1].parse().map_err(|_| self.input_error()));
         Ok(RawInputMessage::write_lvar(&lvar, value))
     }
 
     fn parse_write_offset(self, args: &[&str]) -> io::Result<RawInputMessage> {
         try!(self.require_argc(args, 2));
-        let offset: Offset = try!(args[0].parse());
-        let value = try!(args[1].parse().map(Value::Number).map_err(|_| self.input_error()));
+        let offset = try!(self.dialect.parse_offset(args[0]));
+        let value = try!(args[1].parse().map_err(|_| self.input_error()));
         Ok(RawInputMessage::write_offset(offset, value))
     }
 
@@ -106,7 +136,59 @@ impl<'a> MessageParser<'a> {
 
     fn parse_obs_offset(self, args: &[&str]) -> io::Result<RawInputMessage> {
         try!(self.require_argc(args, 1));
-        Ok(RawInputMessage::obs_offset(try!(args[0].parse())))
+        Ok(RawInputMessage::obs_offset(try!(self.dialect.parse_offset(args[0]))))
+    }
+
+    fn parse_unobs_lvar(self, args: &[&str]) -> io::Result<RawInputMessage> {
+        try!(self.require_argc(args, 1));
+        Ok(RawInputMessage::unobs_lvar(args[0]))
+    }
+
+    fn parse_unobs_offset(self, args: &[&str]) -> io::Result<RawInputMessage> {
+        try!(self.require_argc(args, 1));
+        Ok(RawInputMessage::unobs_offset(try!(self.dialect.parse_offset(args[0]))))
+    }
+
+    fn parse_read_lvar(self, args: &[&str]) -> io::Result<RawInputMessage> {
+        try!(self.require_argc(args, 1));
+        Ok(RawInputMessage::read_lvar(args[0]))
+    }
+
+    fn parse_read_offset(self, args: &[&str]) -> io::Result<RawInputMessage> {
+        try!(self.require_argc(args, 1));
+        Ok(RawInputMessage::read_offset(try!(self.dialect.parse_offset(args[0]))))
+    }
+
+    /// Splits the input into whitespace-separated arguments.
+    ///
+    /// Quoted strings are kept in a single argument along with their quotes, even if
+    /// they contain whitespaces or escaped quotes. 
+    fn split_args(&self) -> io::Result<Vec<&'a str>> {
+        let mut args = Vec::new();
+        let mut start = None;
+        let mut quoted = false;
+        let mut escaped = false;
+        for (i, c) in self.input.char_indices() {
+            if quoted {
+                if escaped { escaped = false; }
+                else if c == '\\' { escaped = true; }
+                else if c == '"' { quoted = false; }
+            } else if c.is_whitespace() {
+                if let Some(from) = start.take() {
+                    args.push(&self.input[from..i]);
+                }
+            } else {
+                if start.is_none() { start = Some(i); }
+                if c == '"' { quoted = true; }
+            }
+        }
+        if quoted {
+            return Err(self.input_error());
+        }
+        if let Some(from) = start {
+            args.push(&self.input[from..]);
+        }
+        Ok(args)
     }
 
     fn require_argc(&self, args: &[&str], expected: usize) -> io::Result<()> {
@@ -140,7 +222,28 @@ mod tests {
     fn should_parse_write_lvar_msg() {
         let buf = "WRITE_LVAR foobar 42";
         let msg = RawInputMessage::from_str(&buf).unwrap();
-        assert_eq!(msg, RawInputMessage::write_lvar("foobar", Value::Number(42)));
+        assert_eq!(msg, RawInputMessage::write_lvar("foobar", Value::I64(42)));
+    }
+
+    #[test]
+    fn should_parse_write_lvar_msg_with_decimal_value() {
+        let buf = "WRITE_LVAR A32NX_AUTOPILOT_HEADING 245.5";
+        let msg = RawInputMessage::from_str(&buf).unwrap();
+        assert_eq!(
+            msg, 
+            RawInputMessage::write_lvar("A32NX_AUTOPILOT_HEADING", Value::Float(245.5)));
+    }
+
+    #[test]
+    fn should_parse_write_lvar_msg_with_bool_and_string_values() {
+        assert_eq!(
+            RawInputMessage::from_str("WRITE_LVAR foobar true").unwrap(),
+            RawInputMessage::write_lvar("foobar", Value::Bool(true)));
+        assert_eq!(
+            RawInputMessage::from_str(r#"WRITE_LVAR foobar "say \"hello world\"""#).unwrap(),
+            RawInputMessage::write_lvar("foobar", Value::Str("say \"hello world\"".to_string())));
+        assert!(RawInputMessage::from_str(r#"WRITE_LVAR foobar "unterminated"#).is_err());
+        assert!(RawInputMessage::from_str("WRITE_LVAR foobar baz").is_err());
     }
 
     #[test]
@@ -151,7 +254,7 @@ mod tests {
             msg,
             RawInputMessage::write_offset(
                 Offset::from(0x1234, 2).unwrap(),
-                Value::Number(42)));
+                Value::I64(42)));
     }
 
     #[test]
@@ -168,6 +271,26 @@ mod tests {
         assert_eq!(msg, RawInputMessage::obs_offset(Offset::from(0x0330, 2).unwrap()));
     }
 
+    #[test]
+    fn should_parse_unobs_msgs() {
+        assert_eq!(
+            RawInputMessage::from_str("UNOBS_LVAR foobar").unwrap(),
+            RawInputMessage::unobs_lvar("foobar"));
+        assert_eq!(
+            RawInputMessage::from_str("UNOBS_OFFSET 330+2").unwrap(),
+            RawInputMessage::unobs_offset(Offset::from(0x0330, 2).unwrap()));
+    }
+
+    #[test]
+    fn should_parse_read_msgs() {
+        assert_eq!(
+            RawInputMessage::from_str("READ_LVAR foobar").unwrap(),
+            RawInputMessage::read_lvar("foobar"));
+        assert_eq!(
+            RawInputMessage::from_str("READ_OFFSET 330+2").unwrap(),
+            RawInputMessage::read_offset(Offset::from(0x0330, 2).unwrap()));
+    }
+
     #[test]
     fn should_fail_to_parse_empty_line() {
         let buf = "";
@@ -7,92 +7,174 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use std::io;
-use std::io::{BufRead, Write};
-use std::str::FromStr;
+use std::io::BufRead;
 
 use domain::DomainDispatcher;
 use io::*;
 use proto::*;
 use types::*;
 
+mod dialect;
+mod discovery;
 mod input;
 mod output;
+mod udp;
 
+use self::dialect::Dialect;
 use self::input::RawInputMessage;
 use self::output::RawOutputMessage;
 
-const PROTOCOL_VERSION: u16 = 2;
+pub use self::discovery::OacspDiscovery;
+pub use self::udp::OacspUdp;
 
-pub struct Oacsp {
-    dev: Device,
+/// The ID FlightVars identifies itself with in the reply to `BEGIN` messages.
+const SERVER_ID: &'static str = "flightvars";
+
+const ERROR_INVALID_MESSAGE: u16 = 1;
+const ERROR_UNEXPECTED_MESSAGE: u16 = 2;
+const ERROR_NOT_FOUND: u16 = 3;
+const ERROR_INTERNAL: u16 = 4;
+
+/// The state of an OACSP session, regardless of the transport that carries it.
+///
+/// A session is identified by a device ID, which is used to subscribe to domain
+/// variables on behalf of the client. Stream transports use the ID of their device,
+/// while datagram transports allocate a logical ID for each remote peer. 
+///
+/// The OACSP version of the session is negotiated with the `BEGIN` message. Sessions
+/// of OACSP v3 reply to every message, either with `OK`, with the requested value or
+/// with an `ERROR` that leaves the session open. Sessions of older versions get no
+/// replies, and any error closes them.
+pub struct Session {
+    id: DeviceId,
     domains: DomainDispatcher,
-    client_id: Option<String>
+    client_id: Option<String>,
+    dialect: Option<Dialect>,
 }
 
-impl Oacsp {
+impl Session {
     
-    pub fn new(dev: Device, domains: DomainDispatcher) -> Oacsp {
-        Oacsp { dev: dev, domains: domains, client_id: None }
+    pub fn new(id: DeviceId, domains: DomainDispatcher) -> Session {
+        Session { id: id, domains: domains, client_id: None, dialect: None }
     }
     
-    fn line_is_ready(&self) -> bool {
-        self.dev.recv_bytes().contains(&b'\n')
+    pub fn id(&self) -> DeviceId { self.id }
+
+    /// Ends the session, removing its subscriptions from all the domains.
+    pub fn end(&mut self) -> io::Result<()> {
+        self.domains.unsubscribe_all(self.id)
+    }
+    
+    pub fn client_id(&self) -> &str {
+        self.client_id
+        	.as_ref()
+        	.map(|id| id.as_str())
+        	.unwrap_or("none")
     }
 
-    fn process_input(&mut self) -> io::Result<usize> {
-        assert!(self.line_is_ready());
-        let dev_id = self.dev.id();
-        let mut buf = io::BufReader::new(self.dev.recv_bytes());
-        let mut line = String::new();
-        let nbytes = try!(buf.read_line(&mut line));
+    /// The ID of the client, if its `BEGIN` message was already received.
+    pub fn client(&self) -> Option<&str> {
+        self.client_id.as_ref().map(|id| id.as_str())
+    }
+
+    /// Processes a line received from the client, returning the reply to be sent back. 
+    pub fn process_line(&mut self, line: &str) -> io::Result<Vec<u8>> {
+        let reply = match self.process_message(line) {
+            Ok(reply) => reply,
+            Err(ref e) if self.replies() => {
+                error!("cannot process message '{}' from client {}: {:?}", 
+                    line.trim(), self.client_id(), e);
+                RawOutputMessage::error(error_code(e), &e.to_string())
+            }
+            Err(e) => return Err(e),
+        };
+        let mut buf = Vec::new();
+        if self.replies() {
+            try!(self.dialect().encode(&reply, &mut buf));
+        }
+        Ok(buf)
+    }
+
+    fn process_message(&mut self, line: &str) -> io::Result<RawOutputMessage> {
+        let dev_id = self.id;
         let begin_received = self.client_id.is_some();
-        match (try!(RawInputMessage::from_str(&line)), begin_received) {
+        let msg = try!(self.dialect().parse(line));
+        match (msg, begin_received) {
             (RawInputMessage::Begin { version, client_id }, false) => {
-                if version != PROTOCOL_VERSION {
-                    let error = io::Error::new(
-                        io::ErrorKind::InvalidInput,
-                        format!("OACSP version {} is not supported by this version of FlightVars",
-                        	version));
-                    return Err(error);
-                }
-                info!("received a begin message from client {}", client_id);
+                let dialect = try!(Dialect::negotiate(version).ok_or(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("OACSP version {} is not supported by this version of FlightVars",
+                    	version))));
+                info!("received a begin message from client {} for OACSP version {}, \
+                    using version {}", client_id, version, dialect.version());
             	self.client_id = Some(client_id);
-            	Ok(nbytes)
+            	self.dialect = Some(dialect);
+            	Ok(RawOutputMessage::begin(dialect.version(), SERVER_ID))
             },
             (RawInputMessage::Begin { version: _, client_id: _ }, true) => {
 				Err(io::Error::new(io::ErrorKind::InvalidData, "begin message already received"))                    
             }
             (RawInputMessage::WriteLvar { lvar, value }, true) => {
                 debug!("received a WRITE_LVAR message from client {}: {} <- {}", 
-                    self.client_id_str(), lvar, value);
+                    self.client_id(), lvar, value);
                 try!(self.domains.with_domain("lvar", |dom| {
 					dom.write(&Var::Named(lvar), &value)                        
                 }));
-                Ok(nbytes)
+                Ok(RawOutputMessage::Ok)
             }
             (RawInputMessage::WriteOffset { offset, value }, true) => {
                 debug!("received a WRITE_OFFSET message from client {}: {} <- {}", 
-                    self.client_id_str(), offset, value);
+                    self.client_id(), offset, value);
                 try!(self.domains.with_domain("fsuipc", |dom| {
 					dom.write(&Var::Offset(offset), &value)                        
                 }));
-                Ok(nbytes)
+                Ok(RawOutputMessage::Ok)
             }
             (RawInputMessage::ObserveLvar { lvar }, true) => {
                 debug!("received a OBSERVE_LVAR message from client {}: {}", 
-                    self.client_id_str(), lvar);
+                    self.client_id(), lvar);
                 try!(self.domains.with_domain("lvar", |dom| {
 					dom.subscribe(dev_id, &Var::Named(lvar))                        
                 }));
-                Ok(nbytes)
+                Ok(RawOutputMessage::Ok)
             }
             (RawInputMessage::ObserveOffset { offset }, true) => {
                 debug!("received a OBSERVE_OFFSET message from client {}: {}", 
-                    self.client_id_str(), offset);
+                    self.client_id(), offset);
                 try!(self.domains.with_domain("fsuipc", |dom| {
 					dom.subscribe(dev_id, &Var::Offset(offset))                        
                 }));
-                Ok(nbytes)
+                Ok(RawOutputMessage::Ok)
+            }
+            (RawInputMessage::UnobserveLvar { lvar }, true) => {
+                debug!("received a UNOBS_LVAR message from client {}: {}", 
+                    self.client_id(), lvar);
+                try!(self.domains.with_domain("lvar", |dom| {
+					dom.unsubscribe(dev_id, &Var::Named(lvar))                        
+                }));
+                Ok(RawOutputMessage::Ok)
+            }
+            (RawInputMessage::UnobserveOffset { offset }, true) => {
+                debug!("received a UNOBS_OFFSET message from client {}: {}", 
+                    self.client_id(), offset);
+                try!(self.domains.with_domain("fsuipc", |dom| {
+					dom.unsubscribe(dev_id, &Var::Offset(offset))                        
+                }));
+                Ok(RawOutputMessage::Ok)
+            }
+            (RawInputMessage::ReadLvar { lvar }, true) => {
+                debug!("received a READ_LVAR message from client {}: {}", 
+                    self.client_id(), lvar);
+                let variable = Var::Named(lvar);
+                let value = try!(self.domains.with_domain("lvar", |dom| dom.read(&variable)));
+                event_message("lvar", variable, value)
+            }
+            (RawInputMessage::ReadOffset { offset }, true) => {
+                debug!("received a READ_OFFSET message from client {}: {}", 
+                    self.client_id(), offset);
+                let variable = Var::Offset(offset);
+                let value = try!(self.domains.with_domain("fsuipc", |dom| dom.read(&variable)));
+                event_message("fsuipc", variable, value)
             }
             (_, false) =>  {
                 let error = io::Error::new(
@@ -102,12 +184,89 @@ impl Oacsp {
             }
         }                
     }
+
+    /// Encodes an update of the given variable, or `None` if the value cannot be sent
+    /// with the OACSP version of the session.
+    pub fn encode_update(&self, domain: &str, variable: Var, value: Value)
+        -> io::Result<Option<Vec<u8>>> {
+        let raw = try!(event_message(domain, variable, value));
+        let mut buf = Vec::new();
+        match self.dialect().encode(&raw, &mut buf) {
+            Ok(()) => Ok(Some(buf)),
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {
+                warn!("cannot send update to client {}: {}", self.client_id(), e);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The dialect of the session, which is the latest one until `BEGIN` is received.
+    fn dialect(&self) -> Dialect {
+        self.dialect.unwrap_or(Dialect::latest())
+    }
+
+    fn replies(&self) -> bool {
+        self.dialect.map(|d| d.replies()).unwrap_or(false)
+    }
+}
+
+fn event_message(domain: &str, variable: Var, value: Value) -> io::Result<RawOutputMessage> {
+    match variable {
+        Var::Offset(offset) if domain == "fsuipc" =>
+            Ok(RawOutputMessage::EventOffset { offset: offset, value: value }),
+        Var::Named(ref lvar) if domain == "lvar" =>
+            Ok(RawOutputMessage::EventLvar { lvar: lvar.clone(), value: value }),
+        _ => {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot encode a message for domain '{}', var '{:?}'", 
+                    domain, variable)))
+        }
+    }
+}
+
+/// Maps an IO error onto the code of the `ERROR` reply that reports it.
+fn error_code(error: &io::Error) -> u16 {
+    match error.kind() {
+        io::ErrorKind::InvalidInput => ERROR_INVALID_MESSAGE,
+        io::ErrorKind::InvalidData => ERROR_UNEXPECTED_MESSAGE,
+        io::ErrorKind::NotFound => ERROR_NOT_FOUND,
+        _ => ERROR_INTERNAL,
+    }
+}
+
+pub struct Oacsp {
+    dev: Device,
+    session: Session,
+}
+
+impl Oacsp {
     
-    fn client_id_str(&self) -> &str {
-        self.client_id
-        	.as_ref()
-        	.map(|id| id.as_str())
-        	.unwrap_or("none")
+    pub fn new(dev: Device, domains: DomainDispatcher) -> Oacsp {
+        let session = Session::new(dev.id(), domains);
+        Oacsp { dev: dev, session: session }
+    }
+    
+    /// The ID of the client, if its `BEGIN` message was already received.
+    pub fn client(&self) -> Option<&str> { self.session.client() }
+
+    fn line_is_ready(&self) -> bool {
+        self.dev.recv_bytes().contains(&b'\n')
+    }
+
+    fn process_input(&mut self) -> io::Result<usize> {
+        assert!(self.line_is_ready());
+        let mut line = String::new();
+        let nbytes = {
+            let mut buf = io::BufReader::new(self.dev.recv_bytes());
+            try!(buf.read_line(&mut line))
+        };
+        let reply = try!(self.session.process_line(&line));
+        if !reply.is_empty() {
+            try!(self.dev.request_write(&reply));
+        }
+        Ok(nbytes)
     }
 }
 
@@ -117,6 +276,11 @@ impl DeviceHandler for Oacsp {
     fn process_event(&mut self, event: Event) -> io::Result<()> {
         match event {
             Event::Ready => self.dev.request_read(),
+            Event::BytesRead(0) => {
+                info!("device {} was disconnected by client {}", 
+                    self.dev.name(), self.session.client_id());
+                self.dev.close()
+            }
             Event::BytesRead(_) => {
                 while self.line_is_ready() {
                     let nread = try!(self.process_input());
@@ -125,6 +289,7 @@ impl DeviceHandler for Oacsp {
                 self.dev.request_read()
             }
             Event::BytesWritten(_) => Ok(()),
+            Event::Closed => self.session.end(),
         }
     }    
 }
@@ -132,20 +297,115 @@ impl DeviceHandler for Oacsp {
 impl Protocol for Oacsp {
         
     fn send_update(&mut self, domain: &str, variable: Var, value: Value) -> io::Result<()> {
-        let raw = try!(match variable {
-            Var::Offset(offset) if domain == "fsuipc" =>
-                Ok(RawOutputMessage::EventOffset { offset: offset, value: value }),
-            Var::Named(ref lvar) if domain == "lvar" =>
-                Ok(RawOutputMessage::EventLvar { lvar: lvar.clone(), value: value }),
-            _ => {
-                Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    format!("cannot encode a message for domain '{}', var '{:?}'", 
-                        domain, variable)))
-            }
-        });        
-        let mut buf = Vec::new();
-        try!(write!(&mut buf, "{}\n", raw));
-        self.dev.request_write(&buf)
+        match try!(self.session.encode_update(domain, variable, value)) {
+            Some(buf) => self.dev.request_write(&buf),
+            None => Ok(()),
+        }
     }    
 }
+
+#[cfg(test)]
+mod tests {
+
+    use config::DomainSettings;
+    use domain::*;
+
+    use super::*;
+
+    #[test]
+    fn should_not_reply_to_oacsp_v2_clients() {
+        let (mut session, lvars) = session();
+        lvars.define("foobar", 0.0);
+        assert!(session.process_line("BEGIN 2 arduino").unwrap().is_empty());
+        assert!(session.process_line("WRITE_LVAR foobar 42").unwrap().is_empty());
+        assert!(session.process_line("READ_LVAR foobar").is_err());
+        assert!(session.process_line("FOOBAR").is_err());
+    }
+
+    #[test]
+    fn should_reply_to_oacsp_v3_clients() {
+        let (mut session, lvars) = session();
+        lvars.define("foobar", 42.0);
+        assert_eq!(reply(&mut session, "BEGIN 3 arduino"), "BEGIN 3 flightvars\n");
+        assert_eq!(reply(&mut session, "WRITE_LVAR foobar 43"), "OK\n");
+        assert_eq!(reply(&mut session, "OBS_LVAR foobar"), "OK\n");
+        assert_eq!(reply(&mut session, "UNOBS_LVAR foobar"), "OK\n");
+        assert_eq!(reply(&mut session, "READ_LVAR foobar"), "EVENT_LVAR foobar 42\n");
+    }
+
+    #[test]
+    fn should_round_trip_decimal_lvar_values() {
+        let (mut session, lvars) = session();
+        lvars.define("A32NX_AUTOPILOT_HEADING", 0.0);
+        reply(&mut session, "BEGIN 3 arduino");
+        assert_eq!(reply(&mut session, "WRITE_LVAR A32NX_AUTOPILOT_HEADING 245.5"), "OK\n");
+        let mut events = Vec::new();
+        session.domains.with_all_domains(|d| d.poll(&mut events)).unwrap();
+        assert_eq!(lvars.value("A32NX_AUTOPILOT_HEADING"), Some(245.5));
+        assert_eq!(
+            reply(&mut session, "READ_LVAR A32NX_AUTOPILOT_HEADING"), 
+            "EVENT_LVAR A32NX_AUTOPILOT_HEADING 245.5\n");
+    }
+
+    #[test]
+    fn should_reply_errors_to_oacsp_v3_clients() {
+        let (mut session, _) = session();
+        assert_eq!(reply(&mut session, "BEGIN 3 arduino"), "BEGIN 3 flightvars\n");
+        assert_eq!(
+            reply(&mut session, "FOOBAR"), 
+            "ERROR 1 invalid oacsp message in 'FOOBAR'\n");
+        assert_eq!(
+            reply(&mut session, "BEGIN 3 arduino"), 
+            "ERROR 2 begin message already received\n");
+        assert_eq!(
+            reply(&mut session, "READ_LVAR foobar"), 
+            "ERROR 3 there is no such lvar named foobar\n");
+    }
+
+    #[test]
+    fn should_not_send_events_after_unobserve() {
+        let (mut session, lvars) = session();
+        lvars.define("foobar", 42.0);
+        reply(&mut session, "BEGIN 3 arduino");
+        reply(&mut session, "OBS_LVAR foobar");
+        reply(&mut session, "UNOBS_LVAR foobar");
+        let mut events = Vec::new();
+        session.domains.with_all_domains(|d| d.poll(&mut events)).unwrap();
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn should_serve_oacsp_v1_clients() {
+        let (mut session, _) = session();
+        assert!(session.process_line("BEGIN 1 arduino").unwrap().is_empty());
+        assert!(session.process_line("OBS_OFFSET 0330:2").unwrap().is_empty());
+        assert!(session.process_line("OBS_LVAR foobar").is_err());
+        let update = session.encode_update(
+            "fsuipc", Var::Offset(Offset::from(0x0330, 2).unwrap()), Value::I64(42));
+        assert_eq!(update.unwrap().unwrap(), b"EVENT_OFFSET 330:2 42\n");
+    }
+
+    #[test]
+    fn should_negotiate_latest_version_with_newer_clients() {
+        let (mut session, _) = session();
+        assert_eq!(reply(&mut session, "BEGIN 4 arduino"), "BEGIN 3 flightvars\n");
+    }
+
+    #[test]
+    fn should_reject_unsupported_versions() {
+        let (mut session, _) = session();
+        assert!(session.process_line("BEGIN 0 arduino").is_err());
+    }
+
+    fn session() -> (Session, lvar::MemoryBackend) {
+        let lvars = lvar::MemoryBackend::new();
+        let mut domains = DomainDispatcher::new(&DomainSettings { enabled: vec![] }).unwrap();
+        domains.add("lvar", lvar::LVar::new(lvars.clone()));
+        domains.add("fsuipc", fsuipc::Fsuipc::new(fsuipc::MemoryBackend::new()));
+        (Session::new(1, domains), lvars)
+    }
+
+    fn reply(session: &mut Session, line: &str) -> String {
+        String::from_utf8(session.process_line(line).unwrap()).unwrap()
+    }
+}
@@ -13,7 +13,10 @@ use types::*;
 #[derive(Clone, Debug, PartialEq)]
 pub enum RawOutputMessage {
     EventLvar { lvar: String, value: Value },
-    EventOffset { offset: Offset, value: Value }
+    EventOffset { offset: Offset, value: Value },
+    Begin { version: u16, server_id: String },
+    Ok,
+    Error { code: u16, message: String },
 }
 
 impl RawOutputMessage {
@@ -26,15 +29,30 @@ impl RawOutputMessage {
     pub fn event_offset(offset: Offset, value: Value) -> RawOutputMessage {
         RawOutputMessage::EventOffset { offset: offset, value: value }
     }
+
+    pub fn begin(version: u16, server_id: &str) -> RawOutputMessage {
+        RawOutputMessage::Begin { version: version, server_id: server_id.to_string() }
+    }
+
+    /// Reports an error, whose message is kept in a single line.
+    pub fn error(code: u16, message: &str) -> RawOutputMessage {
+        let message = message.lines().collect::<Vec<_>>().join(" ");
+        RawOutputMessage::Error { code: code, message: message }
+    }
 }
 
 impl fmt::Display for RawOutputMessage {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
-            &RawOutputMessage::EventLvar { ref lvar, value } =>
+            &RawOutputMessage::EventLvar { ref lvar, ref value } =>
                 write!(f, "EVENT_LVAR {} {}", lvar, value),
-            &RawOutputMessage::EventOffset { ref offset, value } =>
+            &RawOutputMessage::EventOffset { ref offset, ref value } =>
                 write!(f, "EVENT_OFFSET {} {}", offset, value),
+            &RawOutputMessage::Begin { version, ref server_id } =>
+                write!(f, "BEGIN {} {}", version, server_id),
+            &RawOutputMessage::Ok => write!(f, "OK"),
+            &RawOutputMessage::Error { code, ref message } =>
+                write!(f, "ERROR {} {}", code, message),
         }
     }
 }
@@ -48,15 +66,24 @@ mod tests {
 
     #[test]
     fn should_display_event_lvar_msg() {
-        let msg = RawOutputMessage::event_lvar("foobar", Value::Number(42));
+        let msg = RawOutputMessage::event_lvar("foobar", Value::I64(42));
         let buf = format!("{}", msg);
         assert_eq!(buf, "EVENT_LVAR foobar 42")
     }
 
     #[test]
     fn should_display_event_offset_msg() {
-        let msg = RawOutputMessage::event_offset(Offset::from(0x1234, 2).unwrap(), Value::Number(42));
+        let msg = RawOutputMessage::event_offset(Offset::from(0x1234, 2).unwrap(), Value::I64(42));
         let buf = format!("{}", msg);
         assert_eq!(buf, "EVENT_OFFSET 1234+2 42")
     }
+
+    #[test]
+    fn should_display_replies() {
+        assert_eq!(format!("{}", RawOutputMessage::begin(3, "flightvars")), "BEGIN 3 flightvars");
+        assert_eq!(format!("{}", RawOutputMessage::Ok), "OK");
+        assert_eq!(
+            format!("{}", RawOutputMessage::error(3, "no such lvar\nfoobar")),
+            "ERROR 3 no such lvar foobar");
+    }
 }
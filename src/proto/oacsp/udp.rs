@@ -0,0 +1,291 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+use std::io;
+use std::net;
+use std::str;
+use std::time::{Duration, Instant};
+
+use domain::DomainDispatcher;
+use types::*;
+
+use super::Session;
+
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+struct Peer {
+    session: Session,
+    last_activity: Instant,
+}
+
+/// An endpoint that serves OACSP to the peers of a UDP socket. 
+///
+/// Each remote address is mapped to its own OACSP session with a logical device ID. 
+/// A datagram carries one or more OACSP lines, and the trailing line feed may be
+/// omitted for the last one. Empty lines are ignored, so they can be used as 
+/// keep-alive messages. Sessions with no datagrams received for a while are expired,
+/// and their subscriptions are removed from the domains. The events sent to a peer
+/// do not count as activity, since UDP cannot tell whether the peer is still there.
+pub struct OacspUdp {
+    name: String,
+    socket: net::UdpSocket,
+    domains: DomainDispatcher,
+    peers: HashMap<net::SocketAddr, Peer>,
+    addrs: HashMap<DeviceId, net::SocketAddr>,
+    session_timeout: Duration,
+    buffer: Vec<u8>,
+}
+
+impl OacspUdp {
+    
+    pub fn bind(addr: &str, 
+                domains: DomainDispatcher, 
+                session_timeout: Duration) -> io::Result<OacspUdp> {
+        let socket = try!(net::UdpSocket::bind(addr));
+        try!(socket.set_nonblocking(true));
+        let name = format!("udp://{}", try!(socket.local_addr()));
+        Ok(OacspUdp {
+            name: name,
+            socket: socket,
+            domains: domains,
+            peers: HashMap::new(),
+            addrs: HashMap::new(),
+            session_timeout: session_timeout,
+            buffer: vec![0; MAX_DATAGRAM_SIZE],
+        })
+    }
+    
+    pub fn name(&self) -> &str { &self.name }
+    
+    #[allow(dead_code)]
+    pub fn local_addr(&self) -> io::Result<net::SocketAddr> {
+        self.socket.local_addr()
+    }
+    
+    pub fn has_session(&self, id: DeviceId) -> bool {
+        self.addrs.contains_key(&id)
+    }
+    
+    /// Processes all the datagrams received so far, without blocking.
+    ///
+    /// The peers that sent them are considered active at the given instant.
+    pub fn process_datagrams(&mut self, now: Instant) -> io::Result<()> {
+        loop {
+            let (nbytes, addr) = match self.socket.recv_from(&mut self.buffer) {
+                Ok(result) => result,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let data = self.buffer[..nbytes].to_vec();
+            if let Err(e) = self.process_datagram(&addr, &data, now) {
+                error!("unexpected error while processing datagram from {}: {:?}", addr, e);
+                info!("closing session of peer {} due to IO errors", addr);
+                self.close_session(&addr);
+            }
+        }
+    }
+    
+    /// Closes the sessions of the peers that have been idle for too long at the given
+    /// instant.
+    pub fn expire_sessions(&mut self, now: Instant) {
+        let expired: Vec<net::SocketAddr> = self.peers.iter()
+            .filter(|&(_, peer)| now.duration_since(peer.last_activity) >= self.session_timeout)
+            .map(|(addr, _)| *addr)
+            .collect();
+        for addr in expired {
+            info!("session of peer {} on {} expired", addr, self.name);
+            self.close_session(&addr);
+        }
+    }
+    
+    pub fn send_update(&mut self, 
+                       id: DeviceId, 
+                       domain: &str, 
+                       variable: Var, 
+                       value: Value) -> io::Result<()> {
+        let addr = try!(self.addrs.get(&id).cloned().ok_or(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no UDP session for device {}", id))));
+        if let Some(buf) = try!(self.peers[&addr].session.encode_update(domain, variable, value)) {
+            try!(self.socket.send_to(&buf, addr));
+        }
+        Ok(())
+    }
+    
+    fn process_datagram(&mut self,
+                        addr: &net::SocketAddr,
+                        data: &[u8],
+                        now: Instant) -> io::Result<()> {
+        let text = try!(str::from_utf8(data).map_err(|_| io::Error::new(
+            io::ErrorKind::InvalidData, 
+            "datagram is not valid UTF-8 text")));
+        let mut replies = Vec::new();
+        {
+            let peer = self.peer(addr, now);
+            peer.last_activity = now;
+            for line in text.lines() {
+                if !line.trim().is_empty() {
+                    replies.extend(try!(peer.session.process_line(line)));
+                }
+            }
+        }
+        if !replies.is_empty() {
+            try!(self.socket.send_to(&replies, addr));
+        }
+        Ok(())
+    }
+    
+    fn peer(&mut self, addr: &net::SocketAddr, now: Instant) -> &mut Peer {
+        if !self.peers.contains_key(addr) {
+            let id = logical_device_id();
+            info!("new session for peer {} on {} with device ID {}", addr, self.name, id);
+            self.addrs.insert(id, *addr);
+            self.peers.insert(*addr, Peer {
+                session: Session::new(id, self.domains.clone()),
+                last_activity: now,
+            });
+        }
+        self.peers.get_mut(addr).unwrap()
+    }
+    
+    fn close_session(&mut self, addr: &net::SocketAddr) {
+        if let Some(mut peer) = self.peers.remove(addr) {
+            let id = peer.session.id();
+            self.addrs.remove(&id);
+            if let Err(e) = peer.session.end() {
+                error!("cannot remove subscriptions of peer {}: {:?}", addr, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::net;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use config::DomainSettings;
+    use domain::*;
+    use types::*;
+
+    use super::*;
+
+    #[test]
+    fn should_open_session_per_peer() {
+        let mut udp = oacsp_udp(Duration::from_secs(60));
+        let (_, id1) = send_datagram(&mut udp, b"BEGIN 2 client1\n");
+        let (_, id2) = send_datagram(&mut udp, b"BEGIN 2 client2\n");
+        assert!(id1 != id2);
+        assert!(udp.has_session(id1));
+        assert!(udp.has_session(id2));
+    }
+
+    #[test]
+    fn should_process_several_lines_per_datagram() {
+        let lvars = lvar::MemoryBackend::new();
+        lvars.define("foobar", 0.0);
+        let mut udp = oacsp_udp_with_lvars(Duration::from_secs(60), lvars.clone());
+        let (peer, id) = send_datagram(
+            &mut udp, b"BEGIN 2 client\nOBS_LVAR foobar\nWRITE_LVAR foobar 42");
+        let mut events = Vec::new();
+        udp.domains.with_all_domains(|d| d.poll(&mut events)).unwrap();
+        assert_eq!(lvars.value("foobar"), Some(42.0));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].device, id);
+        udp.send_update(id, "lvar", Var::named("foobar"), Value::I64(42)).unwrap();
+        let mut buf = [0; 1024];
+        let (nbytes, _) = peer.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..nbytes], b"EVENT_LVAR foobar 42\n");
+    }
+
+    #[test]
+    fn should_close_session_on_invalid_datagram() {
+        let mut udp = oacsp_udp(Duration::from_secs(60));
+        let (peer, id) = send_datagram(&mut udp, b"BEGIN 2 client\n");
+        peer.send_to(b"THIS IS NOT OACSP\n", udp.local_addr().unwrap()).unwrap();
+        process_datagrams(&mut udp);
+        assert!(!udp.has_session(id));
+    }
+
+    #[test]
+    fn should_reply_to_oacsp_v3_peers() {
+        let mut udp = oacsp_udp(Duration::from_secs(60));
+        let (peer, _) = send_datagram(&mut udp, b"BEGIN 3 client\nREAD_LVAR foobar\n");
+        let mut buf = [0; 1024];
+        let (nbytes, _) = peer.recv_from(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..nbytes], 
+            &b"BEGIN 3 flightvars\nERROR 3 there is no such lvar named foobar\n"[..]);
+    }
+
+    #[test]
+    fn should_expire_idle_sessions() {
+        let mut udp = oacsp_udp(Duration::from_secs(60));
+        let start = Instant::now();
+        let (_, id) = send_datagram_at(&mut udp, b"BEGIN 2 client\n", start);
+        udp.expire_sessions(start + Duration::from_secs(59));
+        assert!(udp.has_session(id));
+        udp.expire_sessions(start + Duration::from_secs(60));
+        assert!(!udp.has_session(id));
+    }
+
+    #[test]
+    fn should_keep_alive_sessions_on_empty_datagrams_only() {
+        let mut udp = oacsp_udp(Duration::from_secs(60));
+        let start = Instant::now();
+        let (peer, id) = send_datagram_at(
+            &mut udp, b"BEGIN 2 client\nOBS_LVAR foobar\n", start);
+        peer.send_to(b"\n", udp.local_addr().unwrap()).unwrap();
+        process_datagrams_at(&mut udp, start + Duration::from_secs(30));
+        udp.expire_sessions(start + Duration::from_secs(60));
+        assert!(udp.has_session(id));
+        udp.send_update(id, "lvar", Var::named("foobar"), Value::I64(42)).unwrap();
+        udp.expire_sessions(start + Duration::from_secs(90));
+        assert!(!udp.has_session(id));
+    }
+
+    fn oacsp_udp(timeout: Duration) -> OacspUdp {
+        oacsp_udp_with_lvars(timeout, lvar::MemoryBackend::new())
+    }
+
+    fn oacsp_udp_with_lvars(timeout: Duration, lvars: lvar::MemoryBackend) -> OacspUdp {
+        let mut domains = DomainDispatcher::new(&DomainSettings { enabled: vec![] }).unwrap();
+        domains.add("lvar", lvar::LVar::new(lvars));
+        OacspUdp::bind("127.0.0.1:0", domains, timeout).unwrap()
+    }
+
+    fn send_datagram(udp: &mut OacspUdp, data: &[u8]) -> (net::UdpSocket, DeviceId) {
+        send_datagram_at(udp, data, Instant::now())
+    }
+
+    fn send_datagram_at(udp: &mut OacspUdp,
+                        data: &[u8],
+                        now: Instant) -> (net::UdpSocket, DeviceId) {
+        let peer = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        peer.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        peer.send_to(data, udp.local_addr().unwrap()).unwrap();
+        process_datagrams_at(udp, now);
+        let id = *udp.addrs.keys().find(|id| udp.addrs[id] == peer.local_addr().unwrap()).unwrap();
+        (peer, id)
+    }
+
+    fn process_datagrams(udp: &mut OacspUdp) {
+        process_datagrams_at(udp, Instant::now());
+    }
+
+    /// Processes the datagrams received for a while, as if they arrived at `now`.
+    fn process_datagrams_at(udp: &mut OacspUdp, now: Instant) {
+        for _ in 0..10 {
+            udp.process_datagrams(now).unwrap();
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
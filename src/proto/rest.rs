@@ -0,0 +1,475 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A HTTP REST API to read and write domain variables.
+//!
+//! Variables are available at `/domains/{domain}/vars/{var}`. FSUIPC offsets are
+//! written as in OACSP (e.g., `/domains/fsuipc/vars/0BC8+2`), while any other domain
+//! uses named variables. `GET` reads the current value of the variable, and `PUT` or
+//! `POST` write the value given in the body, either as a JSON value like `42` or as
+//! an object like `{"value": 42}`. Responses are JSON objects.
+//!
+//! `GET /events` turns the connection into a stream of Server-Sent Events. The
+//! variables to observe are given as `var` parameters in the query string, qualified
+//! by their domain (e.g., `/events?var=lvar/A320_Gear_Lever&var=fsuipc/0BC8+2`). Each
+//! update is sent as a message whose data is the same JSON object returned by `GET`.
+//! Message IDs continue from the `Last-Event-ID` sent by reconnecting clients, which
+//! receive the current value of every variable right after subscribing again.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::io::Write;
+use std::str;
+
+use rustc_serialize::json::{Json, ToJson};
+
+use domain::DomainDispatcher;
+use io::*;
+use proto::*;
+use proto::{domain_var, qualified_var};
+use proto::http::{Request, Response, invalid_request_status};
+use types::*;
+
+/// The time clients should wait before reconnecting to an event stream, in milliseconds.
+const EVENT_STREAM_RETRY_MILLIS: u32 = 3000;
+
+/// The state of a connection turned into an event stream.
+struct EventStream {
+    next_id: u64,
+    names: HashMap<(String, Var), String>,
+}
+
+pub struct Rest {
+    dev: Device,
+    domains: DomainDispatcher,
+    close_on_written: bool,
+    stream: Option<EventStream>,
+}
+
+impl Rest {
+
+    pub fn new(dev: Device, domains: DomainDispatcher) -> Rest {
+        Rest { dev: dev, domains: domains, close_on_written: false, stream: None }
+    }
+
+    fn process_input(&mut self) -> io::Result<()> {
+        if self.stream.is_some() {
+            // Nothing is expected from clients once the stream starts
+            let nbytes = self.dev.recv_bytes().len();
+            self.dev.consume_recv_buffer(nbytes);
+            return Ok(());
+        }
+        while !self.close_on_written {
+            let decoded = Request::decode(self.dev.recv_bytes());
+            let response = match decoded {
+                Ok(Some((request, nbytes))) => {
+                    self.dev.consume_recv_buffer(nbytes);
+                    if request.header_has_token("Connection", "close") {
+                        self.close_on_written = true;
+                    }
+                    self.process_request(&request)
+                }
+                Ok(None) => return Ok(()),
+                Err(e) => {
+                    error!("closing HTTP connection {}: {:?}", self.dev.name(), e);
+                    self.close_on_written = true;
+                    let (status, reason) = invalid_request_status(&e);
+                    json_response(status, reason, error_json(&format!("{}", e)))
+                }
+            };
+            let mut buf = Vec::new();
+            if self.stream.is_some() {
+                try!(response.encode_stream_head(&mut buf));
+                try!(write!(buf, "retry: {}\n\n", EVENT_STREAM_RETRY_MILLIS));
+                return self.dev.request_write(&buf);
+            }
+            try!(response.encode(&mut buf));
+            try!(self.dev.request_write(&buf));
+        }
+        Ok(())
+    }
+
+    fn process_request(&mut self, request: &Request) -> Response {
+        debug!("received HTTP request {} {} from {}",
+            request.method, request.path, self.dev.name());
+        let mut target = request.path.splitn(2, '?');
+        let path = target.next().unwrap_or("");
+        let query = target.next().unwrap_or("");
+        if path.trim_matches('/') == "events" {
+            if request.method != "GET" {
+                let response = json_response(405, "Method Not Allowed", error_json(
+                    &format!("method {} is not allowed", request.method)));
+                return response.with_header("Allow", "GET");
+            }
+            return self.start_event_stream(request, query).unwrap_or_else(|e| error_response(&e));
+        }
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        if segments.len() != 4 || segments[0] != "domains" || segments[2] != "vars" {
+            let error = io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no such resource '{}'", path));
+            return error_response(&error);
+        }
+        let result = percent_decode(segments[1]).and_then(|domain| {
+            let name = try!(percent_decode(segments[3]));
+            let variable = try!(domain_var(&domain, &name));
+            match &request.method[..] {
+                "GET" => {
+                    let value = try!(self.domains.with_domain(&domain, |dom| {
+                        dom.read(&variable)
+                    }));
+                    Ok(var_response(200, "OK", &domain, &name, value))
+                }
+                "PUT" | "POST" => {
+                    let value = try!(body_value(&request.body));
+                    try!(self.domains.with_domain(&domain, |dom| {
+                        dom.write(&variable, &value)
+                    }));
+                    Ok(var_response(202, "Accepted", &domain, &name, value))
+                }
+                _ => {
+                    let response = json_response(405, "Method Not Allowed", error_json(
+                        &format!("method {} is not allowed", request.method)));
+                    Ok(response.with_header("Allow", "GET, PUT, POST"))
+                }
+            }
+        });
+        result.unwrap_or_else(|e| error_response(&e))
+    }
+
+    fn start_event_stream(&mut self, request: &Request, query: &str) -> io::Result<Response> {
+        let mut names = HashMap::new();
+        for param in query.split('&') {
+            let mut pair = param.splitn(2, '=');
+            match (pair.next(), pair.next()) {
+                (Some("var"), Some(value)) => {
+                    let (domain, name) = try!(qualified_var(&try!(percent_decode(value))));
+                    let variable = try!(domain_var(&domain, &name));
+                    names.insert((domain, variable), name);
+                }
+                _ => {}
+            }
+        }
+        if names.is_empty() {
+            let error = io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no variables to stream were given in var parameters");
+            return Err(error);
+        }
+        let dev_id = self.dev.id();
+        for &(ref domain, ref variable) in names.keys() {
+            let result = self.domains.with_domain(domain, |dom| dom.subscribe(dev_id, variable));
+            if let Err(e) = result {
+                self.unsubscribe_all();
+                return Err(e);
+            }
+        }
+        let last_id = request.header("Last-Event-ID")
+            .and_then(|id| id.parse().ok())
+            .unwrap_or(0);
+        info!("streaming events of {} variables to HTTP client {}", names.len(), self.dev.name());
+        self.stream = Some(EventStream { next_id: last_id + 1, names: names });
+        let response = Response::new(200, "OK")
+            .with_header("Content-Type", "text/event-stream")
+            .with_header("Cache-Control", "no-cache");
+        Ok(response)
+    }
+
+    fn unsubscribe_all(&mut self) {
+        let dev_id = self.dev.id();
+        if let Err(e) = self.domains.unsubscribe_all(dev_id) {
+            error!("cannot unsubscribe HTTP client {}: {:?}", self.dev.name(), e);
+        }
+    }
+}
+
+impl DeviceHandler for Rest {
+    fn device(&mut self) -> &mut Device { &mut self.dev }
+
+    fn process_event(&mut self, event: Event) -> io::Result<()> {
+        match event {
+            Event::Ready => self.dev.request_read(),
+            Event::BytesRead(0) => {
+                debug!("device {} was disconnected by HTTP client", self.dev.name());
+                self.dev.close()
+            }
+            Event::BytesRead(_) => {
+                try!(self.process_input());
+                if self.close_on_written { Ok(()) } else { self.dev.request_read() }
+            }
+            Event::BytesWritten(_) if self.close_on_written => self.dev.close(),
+            Event::BytesWritten(_) => Ok(()),
+            Event::Closed => {
+                if self.stream.is_some() {
+                    self.unsubscribe_all();
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Protocol for Rest {
+
+    fn send_update(&mut self, domain: &str, variable: Var, value: Value) -> io::Result<()> {
+        let message = match self.stream {
+            Some(ref mut stream) => {
+                match stream.names.get(&(domain.to_string(), variable)) {
+                    Some(name) => {
+                        let id = stream.next_id;
+                        stream.next_id += 1;
+                        format!("id: {}\ndata: {}\n\n", id, var_json(domain, name, value))
+                    }
+                    None => return Ok(()),
+                }
+            }
+            None => return Ok(()),
+        };
+        self.dev.request_write(message.as_bytes())
+    }
+}
+
+/// Decodes the percent-encoded characters of a path segment.
+fn percent_decode(segment: &str) -> io::Result<String> {
+    let invalid_segment = || io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid percent-encoding in '{}'", segment));
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if i + 3 > bytes.len() {
+                return Err(invalid_segment());
+            }
+            let hex = try!(str::from_utf8(&bytes[i + 1..i + 3]).ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(&invalid_segment));
+            decoded.push(hex);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| invalid_segment())
+}
+
+/// Parses the value to write from a request body.
+fn body_value(body: &[u8]) -> io::Result<Value> {
+    let invalid_body = || io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "request body must be a JSON number or boolean, or an object with a value");
+    let text = try!(str::from_utf8(body).map_err(|_| invalid_body()));
+    let json = try!(Json::from_str(text.trim()).map_err(|_| invalid_body()));
+    let json = match json {
+        Json::Object(mut object) => try!(object.remove("value").ok_or_else(&invalid_body)),
+        other => other,
+    };
+    Value::from_json(&json).ok_or_else(invalid_body)
+}
+
+fn var_response(status: u16, reason: &str, domain: &str, name: &str, value: Value) -> Response {
+    json_response(status, reason, var_json(domain, name, value))
+}
+
+fn var_json(domain: &str, name: &str, value: Value) -> Json {
+    let mut object = BTreeMap::new();
+    object.insert("domain".to_string(), Json::String(domain.to_string()));
+    object.insert("var".to_string(), Json::String(name.to_string()));
+    object.insert("value".to_string(), value.to_json());
+    Json::Object(object)
+}
+
+fn error_response(error: &io::Error) -> Response {
+    let (status, reason) = match error.kind() {
+        io::ErrorKind::NotFound => (404, "Not Found"),
+        io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => (400, "Bad Request"),
+        _ => (500, "Internal Server Error"),
+    };
+    json_response(status, reason, error_json(&format!("{}", error)))
+}
+
+fn error_json(message: &str) -> Json {
+    let mut object = BTreeMap::new();
+    object.insert("error".to_string(), Json::String(message.to_string()));
+    Json::Object(object)
+}
+
+fn json_response(status: u16, reason: &str, json: Json) -> Response {
+    Response::new(status, reason).with_body("application/json", json.to_string().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::iter;
+
+    use domain::*;
+    use io::*;
+    use proto::test_util::*;
+
+    use super::*;
+    use super::{body_value, percent_decode};
+
+    #[test]
+    fn should_decode_path_segments() {
+        assert_eq!(percent_decode("A320%20Gear").unwrap(), "A320 Gear");
+        assert_eq!(percent_decode("0BC8+2").unwrap(), "0BC8+2");
+        assert!(percent_decode("foo%2").is_err());
+        assert!(percent_decode("foo%zz").is_err());
+    }
+
+    #[test]
+    fn should_parse_body_values() {
+        assert_eq!(body_value(b"42").unwrap(), Value::I64(42));
+        assert_eq!(body_value(b"-1\n").unwrap(), Value::I64(-1));
+        assert_eq!(body_value(b"true").unwrap(), Value::Bool(true));
+        assert_eq!(body_value(br#"{"value": 7}"#).unwrap(), Value::I64(7));
+        assert_eq!(body_value(b"1.5").unwrap(), Value::Float(1.5));
+        assert!(body_value(br#"{"foo": 7}"#).is_err());
+        assert!(body_value(b"foobar").is_err());
+    }
+
+    #[test]
+    fn should_read_variables() {
+        let (mut reactor, mut client, lvars) = http_client();
+        lvars.define("foobar", 42.0);
+        let response = request(&mut reactor, &mut client, "GET", "/domains/lvar/vars/foobar", "");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(r#"{"domain":"lvar","value":42,"var":"foobar"}"#));
+    }
+
+    #[test]
+    fn should_write_variables() {
+        let (mut reactor, mut client, lvars) = http_client();
+        lvars.define("foobar", 0.0);
+        let response = request(&mut reactor, &mut client, "PUT", "/domains/lvar/vars/foobar", "42");
+        assert!(response.starts_with("HTTP/1.1 202 Accepted\r\n"));
+        let mut events = Vec::new();
+        reactor.domains.with_all_domains(|d| d.poll(&mut events)).unwrap();
+        assert_eq!(lvars.value("foobar"), Some(42.0));
+    }
+
+    #[test]
+    fn should_fail_with_not_found() {
+        let (mut reactor, mut client, _) = http_client();
+        let response = request(&mut reactor, &mut client, "GET", "/domains/lvar/vars/foobar", "");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let response = request(&mut reactor, &mut client, "GET", "/domains/foo/vars/bar", "");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let response = request(&mut reactor, &mut client, "GET", "/foobar", "");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn should_fail_with_bad_request() {
+        let (mut reactor, mut client, _) = http_client();
+        let response = request(&mut reactor, &mut client, "PUT", "/domains/lvar/vars/foobar", "x");
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn should_fail_with_too_large_requests() {
+        // The server answers as soon as it knows the request is too large, without
+        // waiting for the rest of it
+        let (mut reactor, mut client, _) = http_client();
+        let body: String = iter::repeat('4').take(3000).collect();
+        client.send(format!("PUT /domains/lvar/vars/foobar HTTP/1.1\r\n\
+            Content-Length: 5000\r\n\r\n{}", body).as_bytes()).unwrap();
+        reactor.run();
+        let response = String::from_utf8(client.recv().unwrap()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        let (mut reactor, mut client, _) = http_client();
+        let name: String = iter::repeat('x').take(3000).collect();
+        client.send(format!("GET /domains/lvar/vars/{}", name).as_bytes()).unwrap();
+        reactor.run();
+        let response = String::from_utf8(client.recv().unwrap()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+    }
+
+    #[test]
+    fn should_fail_with_method_not_allowed() {
+        let (mut reactor, mut client, _) = http_client();
+        let response = request(&mut reactor, &mut client, "DELETE", "/domains/lvar/vars/foobar", "");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(response.contains("Allow: GET, PUT, POST\r\n"));
+    }
+
+    #[test]
+    fn should_stream_events() {
+        let (mut reactor, mut client, lvars) = http_client();
+        lvars.define("foobar", 42.0);
+        let response = request(&mut reactor, &mut client, "GET", "/events?var=lvar/foobar", "");
+        assert_eq!(
+            response,
+            "HTTP/1.1 200 OK\r\n\
+            Content-Type: text/event-stream\r\n\
+            Cache-Control: no-cache\r\n\r\n\
+            retry: 3000\n\n");
+        reactor.poll_domains();
+        lvars.define("foobar", 7.0);
+        reactor.poll_domains();
+        reactor.run();
+        assert_eq!(
+            String::from_utf8(client.recv().unwrap()).unwrap(),
+            "id: 1\ndata: {\"domain\":\"lvar\",\"value\":42,\"var\":\"foobar\"}\n\n\
+            id: 2\ndata: {\"domain\":\"lvar\",\"value\":7,\"var\":\"foobar\"}\n\n");
+    }
+
+    #[test]
+    fn should_resume_event_ids_after_last_event_id() {
+        let (mut reactor, mut client, lvars) = http_client();
+        lvars.define("foobar", 42.0);
+        client.send(b"GET /events?var=lvar%2Ffoobar HTTP/1.1\r\nLast-Event-ID: 41\r\n\r\n").unwrap();
+        reactor.run();
+        client.recv().unwrap();
+        reactor.poll_domains();
+        reactor.run();
+        let events = String::from_utf8(client.recv().unwrap()).unwrap();
+        assert!(events.starts_with("id: 42\n"));
+    }
+
+    #[test]
+    fn should_fail_to_stream_invalid_vars() {
+        let (mut reactor, mut client, _) = http_client();
+        let response = request(&mut reactor, &mut client, "GET", "/events", "");
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        let response = request(&mut reactor, &mut client, "GET", "/events?var=foo/bar", "");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn should_unsubscribe_when_stream_client_goes_away() {
+        let (mut reactor, mut client, lvars) = http_client();
+        lvars.define("foobar", 42.0);
+        request(&mut reactor, &mut client, "GET", "/events?var=lvar/foobar", "");
+        drop(client);
+        reactor.run();
+        let mut events = Vec::new();
+        reactor.domains.with_all_domains(|d| d.poll(&mut events)).unwrap();
+        assert!(events.is_empty());
+    }
+
+    fn http_client() -> (TestReactor, Loopback, lvar::MemoryBackend) {
+        let (domains, lvars) = lvar_domains();
+        let (dev, client) = loopback("http").unwrap();
+        let mut reactor = TestReactor::new(domains);
+        reactor.attach(|domains| Rest::new(dev, domains));
+        (reactor, client, lvars)
+    }
+
+    fn request(reactor: &mut TestReactor, client: &mut Loopback,
+        method: &str, path: &str, body: &str) -> String {
+        let request = format!("{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            method, path, body.len(), body);
+        client.send(request.as_bytes()).unwrap();
+        reactor.run();
+        String::from_utf8(client.recv().unwrap()).unwrap()
+    }
+}
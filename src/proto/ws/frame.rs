@@ -0,0 +1,244 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io;
+use std::io::Cursor;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+/// The largest frame header, with a 64-bits length and a masking key.
+const MAX_HEADER_SIZE: usize = 14;
+
+/// The largest frame payload accepted from clients.
+///
+/// Any frame up to this size fits in the 4096 bytes read buffer of the devices.
+pub const MAX_PAYLOAD_SIZE: usize = 4096 - MAX_HEADER_SIZE;
+
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_UNSUPPORTED_DATA: u16 = 1003;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(code: u8) -> io::Result<Opcode> {
+        match code {
+            0x0 => Ok(Opcode::Continuation),
+            0x1 => Ok(Opcode::Text),
+            0x2 => Ok(Opcode::Binary),
+            0x8 => Ok(Opcode::Close),
+            0x9 => Ok(Opcode::Ping),
+            0xa => Ok(Opcode::Pong),
+            _ => Err(invalid_frame(&format!("unknown opcode {:x}", code))),
+        }
+    }
+
+    fn as_u8(&self) -> u8 {
+        match *self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xa,
+        }
+    }
+
+    pub fn is_control(&self) -> bool {
+        self.as_u8() & 0x8 != 0
+    }
+}
+
+/// A WebSocket frame as defined in RFC 6455.
+///
+/// The payload is always kept unmasked. Frames sent by clients carry the masking key
+/// they were decoded with, while frames sent by the server have no mask.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub mask: Option<[u8; 4]>,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+
+    pub fn text(payload: &[u8]) -> Frame {
+        Frame { fin: true, opcode: Opcode::Text, mask: None, payload: payload.to_vec() }
+    }
+
+    pub fn pong(payload: &[u8]) -> Frame {
+        Frame { fin: true, opcode: Opcode::Pong, mask: None, payload: payload.to_vec() }
+    }
+
+    pub fn close(code: u16) -> Frame {
+        let mut payload = Vec::with_capacity(2);
+        payload.write_u16::<BigEndian>(code).unwrap();
+        Frame { fin: true, opcode: Opcode::Close, mask: None, payload: payload }
+    }
+
+    /// Decodes a frame from the given buffer.
+    ///
+    /// It returns the frame and the number of bytes it takes, or `None` if the buffer
+    /// does not contain a complete frame yet.
+    pub fn decode(buf: &[u8]) -> io::Result<Option<(Frame, usize)>> {
+        if buf.len() < 2 {
+            return Ok(None);
+        }
+        let fin = buf[0] & 0x80 != 0;
+        if buf[0] & 0x70 != 0 {
+            return Err(invalid_frame("reserved bits are set"));
+        }
+        let opcode = try!(Opcode::from_u8(buf[0] & 0x0f));
+        let masked = buf[1] & 0x80 != 0;
+        let mut cursor = Cursor::new(&buf[2..]);
+        let len = match buf[1] & 0x7f {
+            126 if buf.len() < 4 => return Ok(None),
+            126 => try!(cursor.read_u16::<BigEndian>()) as u64,
+            127 if buf.len() < 10 => return Ok(None),
+            127 => try!(cursor.read_u64::<BigEndian>()),
+            len => len as u64,
+        };
+        if len > MAX_PAYLOAD_SIZE as u64 {
+            return Err(invalid_frame("payload too large"));
+        }
+        if opcode.is_control() && (!fin || len > 125) {
+            return Err(invalid_frame("fragmented or too large control frame"));
+        }
+        let len = len as usize;
+        let header_len = 2 + cursor.position() as usize + if masked { 4 } else { 0 };
+        if buf.len() < header_len + len {
+            return Ok(None);
+        }
+        let mask = if masked {
+            let key = &buf[header_len - 4..header_len];
+            Some([key[0], key[1], key[2], key[3]])
+        } else {
+            None
+        };
+        let mut payload = buf[header_len..header_len + len].to_vec();
+        if let Some(ref key) = mask {
+            apply_mask(key, &mut payload);
+        }
+        let frame = Frame { fin: fin, opcode: opcode, mask: mask, payload: payload };
+        Ok(Some((frame, header_len + len)))
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) -> io::Result<()> {
+        let fin = if self.fin { 0x80 } else { 0x00 };
+        buf.push(fin | self.opcode.as_u8());
+        let masked = if self.mask.is_some() { 0x80 } else { 0x00 };
+        let len = self.payload.len();
+        if len < 126 {
+            buf.push(masked | len as u8);
+        } else if len <= u16::max_value() as usize {
+            buf.push(masked | 126);
+            try!(buf.write_u16::<BigEndian>(len as u16));
+        } else {
+            buf.push(masked | 127);
+            try!(buf.write_u64::<BigEndian>(len as u64));
+        }
+        match self.mask {
+            Some(ref key) => {
+                buf.extend_from_slice(key);
+                let mut payload = self.payload.clone();
+                apply_mask(key, &mut payload);
+                buf.extend_from_slice(&payload);
+            }
+            None => buf.extend_from_slice(&self.payload),
+        }
+        Ok(())
+    }
+}
+
+fn apply_mask(key: &[u8; 4], payload: &mut [u8]) {
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= key[i % 4];
+    }
+}
+
+fn invalid_frame(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid WebSocket frame: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn should_decode_masked_frame() {
+        // Example taken from RFC 6455, section 5.7
+        let buf = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let (frame, nbytes) = Frame::decode(&buf).unwrap().unwrap();
+        assert_eq!(nbytes, buf.len());
+        assert_eq!(frame, Frame {
+            fin: true,
+            opcode: Opcode::Text,
+            mask: Some([0x37, 0xfa, 0x21, 0x3d]),
+            payload: b"Hello".to_vec(),
+        });
+    }
+
+    #[test]
+    fn should_decode_extended_length() {
+        let mut buf = vec![0x82, 0x7e, 0x01, 0x00];
+        buf.extend_from_slice(&[0x42; 256]);
+        let (frame, nbytes) = Frame::decode(&buf).unwrap().unwrap();
+        assert_eq!(nbytes, buf.len());
+        assert_eq!(frame.opcode, Opcode::Binary);
+        assert_eq!(frame.payload.len(), 256);
+    }
+
+    #[test]
+    fn should_wait_for_complete_frame() {
+        assert_eq!(Frame::decode(&[0x81]).unwrap(), None);
+        assert_eq!(Frame::decode(&[0x81, 0x7e, 0x01]).unwrap(), None);
+        assert_eq!(Frame::decode(&[0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f]).unwrap(), None);
+    }
+
+    #[test]
+    fn should_fail_decode_invalid_frames() {
+        assert!(Frame::decode(&[0xc1, 0x00]).is_err());
+        assert!(Frame::decode(&[0x83, 0x00]).is_err());
+        assert!(Frame::decode(&[0x09, 0x00]).is_err());
+        assert!(Frame::decode(&[0x81, 0x7f, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+
+    #[test]
+    fn should_encode_frames() {
+        let mut buf = Vec::new();
+        Frame::text(b"Hello").encode(&mut buf).unwrap();
+        assert_eq!(&buf[..], &[0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f][..]);
+
+        buf.clear();
+        Frame::close(CLOSE_NORMAL).encode(&mut buf).unwrap();
+        assert_eq!(&buf[..], &[0x88, 0x02, 0x03, 0xe8][..]);
+    }
+
+    #[test]
+    fn should_encode_and_decode_masked_frames() {
+        let frame = Frame {
+            fin: false,
+            opcode: Opcode::Text,
+            mask: Some([1, 2, 3, 4]),
+            payload: vec![0x42; 300],
+        };
+        let mut buf = Vec::new();
+        frame.encode(&mut buf).unwrap();
+        assert_eq!(Frame::decode(&buf).unwrap(), Some((frame, buf.len())));
+    }
+}
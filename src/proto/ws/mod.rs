@@ -0,0 +1,432 @@
+//
+// FlightVars
+// Copyright (c) 2015, 2016 Alvaro Polo
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! OACSP over WebSocket.
+//!
+//! Once the opening handshake completes, clients send OACSP messages in text frames,
+//! one or more lines per message. Each variable update is sent back to the client
+//! as a text message containing a single OACSP line without its line terminator.
+
+use std::io;
+use std::str;
+
+use rustc_serialize::base64::{STANDARD, ToBase64};
+use sha1::Sha1;
+
+use domain::DomainDispatcher;
+use io::*;
+use proto::*;
+use proto::http::{Request, Response, invalid_request_status};
+use proto::oacsp::Session;
+use types::*;
+
+mod frame;
+
+pub use self::frame::*;
+
+/// The GUID used to compute the accept key of the opening handshake.
+const HANDSHAKE_GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The largest message accepted from clients, once its fragments are put together.
+const MAX_MESSAGE_SIZE: usize = 4096;
+
+pub struct WebSocket {
+    dev: Device,
+    session: Session,
+    open: bool,
+    message: Option<Vec<u8>>,
+    close_on_written: bool,
+}
+
+impl WebSocket {
+
+    pub fn new(dev: Device, domains: DomainDispatcher) -> WebSocket {
+        let session = Session::new(dev.id(), domains);
+        WebSocket {
+            dev: dev,
+            session: session,
+            open: false,
+            message: None,
+            close_on_written: false,
+        }
+    }
+
+    fn process_input(&mut self) -> io::Result<()> {
+        if !self.open {
+            let decoded = match Request::decode(self.dev.recv_bytes()) {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    error!("rejecting invalid WebSocket handshake from {}: {:?}",
+                        self.dev.name(), e);
+                    self.close_on_written = true;
+                    let (status, reason) = invalid_request_status(&e);
+                    return self.send_response(&Response::new(status, reason));
+                }
+            };
+            match decoded {
+                Some((request, nbytes)) => {
+                    self.dev.consume_recv_buffer(nbytes);
+                    try!(self.process_handshake(&request));
+                }
+                None => return Ok(()),
+            }
+        }
+        while self.open && !self.close_on_written {
+            let decoded = match Frame::decode(self.dev.recv_bytes()) {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    error!("closing WebSocket connection {}: {:?}", self.dev.name(), e);
+                    return self.close(CLOSE_PROTOCOL_ERROR);
+                }
+            };
+            match decoded {
+                Some((frame, nbytes)) => {
+                    self.dev.consume_recv_buffer(nbytes);
+                    try!(self.process_frame(frame));
+                }
+                None => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    fn process_handshake(&mut self, request: &Request) -> io::Result<()> {
+        let key = request.header("Sec-WebSocket-Key").map(|key| key.to_string());
+        let is_upgrade = request.method == "GET" &&
+            request.header_has_token("Upgrade", "websocket") &&
+            request.header_has_token("Connection", "upgrade") &&
+            request.header("Sec-WebSocket-Version") == Some("13");
+        match key {
+            Some(ref key) if is_upgrade => {
+                info!("WebSocket connection {} opened for {}", self.dev.name(), request.path);
+                self.open = true;
+                let response = Response::new(101, "Switching Protocols")
+                    .with_header("Upgrade", "websocket")
+                    .with_header("Connection", "Upgrade")
+                    .with_header("Sec-WebSocket-Accept", &accept_key(key));
+                self.send_response(&response)
+            }
+            _ => {
+                error!("rejecting invalid WebSocket handshake from {}", self.dev.name());
+                // The connection is closed once the client receives the response
+                self.close_on_written = true;
+                let response = Response::new(400, "Bad Request")
+                    .with_header("Sec-WebSocket-Version", "13")
+                    .with_body("text/plain", b"WebSocket upgrade expected".to_vec());
+                self.send_response(&response)
+            }
+        }
+    }
+
+    fn process_frame(&mut self, frame: Frame) -> io::Result<()> {
+        if frame.mask.is_none() {
+            error!("closing WebSocket connection {}: unmasked frame received", self.dev.name());
+            return self.close(CLOSE_PROTOCOL_ERROR);
+        }
+        match (frame.opcode, self.message.take()) {
+            (Opcode::Ping, message) => {
+                self.message = message;
+                self.send_frame(&Frame::pong(&frame.payload))
+            }
+            (Opcode::Pong, message) => {
+                self.message = message;
+                Ok(())
+            }
+            (Opcode::Close, _) => {
+                info!("WebSocket connection {} closed by client {}",
+                    self.dev.name(), self.session.client_id());
+                self.close(CLOSE_NORMAL)
+            }
+            (Opcode::Text, None) => self.process_fragment(Vec::new(), frame),
+            (Opcode::Continuation, Some(message)) => self.process_fragment(message, frame),
+            (Opcode::Binary, _) => {
+                error!("closing WebSocket connection {}: binary messages are not supported",
+                    self.dev.name());
+                self.close(CLOSE_UNSUPPORTED_DATA)
+            }
+            (opcode, _) => {
+                error!("closing WebSocket connection {}: unexpected {:?} frame",
+                    self.dev.name(), opcode);
+                self.close(CLOSE_PROTOCOL_ERROR)
+            }
+        }
+    }
+
+    fn process_fragment(&mut self, mut message: Vec<u8>, frame: Frame) -> io::Result<()> {
+        message.extend_from_slice(&frame.payload);
+        if message.len() > MAX_MESSAGE_SIZE {
+            error!("closing WebSocket connection {}: message too large", self.dev.name());
+            return self.close(CLOSE_PROTOCOL_ERROR);
+        }
+        if !frame.fin {
+            self.message = Some(message);
+            return Ok(());
+        }
+        let text = try!(str::from_utf8(&message).map_err(|_| io::Error::new(
+            io::ErrorKind::InvalidData,
+            "WebSocket text message is not valid UTF-8")));
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let reply = try!(self.session.process_line(line));
+            for reply_line in reply.split(|b| *b == b'\n').filter(|l| !l.is_empty()) {
+                try!(self.send_frame(&Frame::text(reply_line)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts the closing handshake, closing the device once the close frame is sent.
+    fn close(&mut self, code: u16) -> io::Result<()> {
+        self.close_on_written = true;
+        self.send_frame(&Frame::close(code))
+    }
+
+    fn send_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let mut buf = Vec::new();
+        try!(frame.encode(&mut buf));
+        self.dev.request_write(&buf)
+    }
+
+    fn send_response(&mut self, response: &Response) -> io::Result<()> {
+        let mut buf = Vec::new();
+        try!(response.encode(&mut buf));
+        self.dev.request_write(&buf)
+    }
+}
+
+impl DeviceHandler for WebSocket {
+    fn device(&mut self) -> &mut Device { &mut self.dev }
+
+    fn process_event(&mut self, event: Event) -> io::Result<()> {
+        match event {
+            Event::Ready => self.dev.request_read(),
+            Event::BytesRead(0) => {
+                info!("device {} was disconnected by WebSocket client {}",
+                    self.dev.name(), self.session.client_id());
+                self.dev.close()
+            }
+            Event::BytesRead(_) => {
+                try!(self.process_input());
+                if self.close_on_written { Ok(()) } else { self.dev.request_read() }
+            }
+            Event::BytesWritten(_) if self.close_on_written => self.dev.close(),
+            Event::BytesWritten(_) => Ok(()),
+            Event::Closed => self.session.end(),
+        }
+    }
+}
+
+impl Protocol for WebSocket {
+
+    fn send_update(&mut self, domain: &str, variable: Var, value: Value) -> io::Result<()> {
+        if !self.open || self.close_on_written {
+            return Ok(());
+        }
+        let mut line = match try!(self.session.encode_update(domain, variable, value)) {
+            Some(line) => line,
+            None => return Ok(()),
+        };
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+        self.send_frame(&Frame::text(&line))
+    }
+}
+
+/// Computes the `Sec-WebSocket-Accept` header for the given `Sec-WebSocket-Key`.
+fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(HANDSHAKE_GUID.as_bytes());
+    sha1.digest().bytes()[..].to_base64(STANDARD)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::iter;
+
+    use domain::*;
+    use io::*;
+    use proto::test_util::*;
+
+    use super::*;
+    use super::accept_key;
+
+    #[test]
+    fn should_compute_accept_key() {
+        // Example taken from RFC 6455, section 1.3
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn should_accept_handshake() {
+        let (mut reactor, mut client, _) = ws_client();
+        let response = handshake(&mut reactor, &mut client);
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+    }
+
+    #[test]
+    fn should_reject_invalid_handshake() {
+        let (mut reactor, mut client, _) = ws_client();
+        client.send(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        reactor.run();
+        let response = String::from_utf8(client.recv().unwrap()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn should_reject_too_large_handshake() {
+        let (mut reactor, mut client, _) = ws_client();
+        let path: String = iter::repeat('x').take(3000).collect();
+        client.send(format!("GET /{}", path).as_bytes()).unwrap();
+        reactor.run();
+        let response = String::from_utf8(client.recv().unwrap()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+    }
+
+    #[test]
+    fn should_write_variables_from_text_messages() {
+        let (mut reactor, mut client, lvars) = ws_client();
+        lvars.define("foobar", 0.0);
+        handshake(&mut reactor, &mut client);
+        send(&mut reactor, &mut client, &text("BEGIN 2 test\nWRITE_LVAR foobar 42\n"));
+        reactor.poll_domains();
+        assert_eq!(lvars.value("foobar"), Some(42.0));
+    }
+
+    #[test]
+    fn should_join_fragmented_messages() {
+        let (mut reactor, mut client, lvars) = ws_client();
+        lvars.define("foobar", 0.0);
+        handshake(&mut reactor, &mut client);
+        let mut first = text("BEGIN 2 test\nWRITE_LV");
+        first.fin = false;
+        let mut last = text("AR foobar 42");
+        last.opcode = Opcode::Continuation;
+        send(&mut reactor, &mut client, &first);
+        send(&mut reactor, &mut client, &last);
+        reactor.poll_domains();
+        assert_eq!(lvars.value("foobar"), Some(42.0));
+    }
+
+    #[test]
+    fn should_send_events_as_text_messages() {
+        let (mut reactor, mut client, lvars) = ws_client();
+        lvars.define("foobar", 42.0);
+        handshake(&mut reactor, &mut client);
+        send(&mut reactor, &mut client, &text("BEGIN 2 test\nOBS_LVAR foobar"));
+        reactor.poll_domains();
+        assert_eq!(recv(&mut reactor, &mut client), vec![Frame::text(b"EVENT_LVAR foobar 42")]);
+    }
+
+    #[test]
+    fn should_answer_ping_and_close() {
+        let (mut reactor, mut client, _) = ws_client();
+        handshake(&mut reactor, &mut client);
+        let mut ping = text("hi");
+        ping.opcode = Opcode::Ping;
+        let mut close = text("");
+        close.opcode = Opcode::Close;
+        send(&mut reactor, &mut client, &ping);
+        send(&mut reactor, &mut client, &close);
+        assert_eq!(
+            recv(&mut reactor, &mut client),
+            vec![Frame::pong(b"hi"), Frame::close(CLOSE_NORMAL)]);
+    }
+
+    #[test]
+    fn should_close_on_unmasked_frames() {
+        let (mut reactor, mut client, _) = ws_client();
+        handshake(&mut reactor, &mut client);
+        let mut unmasked = text("BEGIN 2 test");
+        unmasked.mask = None;
+        send(&mut reactor, &mut client, &unmasked);
+        assert_eq!(
+            recv(&mut reactor, &mut client),
+            vec![Frame::close(CLOSE_PROTOCOL_ERROR)]);
+    }
+
+    #[test]
+    fn should_close_on_too_large_frames() {
+        let (mut reactor, mut client, _) = ws_client();
+        handshake(&mut reactor, &mut client);
+        let payload: String = iter::repeat('x').take(5000).collect();
+        let mut buf = Vec::new();
+        text(&payload).encode(&mut buf).unwrap();
+        // The frame is rejected once its header is received
+        client.send(&buf[..3000]).unwrap();
+        assert_eq!(
+            recv(&mut reactor, &mut client),
+            vec![Frame::close(CLOSE_PROTOCOL_ERROR)]);
+    }
+
+    #[test]
+    fn should_close_on_too_large_messages() {
+        let (mut reactor, mut client, _) = ws_client();
+        handshake(&mut reactor, &mut client);
+        let payload: String = iter::repeat('x').take(2500).collect();
+        let mut first = text(&payload);
+        first.fin = false;
+        let mut last = text(&payload);
+        last.opcode = Opcode::Continuation;
+        send(&mut reactor, &mut client, &first);
+        send(&mut reactor, &mut client, &last);
+        assert_eq!(
+            recv(&mut reactor, &mut client),
+            vec![Frame::close(CLOSE_PROTOCOL_ERROR)]);
+    }
+
+    fn ws_client() -> (TestReactor, Loopback, lvar::MemoryBackend) {
+        let (domains, lvars) = lvar_domains();
+        let (dev, client) = loopback("ws").unwrap();
+        let mut reactor = TestReactor::new(domains);
+        reactor.attach(|domains| WebSocket::new(dev, domains));
+        (reactor, client, lvars)
+    }
+
+    fn handshake(reactor: &mut TestReactor, client: &mut Loopback) -> String {
+        client.send(
+            b"GET /flightvars HTTP/1.1\r\n\
+            Host: localhost\r\n\
+            Upgrade: websocket\r\n\
+            Connection: Upgrade\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            Sec-WebSocket-Version: 13\r\n\r\n").unwrap();
+        reactor.run();
+        String::from_utf8(client.recv().unwrap()).unwrap()
+    }
+
+    fn text(payload: &str) -> Frame {
+        Frame {
+            fin: true,
+            opcode: Opcode::Text,
+            mask: Some([0x12, 0x34, 0x56, 0x78]),
+            payload: payload.as_bytes().to_vec(),
+        }
+    }
+
+    fn send(reactor: &mut TestReactor, client: &mut Loopback, frame: &Frame) {
+        let mut buf = Vec::new();
+        frame.encode(&mut buf).unwrap();
+        client.send(&buf).unwrap();
+        reactor.run();
+    }
+
+    fn recv(reactor: &mut TestReactor, client: &mut Loopback) -> Vec<Frame> {
+        reactor.run();
+        let data = client.recv().unwrap();
+        let mut frames = Vec::new();
+        let mut offset = 0;
+        while let Some((frame, nbytes)) = Frame::decode(&data[offset..]).unwrap() {
+            frames.push(frame);
+            offset += nbytes;
+        }
+        frames
+    }
+}
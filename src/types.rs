@@ -9,58 +9,186 @@
 use std::fmt;
 use std::io;
 use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+use rustc_serialize::json::{Json, ToJson};
 
 /// Values of this type are used to identify devices. 
 pub type DeviceId = u32;
 
+static NEXT_LOGICAL_DEVICE_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Allocates a device ID for a logical device.
+///
+/// Logical devices are sessions that are not backed by an IO device, like the peers
+/// of a datagram socket. Their IDs are taken from the upper half of the ID space, 
+/// which is never used by file descriptors nor Windows handles. 
+pub fn logical_device_id() -> DeviceId {
+    let seq = NEXT_LOGICAL_DEVICE_ID.fetch_add(1, Ordering::SeqCst) as DeviceId;
+    0x80000000 | (seq & 0x7fffffff)
+}
+
+/// The type of the data an offset points to.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum OffsetType {
+    I8, U8, I16, U16, I32, U32, I64, U64,
+    /// A single-precision floating-point number.
+    F32,
+    /// A double-precision floating-point number.
+    F64,
+    /// A 64-bit fixed-point number, whose upper 32 bits are the signed integer part.
+    Fix64,
+    /// A NUL-terminated string that occupies up to the size of the offset.
+    Str,
+    /// A block of bytes.
+    Bytes,
+}
+
+impl OffsetType {
+    /// The type of the offsets of the given size whose type is not specified.
+    ///
+    /// 1-byte offsets are unsigned, while the rest are signed.
+    pub fn default_for(size: u16) -> Option<OffsetType> {
+        match size {
+            1 => Some(OffsetType::U8),
+            2 => Some(OffsetType::I16),
+            4 => Some(OffsetType::I32),
+            8 => Some(OffsetType::I64),
+            _ => None,
+        }
+    }
+
+    /// The size of the offsets of this type, or `None` for types of any size.
+    pub fn size(&self) -> Option<u16> {
+        match *self {
+            OffsetType::I8 | OffsetType::U8 => Some(1),
+            OffsetType::I16 | OffsetType::U16 => Some(2),
+            OffsetType::I32 | OffsetType::U32 | OffsetType::F32 => Some(4),
+            OffsetType::I64 | OffsetType::U64 | OffsetType::F64 | OffsetType::Fix64 => Some(8),
+            OffsetType::Str | OffsetType::Bytes => None,
+        }
+    }
+}
+
+impl fmt::Display for OffsetType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let name = match *self {
+            OffsetType::I8 => "i8",
+            OffsetType::U8 => "u8",
+            OffsetType::I16 => "i16",
+            OffsetType::U16 => "u16",
+            OffsetType::I32 => "i32",
+            OffsetType::U32 => "u32",
+            OffsetType::I64 => "i64",
+            OffsetType::U64 => "u64",
+            OffsetType::F32 => "f32",
+            OffsetType::F64 => "f64",
+            OffsetType::Fix64 => "fix64",
+            OffsetType::Str => "str",
+            OffsetType::Bytes => "bytes",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl str::FromStr for OffsetType {
+    type Err = io::Error;
+    fn from_str(s: &str) -> io::Result<OffsetType> {
+        match &s.to_lowercase()[..] {
+            "i8" => Ok(OffsetType::I8),
+            "u8" => Ok(OffsetType::U8),
+            "i16" => Ok(OffsetType::I16),
+            "u16" => Ok(OffsetType::U16),
+            "i32" => Ok(OffsetType::I32),
+            "u32" => Ok(OffsetType::U32),
+            "i64" => Ok(OffsetType::I64),
+            "u64" => Ok(OffsetType::U64),
+            "f32" => Ok(OffsetType::F32),
+            "f64" => Ok(OffsetType::F64),
+            "fix64" => Ok(OffsetType::Fix64),
+            "str" => Ok(OffsetType::Str),
+            "bytes" => Ok(OffsetType::Bytes),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid offset type '{}'", s))),
+        }
+    }
+}
+
 /// An offset into a data vector.
 ///
 /// Some domains uses 16-bits offsets to reference an specific item in a data vector.
 /// That's the case of FSUIPC or IOCP. The `Offset` type serves to this purpose by
-/// specifying a 16-bits offset and the number of bytes the data occupies from there. 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub struct Offset(pub u16, pub u8);
+/// specifying a 16-bits offset, the number of bytes the data occupies from there and
+/// the type of such data. 
+///
+/// Offsets are written as `<address>+<size>[:<type>]` (e.g., `0574+4:u32`). The type
+/// may be omitted for the default type of the size. 
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Offset(pub u16, pub u16, pub OffsetType);
 
 #[allow(dead_code)]
 impl Offset {
-    pub fn from(addr: u16, size: u8) -> Option<Offset> {
-        match size {
-            1 | 2 | 4 | 8 => Some(Offset(addr, size)),
-            _ => None,
+    /// Obtains the offset of the given size with the default type for such size.
+    pub fn from(addr: u16, size: u16) -> Option<Offset> {
+        OffsetType::default_for(size).and_then(|t| Offset::typed(addr, size, t))
+    }
+
+    /// Obtains the offset of the given size and type, if the type admits such size.
+    ///
+    /// Offsets cannot span beyond the end of the 64KB offset space.
+    pub fn typed(addr: u16, size: u16, offset_type: OffsetType) -> Option<Offset> {
+        let size_is_valid = match offset_type.size() {
+            Some(type_size) => type_size == size,
+            None => size > 0,
+        };
+        if size_is_valid && addr as usize + size as usize <= 0x10000 {
+            Some(Offset(addr, size, offset_type))
+        } else {
+            None
         }
     }
+
+    /// Whether the size of this offset is admitted by its type.
+    pub fn is_valid(&self) -> bool {
+        Offset::typed(self.0, self.1, self.2).is_some()
+    }
 }
 
 impl fmt::Display for Offset {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(f, "{:x}+{}", self.0, self.1)
+        try!(write!(f, "{:x}+{}", self.0, self.1));
+        if OffsetType::default_for(self.1) != Some(self.2) {
+            try!(write!(f, ":{}", self.2));
+        }
+        Ok(())
     }
 }
 
 impl str::FromStr for Offset {
     type Err = io::Error;
     fn from_str(s: &str) -> io::Result<Offset> {
-        let pair: Vec<&str> = s.split("+").collect();
-        if pair.len() == 2 {
-            let addr = try!(u16::from_str_radix(pair[0], 16)
-            	.map_err(|_| io::Error::new(
-                    io::ErrorKind::InvalidInput, 
-                    format!("invalid FSUIPC offset in '{}'", s))));
-            let size = try!(u8::from_str(pair[1])
-                .map_err(|_| io::Error::new(	
-                    io::ErrorKind::InvalidInput, 
-                    format!("invalid FSUIPC offset in '{}'", s))));
-            Ok(Offset(addr, size))
-        } else {
-            Err(io::Error::new(
-                    io::ErrorKind::InvalidInput, 
-                    format!("invalid FSUIPC offset in '{}'", s)))
+        let invalid_offset = || io::Error::new(
+            io::ErrorKind::InvalidInput, 
+            format!("invalid FSUIPC offset in '{}'", s));
+        let mut parts = s.splitn(2, ':');
+        let location = parts.next().unwrap_or("");
+        let pair: Vec<&str> = location.split("+").collect();
+        if pair.len() != 2 {
+            return Err(invalid_offset());
         }
+        let addr = try!(u16::from_str_radix(pair[0], 16).map_err(|_| invalid_offset()));
+        let size = try!(u16::from_str(pair[1]).map_err(|_| invalid_offset()));
+        let offset = match parts.next() {
+            Some(type_name) => Offset::typed(addr, size, try!(type_name.parse())),
+            None => Offset::from(addr, size),
+        };
+        offset.ok_or_else(invalid_offset)
     }
 }
 
 /// A domain-agnostic variable
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Var {
     /// A variable referenced by its name. 
     Named(String),
@@ -72,44 +200,252 @@ pub enum Var {
 impl Var {
     pub fn named(n: &str) -> Var { Var::Named(n.to_string()) }
     
-    pub fn offset(addr: u16, width: u8) -> Option<Var> {
+    pub fn offset(addr: u16, width: u16) -> Option<Var> {
         Offset::from(addr, width).map(Var::Offset)
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// A value of a domain variable.
+///
+/// Integers keep the width and the signedness they were read with, like the
+/// FSUIPC offsets they come from. Integers parsed from text are `I64` unless they
+/// only fit in an `U64`.
+#[derive(Clone, Debug, PartialEq)]
 pub enum Value {
     Bool(bool),
-    Number(isize),
+    I8(i8),
+    U8(u8),
+    I16(i16),
+    U16(u16),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    U64(u64),
+    Float(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+/// A type that values can be converted into.
+///
+/// The conversion fails instead of truncating or wrapping if the value cannot be
+/// represented by the type.
+pub trait FromValue: Sized {
+    fn from_value(v: &Value) -> io::Result<Self>;
 }
 
 macro_rules! define_from_value {
-    ($t:ty) => (
-        impl<'a> From<&'a Value> for $t {
-            fn from(v: &Value) -> $t {
-                match v {
-                    &Value::Bool(true) => 1 as $t,
-                    &Value::Bool(false) => 0 as $t,
-                    &Value::Number(i) => i as $t,
-                }
+    ($t:ident) => (
+        impl FromValue for $t {
+            fn from_value(v: &Value) -> io::Result<$t> {
+                let result = match *v {
+                    Value::Bool(b) => Some(b as $t),
+                    Value::I8(i) => checked_int!(i as i64, $t),
+                    Value::U8(i) => checked_int!(i as i64, $t),
+                    Value::I16(i) => checked_int!(i as i64, $t),
+                    Value::U16(i) => checked_int!(i as i64, $t),
+                    Value::I32(i) => checked_int!(i as i64, $t),
+                    Value::U32(i) => checked_int!(i as i64, $t),
+                    Value::I64(i) => checked_int!(i, $t),
+                    Value::U64(i) if i <= ::std::$t::MAX as u64 => Some(i as $t),
+                    Value::U64(_) => None,
+                    Value::Float(f) => float_to_i64(f).and_then(|i| checked_int!(i, $t)),
+                    _ => None,
+                };
+                result.ok_or_else(|| conversion_error(v, stringify!($t)))
             }
         }
     );
 }
 
+macro_rules! checked_int {
+    ($i:expr, $t:ident) => ({
+        let i: i64 = $i;
+        if i < (::std::$t::MIN as i64) { None }
+        else if i > 0 && (i as u64) > (::std::$t::MAX as u64) { None }
+        else { Some(i as $t) }
+    });
+}
+
 define_from_value!(u8);
 define_from_value!(i8);
 define_from_value!(u16);
 define_from_value!(i16);
 define_from_value!(u32);
 define_from_value!(i32);
-define_from_value!(f64);
+define_from_value!(u64);
+define_from_value!(i64);
 
+impl FromValue for f64 {
+    fn from_value(v: &Value) -> io::Result<f64> {
+        let result = match *v {
+            Value::Bool(b) => Some(b as u8 as f64),
+            Value::I8(i) => Some(i as f64),
+            Value::U8(i) => Some(i as f64),
+            Value::I16(i) => Some(i as f64),
+            Value::U16(i) => Some(i as f64),
+            Value::I32(i) => Some(i as f64),
+            Value::U32(i) => Some(i as f64),
+            Value::I64(i) if (i as f64) as i64 == i => Some(i as f64),
+            Value::U64(i) if (i as f64) as u64 == i => Some(i as f64),
+            Value::Float(f) => Some(f),
+            _ => None,
+        };
+        result.ok_or_else(|| conversion_error(v, "f64"))
+    }
+}
+
+/// Converts an integral floating-point number into an `i64`, if it fits.
+fn float_to_i64(f: f64) -> Option<i64> {
+    let limit = (1u64 << 63) as f64;
+    if f.fract() == 0.0 && f >= -limit && f < limit { Some(f as i64) } else { None }
+}
+
+fn conversion_error(v: &Value, target: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("value {} cannot be converted into {}", v, target))
+}
+
+/// Values are displayed so they can be parsed back into an equivalent value.
+///
+/// Floating-point numbers always have a decimal point or an exponent, strings are
+/// quoted, escaping any quote or backslash with a backslash, and byte blocks are
+/// written as `0x` followed by the hexadecimal digits of their bytes.
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match *self {
             Value::Bool(v) => write!(f, "{}", v),
-            Value::Number(v) => write!(f, "{}", v),
+            Value::I8(v) => write!(f, "{}", v),
+            Value::U8(v) => write!(f, "{}", v),
+            Value::I16(v) => write!(f, "{}", v),
+            Value::U16(v) => write!(f, "{}", v),
+            Value::I32(v) => write!(f, "{}", v),
+            Value::U32(v) => write!(f, "{}", v),
+            Value::I64(v) => write!(f, "{}", v),
+            Value::U64(v) => write!(f, "{}", v),
+            Value::Float(v) => {
+                let repr = format!("{}", v);
+                if v.is_finite() && !repr.contains('.') && !repr.contains('e') {
+                    write!(f, "{}.0", repr)
+                } else {
+                    write!(f, "{}", repr)
+                }
+            }
+            Value::Str(ref v) => {
+                try!(write!(f, "\""));
+                for c in v.chars() {
+                    if c == '"' || c == '\\' {
+                        try!(write!(f, "\\"));
+                    }
+                    try!(write!(f, "{}", c));
+                }
+                write!(f, "\"")
+            }
+            Value::Bytes(ref v) => {
+                try!(write!(f, "0x"));
+                for b in v {
+                    try!(write!(f, "{:02x}", b));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl str::FromStr for Value {
+    type Err = io::Error;
+    fn from_str(s: &str) -> io::Result<Value> {
+        let error = || io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid value '{}'", s));
+        if s == "true" {
+            Ok(Value::Bool(true))
+        } else if s == "false" {
+            Ok(Value::Bool(false))
+        } else if s.starts_with('"') {
+            if s.len() < 2 || !s.ends_with('"') {
+                return Err(error());
+            }
+            let mut value = String::new();
+            let mut escaped = false;
+            for c in s[1..s.len() - 1].chars() {
+                if !escaped && c == '\\' {
+                    escaped = true;
+                } else if !escaped && c == '"' {
+                    return Err(error());
+                } else {
+                    value.push(c);
+                    escaped = false;
+                }
+            }
+            if escaped { Err(error()) } else { Ok(Value::Str(value)) }
+        } else if s.starts_with("0x") {
+            let digits = &s[2..];
+            if digits.len() % 2 != 0 || !digits.chars().all(|c| c.is_digit(16)) {
+                return Err(error());
+            }
+            let mut bytes = Vec::with_capacity(digits.len() / 2);
+            for i in 0..digits.len() / 2 {
+                let pair = &digits[2 * i..2 * i + 2];
+                bytes.push(try!(u8::from_str_radix(pair, 16).map_err(|_| error())));
+            }
+            Ok(Value::Bytes(bytes))
+        } else if let Ok(n) = s.parse() {
+            Ok(Value::I64(n))
+        } else if let Ok(n) = s.parse() {
+            Ok(Value::U64(n))
+        } else {
+            s.parse().map(Value::Float).map_err(|_| error())
+        }
+    }
+}
+
+impl Value {
+    /// Obtains the value of a floating-point number, which is integral if possible.
+    pub fn from_f64(v: f64) -> Value {
+        if v.fract() == 0.0 && v.abs() < (1u64 << 53) as f64 {
+            Value::I64(v as i64)
+        } else {
+            Value::Float(v)
+        }
+    }
+
+    /// Obtains the value represented by a JSON value, if any.
+    ///
+    /// Byte blocks are represented by arrays of bytes.
+    pub fn from_json(json: &Json) -> Option<Value> {
+        match *json {
+            Json::Boolean(b) => Some(Value::Bool(b)),
+            Json::I64(n) => Some(Value::I64(n)),
+            Json::U64(n) if n <= i64::max_value() as u64 => Some(Value::I64(n as i64)),
+            Json::U64(n) => Some(Value::U64(n)),
+            Json::F64(n) => Some(Value::from_f64(n)),
+            Json::String(ref s) => Some(Value::Str(s.clone())),
+            Json::Array(ref items) => items.iter()
+                .map(|item| item.as_u64().and_then(|b| if b <= 0xff { Some(b as u8) } else { None }))
+                .collect::<Option<Vec<u8>>>()
+                .map(Value::Bytes),
+            _ => None,
+        }
+    }
+}
+
+impl ToJson for Value {
+    fn to_json(&self) -> Json {
+        match *self {
+            Value::Bool(b) => Json::Boolean(b),
+            Value::I8(n) => Json::I64(n as i64),
+            Value::U8(n) => Json::U64(n as u64),
+            Value::I16(n) => Json::I64(n as i64),
+            Value::U16(n) => Json::U64(n as u64),
+            Value::I32(n) => Json::I64(n as i64),
+            Value::U32(n) => Json::U64(n as u64),
+            Value::I64(n) => Json::I64(n),
+            Value::U64(n) => Json::U64(n),
+            Value::Float(n) => Json::F64(n),
+            Value::Str(ref s) => Json::String(s.clone()),
+            Value::Bytes(ref bytes) => Json::Array(bytes.iter().map(|b| Json::U64(*b as u64)).collect()),
         }
     }
 }
@@ -119,18 +455,34 @@ mod tests {
     use std::io;
     use std::str::FromStr;
 
+    use rustc_serialize::json::{Json, ToJson};
+
     use super::*;
 
+    #[test]
+    fn should_allocate_distinct_logical_device_ids() {
+        let id1 = logical_device_id();
+        let id2 = logical_device_id();
+        assert!(id1 != id2);
+        assert!(id1 & 0x80000000 != 0);
+        assert!(id2 & 0x80000000 != 0);
+    }
+
     #[test]
     fn should_display_offset_addr() {
-        assert_eq!(format!("{}", Offset(0x1234, 1)), "1234+1");
-        assert_eq!(format!("{}", Offset(0xabcd, 2)), "abcd+2");
+        assert_eq!(format!("{}", Offset::from(0x1234, 1).unwrap()), "1234+1");
+        assert_eq!(format!("{}", Offset::from(0xabcd, 2).unwrap()), "abcd+2");
+        assert_eq!(format!("{}", Offset(0x0574, 4, OffsetType::U32)), "574+4:u32");
     }
 
     #[test]
     fn should_get_offset_addr_from_str() {
-        assert_eq!(Offset::from_str("1234+1").unwrap(), Offset(0x1234, 1));
-        assert_eq!(Offset::from_str("abcd+2").unwrap(), Offset(0xabcd, 2));
+        assert_eq!(Offset::from_str("1234+1").unwrap(), Offset(0x1234, 1, OffsetType::U8));
+        assert_eq!(Offset::from_str("abcd+2").unwrap(), Offset(0xabcd, 2, OffsetType::I16));
+        assert_eq!(Offset::from_str("abcd+2:i16").unwrap(), Offset(0xabcd, 2, OffsetType::I16));
+        assert_eq!(Offset::from_str("0574+4:u32").unwrap(), Offset(0x0574, 4, OffsetType::U32));
+        assert_eq!(Offset::from_str("2B00+8:F64").unwrap(), Offset(0x2b00, 8, OffsetType::F64));
+        assert_eq!(Offset::from_str("3D00+256:str").unwrap(), Offset(0x3d00, 256, OffsetType::Str));
     }
 
     #[test]
@@ -138,5 +490,102 @@ mod tests {
         assert_eq!(Offset::from_str("").unwrap_err().kind(), io::ErrorKind::InvalidInput);
         assert_eq!(Offset::from_str("foobar").unwrap_err().kind(), io::ErrorKind::InvalidInput);
         assert_eq!(Offset::from_str("1234").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(Offset::from_str("1234+3").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(Offset::from_str("1234+2:u32").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(Offset::from_str("1234+0:str").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(Offset::from_str("1234+2:foo").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(Offset::from_str("ffff+2").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(Offset::from_str("ff00+512:str").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn should_convert_values_from_and_to_json() {
+        assert_eq!(Value::from_json(&Json::Boolean(true)), Some(Value::Bool(true)));
+        assert_eq!(Value::from_json(&Json::I64(-1)), Some(Value::I64(-1)));
+        assert_eq!(Value::from_json(&Json::F64(2.0)), Some(Value::I64(2)));
+        assert_eq!(Value::from_json(&Json::F64(2.5)), Some(Value::Float(2.5)));
+        assert_eq!(Value::from_json(&Json::String("1".to_string())), Some(Value::Str("1".to_string())));
+        assert_eq!(Value::from_json(&Json::Null), None);
+        assert_eq!(Value::I64(42).to_json(), Json::I64(42));
+        assert_eq!(Value::Bool(false).to_json(), Json::Boolean(false));
+    }
+
+    #[test]
+    fn should_display_values() {
+        assert_eq!(format!("{}", Value::I64(-42)), "-42");
+        assert_eq!(format!("{}", Value::Bool(true)), "true");
+        assert_eq!(format!("{}", Value::Float(245.5)), "245.5");
+        assert_eq!(format!("{}", Value::Float(3.0)), "3.0");
+        assert_eq!(format!("{}", Value::Str("say \"hi\"".to_string())), r#""say \"hi\"""#);
+    }
+
+    #[test]
+    fn should_parse_values_from_str() {
+        assert_eq!(Value::from_str("-42").unwrap(), Value::I64(-42));
+        assert_eq!(Value::from_str("false").unwrap(), Value::Bool(false));
+        assert_eq!(Value::from_str("245.5").unwrap(), Value::Float(245.5));
+        assert_eq!(Value::from_str("1e3").unwrap(), Value::Float(1000.0));
+        assert_eq!(Value::from_str(r#""a \\ b""#).unwrap(), Value::Str("a \\ b".to_string()));
+        assert!(Value::from_str("foobar").is_err());
+        assert!(Value::from_str(r#""foo"#).is_err());
+        assert!(Value::from_str(r#""foo\""#).is_err());
+    }
+
+    #[test]
+    fn should_round_trip_values_through_str() {
+        let values = vec![
+            Value::I64(7), Value::Bool(false), Value::Float(245.5), Value::Float(3.0),
+            Value::Float(-0.125), Value::Str("A \"quoted\" \\ string".to_string()),
+        ];
+        for value in values {
+            assert_eq!(Value::from_str(&format!("{}", value)).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn should_obtain_values_from_f64() {
+        assert_eq!(Value::from_f64(42.0), Value::I64(42));
+        assert_eq!(Value::from_f64(245.5), Value::Float(245.5));
+    }
+
+    #[test]
+    fn should_convert_values_checked() {
+        assert_eq!(u8::from_value(&Value::I64(255)).unwrap(), 255);
+        assert!(u8::from_value(&Value::I64(256)).is_err());
+        assert!(u32::from_value(&Value::I64(-1)).is_err());
+        assert!(i8::from_value(&Value::U8(200)).is_err());
+        assert_eq!(i16::from_value(&Value::Bool(true)).unwrap(), 1);
+        assert_eq!(u64::from_value(&Value::U64(u64::max_value())).unwrap(), u64::max_value());
+        assert!(i64::from_value(&Value::U64(u64::max_value())).is_err());
+        assert_eq!(i32::from_value(&Value::Float(-2.0)).unwrap(), -2);
+        assert!(i32::from_value(&Value::Float(2.5)).is_err());
+        assert!(i32::from_value(&Value::Float(1e20)).is_err());
+        assert!(i32::from_value(&Value::Str("1".to_string())).is_err());
+        assert_eq!(f64::from_value(&Value::I16(-7)).unwrap(), -7.0);
+        assert!(f64::from_value(&Value::I64((1 << 53) + 1)).is_err());
+        assert!(f64::from_value(&Value::Bytes(vec![1])).is_err());
+    }
+
+    #[test]
+    fn should_display_and_parse_byte_blocks() {
+        assert_eq!(format!("{}", Value::Bytes(vec![0x0a, 0xff])), "0x0aff");
+        assert_eq!(Value::from_str("0x0aFF").unwrap(), Value::Bytes(vec![0x0a, 0xff]));
+        assert_eq!(Value::from_str("0x").unwrap(), Value::Bytes(vec![]));
+        assert!(Value::from_str("0x0a0").is_err());
+        assert!(Value::from_str("0xzz").is_err());
+    }
+
+    #[test]
+    fn should_parse_unsigned_values_beyond_i64() {
+        assert_eq!(Value::from_str("18446744073709551615").unwrap(), Value::U64(u64::max_value()));
+    }
+
+    #[test]
+    fn should_convert_byte_blocks_from_and_to_json() {
+        let json = Json::Array(vec![Json::U64(1), Json::U64(255)]);
+        assert_eq!(Value::from_json(&json), Some(Value::Bytes(vec![1, 255])));
+        assert_eq!(Value::Bytes(vec![1, 255]).to_json(), json);
+        assert_eq!(Value::from_json(&Json::Array(vec![Json::U64(256)])), None);
+        assert_eq!(Value::U16(7).to_json(), Json::U64(7));
     }
 }